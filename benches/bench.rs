@@ -0,0 +1,75 @@
+#![feature(test)]
+
+extern crate docopt;
+extern crate test;
+
+use docopt::Docopt;
+use test::Bencher;
+
+fn parse(usage: &str, argv: &[String]) {
+    let dopt = Docopt::new(usage).unwrap()
+                                 .argv(argv.iter().map(|s| &**s));
+    let vals = dopt.parse().unwrap();
+    test::black_box(vals);
+}
+
+#[bench]
+fn positionals_50k(b: &mut Bencher) {
+    let usage = "Usage: prog <file>...";
+    let mut argv = vec!["prog".to_string()];
+    argv.extend((0..50000).map(|i| format!("file{}.txt", i)));
+    b.iter(|| parse(usage, &argv));
+}
+
+#[bench]
+fn repeated_options_50k(b: &mut Bencher) {
+    let usage = "
+Usage: prog [--include=<dir>]...
+
+Options:
+    --include=<dir>  A directory to include.
+";
+    let mut argv = vec!["prog".to_string()];
+    argv.extend((0..50000).map(|i| format!("--include=dir{}", i)));
+    b.iter(|| parse(usage, &argv));
+}
+
+#[bench]
+fn positionals_and_options_50k(b: &mut Bencher) {
+    let usage = "
+Usage: prog [--include=<dir>]... <file>...
+
+Options:
+    -I, --include=<dir>  A directory to include.
+";
+    let mut argv = vec!["prog".to_string()];
+    for i in 0..25000 {
+        argv.push(format!("file{}.txt", i));
+        argv.push(format!("-Idir{}", i));
+    }
+    b.iter(|| parse(usage, &argv));
+}
+
+// A usage that is a fixed sequence of positional arguments and commands is
+// matched without the general matcher. The `[--]` in the second benchmark
+// makes the same usage go through the general matcher, for comparison.
+
+fn match_only(usage: &str, argv: &[&str], b: &mut Bencher) {
+    let dopt = Docopt::new(usage).unwrap();
+    b.iter(|| {
+        let vals = dopt.argv(argv.iter()).parse().unwrap();
+        test::black_box(vals);
+    });
+}
+
+#[bench]
+fn fixed_positionals(b: &mut Bencher) {
+    match_only("Usage: prog copy <input> <output>",
+               &["prog", "copy", "in.txt", "out.txt"], b);
+}
+
+#[bench]
+fn fixed_positionals_general(b: &mut Bencher) {
+    match_only("Usage: prog copy <input> <output> [--]",
+               &["prog", "copy", "in.txt", "out.txt"], b);
+}
@@ -10,6 +10,7 @@ function _docopt_wordlist {
 
   cword=$(_get_cword)
   cmd="${COMP_WORDS[0]}"
+  gen_values "$cmd" "$cword" && return
   wordlist=$("$cmd" --help 2>&1 | "$DOCOPT_WORDLIST_BIN")
   gen "$cword" "$wordlist"
 }
@@ -42,6 +43,7 @@ function _docopt_wordlist_commands {
   fi
 
   cword=$(_get_cword)
+  gen_values "${COMP_WORDS[0]}" "$cword" && return
   if [ "$COMP_CWORD" = 1 ]; then
     cmd="${COMP_WORDS[0]}"
     wordlist=$("$cmd" --help 2>&1 | "$DOCOPT_WORDLIST_BIN")
@@ -59,6 +61,35 @@ function _docopt_wordlist_commands {
   fi
 }
 
+# A helper function for completing the value of the flag before the cursor.
+#
+# Programs that list the values of a flag at runtime (with
+# `Docopt::complete_values`) print them for `$cmd --complete FLAG PREFIX`.
+# Since other programs may treat `--complete` as something else entirely,
+# this is only done for programs listed (by name, separated by spaces) in
+# `$DOCOPT_WORDLIST_COMPLETE`, e.g.:
+#
+#   DOCOPT_WORDLIST_COMPLETE="deploy mytool"
+#
+# If the program isn't listed or there are no such values, then this fails
+# and nothing is completed.
+function gen_values {
+  cmd="$1"
+  cword="$2"
+  prev="${COMP_WORDS[COMP_CWORD-1]}"
+  if [[ "$prev" != -* ]]; then
+    return 1
+  fi
+  if [[ " $DOCOPT_WORDLIST_COMPLETE " != *" ${cmd##*/} "* ]]; then
+    return 1
+  fi
+  values=$("$cmd" --complete "$prev" "$cword" 2>/dev/null)
+  if [ -z "$values" ]; then
+    return 1
+  fi
+  COMPREPLY=($(compgen -W "$values" -- "$cword"))
+}
+
 # A helper function for running `compgen`, which is responsible for taking
 # a prefix and presenting possible completions.
 #
@@ -16,7 +16,10 @@ use std::collections::HashMap;
 use rustc_plugin::Registry;
 use syntax::{ast, codemap, tokenstream};
 use syntax::errors::DiagnosticBuilder;
-use syntax::ext::base::{ExtCtxt, MacResult, MacEager, DummyResult};
+use syntax::ext::base::{
+    ExtCtxt, MacResult, MacEager, DummyResult, SyntaxExtension,
+    TTMacroExpander,
+};
 use syntax::ext::build::AstBuilder;
 use syntax::fold::Folder;
 use syntax::parse::common::SeqSep;
@@ -28,22 +31,66 @@ use syntax::symbol;
 use syntax::util::small_vector::SmallVector;
 
 use docopt::{Docopt, ArgvMap};
-use docopt::parse::{Options, Atom, Positional, Zero, One};
+use docopt::parse::{Argument, Options, Atom, Positional, Zero, One};
 
 type PResult<'a, T> = Result<T, DiagnosticBuilder<'a>>;
 
+/// Registers the `docopt!` macro.
+///
+/// The plugin accepts one optional argument, `max_width`, e.g.,
+/// `#![plugin(docopt_macros(max_width = 80))]`. When it is set, a warning
+/// is emitted for every line of a usage string that is wider than it.
 #[plugin_registrar]
 pub fn plugin_registrar(reg: &mut Registry) {
-    reg.register_macro("docopt", expand);
+    let mut expander = Expander { max_width: None };
+    for arg in reg.args().to_vec() {
+        match max_width_arg(&arg) {
+            Some(width) => expander.max_width = Some(width),
+            None => {
+                reg.sess.span_err(arg.span, "Expected `max_width = <number>`")
+            }
+        }
+    }
+    reg.register_syntax_extension(
+        intern("docopt"),
+        SyntaxExtension::NormalTT(Box::new(expander), None, false));
 }
 
-fn expand(cx: &mut ExtCtxt, span: codemap::Span, tts: &[tokenstream::TokenTree])
-         -> Box<MacResult+'static> {
-    let parsed = match MacParser::new(cx, tts).parse() {
-        Ok(parsed) => parsed,
-        Err(_) => return DummyResult::any(span),
+// Returns the width in a plugin argument of the form `max_width = 80`.
+fn max_width_arg(arg: &ast::NestedMetaItem) -> Option<usize> {
+    let mi = match arg.meta_item() {
+        Some(mi) if &*mi.name().as_str() == "max_width" => mi,
+        _ => return None,
     };
-    parsed.items(cx)
+    match mi.node {
+        ast::MetaItemKind::NameValue(ref lit) => match lit.node {
+            ast::LitKind::Int(n, _) => Some(n as usize),
+            ast::LitKind::Str(ref s, _) => s.as_str().parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+struct Expander {
+    /// The widest a line in a usage string may be before a warning is
+    /// emitted. No lines are checked when this is `None`.
+    max_width: Option<usize>,
+}
+
+impl TTMacroExpander for Expander {
+    fn expand<'cx>(&self, cx: &'cx mut ExtCtxt, span: codemap::Span,
+                   tts: &[tokenstream::TokenTree])
+                  -> Box<MacResult+'cx> {
+        let parsed = match MacParser::new(cx, tts).parse() {
+            Ok(parsed) => parsed,
+            Err(_) => return DummyResult::any(span),
+        };
+        if let Some(max) = self.max_width {
+            parsed.check_width(cx, max);
+        }
+        parsed.items(cx)
+    }
 }
 
 /// Parsed corresponds to the result of parsing a `docopt` macro call.
@@ -55,62 +102,193 @@ struct Parsed {
     /// When a type annotation for an atom doesn't exist, then one is
     /// inferred automatically. It is one of: `bool`, `usize`, `String` or
     /// `Vec<String>`.
-    types: HashMap<Atom, P<ast::Ty>>,
+    ///
+    /// Annotations are keyed by struct field name, which is matched against
+    /// `ArgvMap::key_to_struct_field` of each atom.
+    types: HashMap<String, P<ast::Ty>>,
+    /// Conversion functions for annotations of the form
+    /// `field: Ty = path::to::fn`, keyed by struct field name. A converted
+    /// field is decoded with its inferred type and then passed by reference
+    /// to its function, which returns a `Result<Ty, E>` where `E: Debug`.
+    converters: HashMap<String, P<ast::Expr>>,
 }
 
 impl Parsed {
     /// Returns a macro result suitable for expansion.
     /// Contains two items: one for the struct and one for the struct impls.
+    /// The impl defines `USAGE`, the usage string exactly as written,
+    /// `docopt()`, which parses it, and `help()`, which returns the help
+    /// message shown for `--help`.
     fn items(&self, cx: &ExtCtxt) -> Box<MacResult+'static> {
         let mut its = vec!();
         its.push(self.struct_decl(cx));
+        if !self.converters.is_empty() {
+            its.push(self.raw_struct_decl(cx));
+            its.push(self.decodable_impl(cx));
+        }
 
         let struct_name = self.struct_info.name;
-        let full_doc = &*self.doc.parser().full_doc;
+        let source = self.doc.source_text();
+        let help = self.doc.help_string();
         its.push(quote_item!(cx,
             impl $struct_name {
+                #[allow(dead_code)]
+                const USAGE: &'static str = $source;
+
                 #[allow(dead_code)]
                 fn docopt() -> docopt::Docopt {
                     // The unwrap is justified here because this code
                     // gen only happens if the Docopt usage string is valid.
-                    docopt::Docopt::new($full_doc).unwrap()
+                    docopt::Docopt::new($source).unwrap()
+                }
+
+                #[allow(dead_code)]
+                fn help() -> &'static str {
+                    $help
                 }
             }
-        ).unwrap());
+        ).unwrap().map(|mut it| {
+            it.span = cx.call_site();
+            it
+        }));
         MacEager::items(SmallVector::many(its))
     }
 
+    /// Warns about each line of the usage string that is wider than `max`
+    /// characters.
+    fn check_width(&self, cx: &ExtCtxt, max: usize) {
+        let full_doc = &*self.doc.parser().full_doc;
+        for (i, line) in full_doc.lines().enumerate() {
+            let width = line.chars().count();
+            if width > max {
+                cx.span_warn(cx.call_site(), &format!(
+                    "line {} of the usage string is {} characters wide, \
+                     which is more than the maximum of {}",
+                    i + 1, width, max));
+            }
+        }
+    }
+
     /// Returns an item for the struct definition.
+    ///
+    /// `RustcDecodable` is derived unless a field has a conversion
+    /// function, in which case `decodable_impl` implements it instead.
     fn struct_decl(&self, cx: &ExtCtxt) -> P<ast::Item> {
-        let name = self.struct_info.name.clone();
+        let mut traits = vec![];
+        if self.converters.is_empty() {
+            traits.push("RustcDecodable".to_string());
+        }
+        for t in &self.struct_info.deriving {
+            if !traits.contains(t) && t != "RustcDecodable" {
+                traits.push(t.clone());
+            }
+        }
         let vis = if self.struct_info.public { ast::Visibility::Public }
                   else { ast::Visibility::Inherited };
-        let def = ast::VariantData::Struct(
-            self.struct_fields(cx), ast::DUMMY_NODE_ID);
+        self.mk_struct(cx, self.struct_info.name, vis, traits,
+                       self.struct_fields(cx, false))
+    }
 
-        let mut traits = vec!["RustcDecodable".to_string()];
-        traits.extend(self.struct_info.deriving.iter().cloned());
-        let attrs = vec![attribute(cx, "allow", vec!["non_snake_case"]),
-                         attribute(cx, "derive", traits)];
+    /// Returns an item for a private struct that is decoded in place of the
+    /// real one when some fields have conversion functions. Those fields
+    /// have their inferred types.
+    fn raw_struct_decl(&self, cx: &ExtCtxt) -> P<ast::Item> {
+        self.mk_struct(cx, self.raw_name(), ast::Visibility::Inherited,
+                       vec!["RustcDecodable".to_string()],
+                       self.struct_fields(cx, true))
+    }
+
+    /// Returns an impl of `Decodable` for the struct that decodes the raw
+    /// struct and then converts each field that has a conversion function.
+    fn decodable_impl(&self, cx: &ExtCtxt) -> P<ast::Item> {
+        let sp = cx.call_site();
+        let struct_name = self.struct_info.name;
+        let raw_name = self.raw_name();
+        let raw = ident("raw");
+        let fields = self.struct_fields(cx, false).into_iter().map(|field| {
+            let name = field.ident.unwrap();
+            let value = match self.converters.get(&*name.name.as_str()) {
+                None => quote_expr!(cx, $raw.$name),
+                Some(conv) => {
+                    let field_name = name.to_string();
+                    quote_expr!(cx, try!($conv(&$raw.$name).map_err(|e| {
+                        let msg = format!("Could not convert struct field \
+                                           '{}': {:?}", $field_name, e);
+                        d.error(&msg)
+                    })))
+                }
+            };
+            cx.field_imm(sp, name, value)
+        }).collect();
+        let construct = cx.expr_struct_ident(sp, struct_name, fields);
+        quote_item!(cx,
+            impl ::rustc_serialize::Decodable for $struct_name {
+                fn decode<D: ::rustc_serialize::Decoder>(d: &mut D)
+                        -> Result<$struct_name, D::Error> {
+                    let $raw: $raw_name =
+                        try!(::rustc_serialize::Decodable::decode(d));
+                    Ok($construct)
+                }
+            }
+        ).unwrap().map(|mut it| {
+            it.span = sp;
+            it
+        })
+    }
 
-        let st = cx.item_struct(codemap::DUMMY_SP, name.clone(), def);
-        cx.item(codemap::DUMMY_SP, name, attrs, st.node.clone()).map(|mut it| {
+    /// Returns the name of the raw struct used by `decodable_impl`.
+    fn raw_name(&self) -> ast::Ident {
+        ident(&format!("__DocoptRaw{}", self.struct_info.name))
+    }
+
+    /// Returns a struct item with the given fields. Its span is the macro
+    /// call site, so errors in it point at the `docopt!` invocation.
+    fn mk_struct(&self, cx: &ExtCtxt, name: ast::Ident, vis: ast::Visibility,
+                 traits: Vec<String>, fields: Vec<ast::StructField>)
+                -> P<ast::Item> {
+        let sp = cx.call_site();
+        let def = ast::VariantData::Struct(fields, ast::DUMMY_NODE_ID);
+        let mut attrs = vec![attribute(cx, "allow", vec!["non_snake_case"])];
+        if !traits.is_empty() {
+            attrs.push(attribute(cx, "derive", traits));
+        }
+        let st = cx.item_struct(sp, name.clone(), def);
+        cx.item(sp, name, attrs, st.node.clone()).map(|mut it| {
             it.vis = vis;
             it
         })
     }
 
     /// Returns a list of fields for the struct definition.
-    /// Handles type annotations.
-    fn struct_fields(&self, cx: &ExtCtxt) -> Vec<ast::StructField> {
+    /// Handles type annotations. When `raw` is true, fields that have
+    /// conversion functions get their inferred types instead.
+    fn struct_fields(&self, cx: &ExtCtxt, raw: bool) -> Vec<ast::StructField> {
         let mut fields: Vec<ast::StructField> = vec!();
-        for (atom, opts) in self.doc.parser().descs.iter() {
+        for (atom, opts) in self.doc.parser().descs_in_order() {
             let name = ArgvMap::key_to_struct_field(&*atom.to_string());
-            let ty = match self.types.get(atom) {
+            let annotated =
+                if raw && self.converters.contains_key(&name) {
+                    None
+                } else {
+                    self.types.get(&name)
+                };
+            let ty = match annotated {
                 None => self.pat_type(cx, atom, opts),
                 Some(ty) => ty.clone(),
             };
-            fields.push(self.mk_struct_field(&*name, ty));
+            fields.push(self.mk_struct_field(cx, &*name, ty));
+        }
+        // An annotated `cmd` field holds an enum with one variant for each
+        // command, and a field annotated with a `OneOf` type holds an enum
+        // with one variant for each of a group of atoms. They don't
+        // correspond to any atom, so add them separately.
+        let mut extra: Vec<&String> = self.types.iter().filter(|&(name, ty)| {
+            *name == "cmd" || is_one_of(ty)
+        }).map(|(name, _)| name).collect();
+        extra.sort();
+        for name in extra {
+            let ty = self.types[name].clone();
+            fields.push(self.mk_struct_field(cx, name, ty));
         }
         fields
     }
@@ -132,15 +310,24 @@ impl Parsed {
                     _ => cx.ty_ident(sp, ident("usize")),
                 }
             }
+            (false, &One(_)) if opts.sep.is_some() => ty_vec_string(cx),
             (false, &One(_)) => cx.ty_ident(sp, ident("String")),
             (true, &One(_)) => ty_vec_string(cx),
+            (false, &Argument::Optional(_)) if opts.sep.is_some() => {
+                ty_vec_string(cx)
+            }
+            (false, &Argument::Optional(_)) => {
+                cx.ty_option(cx.ty_ident(sp, ident("String")))
+            }
+            (true, &Argument::Optional(_)) => ty_vec_string(cx),
         }
     }
 
     /// Creates a struct field from a member name and type.
-    fn mk_struct_field(&self, name: &str, ty: P<ast::Ty>) -> ast::StructField {
+    fn mk_struct_field(&self, cx: &ExtCtxt, name: &str, ty: P<ast::Ty>)
+                      -> ast::StructField {
         ast::StructField {
-            span: codemap::DUMMY_SP,
+            span: cx.call_site(),
             ident: Some(ident(name)),
             vis: ast::Visibility::Public,
             id: ast::DUMMY_NODE_ID,
@@ -173,35 +360,36 @@ impl<'a, 'b> MacParser<'a, 'b> {
             return Err(err);
         }
         let struct_info = try!(self.parse_struct_info());
-        let docstr = try!(self.parse_str());
+        let (docstr, docstr_span) = try!(self.parse_str());
 
         let mut types = HashMap::new();
+        let mut converters = HashMap::new();
         if !self.p.check(&token::Eof) {
             let sep = SeqSep {
                 sep: Some(token::Comma),
                 trailing_sep_allowed: true,
             };
-            types = self.p.parse_seq_to_before_end(
+            let annotations = self.p.parse_seq_to_before_end(
                 &token::Eof, sep, |p| MacParser::parse_type_annotation(p)
-            ).into_iter()
-             .map(|(ident, ty)| {
-                  let field_name = ident.to_string();
-                  let key = ArgvMap::struct_field_to_key(&*field_name);
-                  (Atom::new(&*key), ty)
-              })
-             .collect::<HashMap<Atom, P<ast::Ty>>>();
+            );
+            for (ident, ty, conv) in annotations {
+                if let Some(conv) = conv {
+                    converters.insert(ident.to_string(), conv);
+                }
+                types.insert(ident.to_string(), ty);
+            }
             try!(self.p.expect(&token::Eof));
         }
 
         // This config does not matter because we're only asking for the
         // usage patterns in the Docopt string. The configuration does not
         // affect the retrieval of usage patterns.
-        let doc = match Docopt::new(docstr) {
+        let doc = match Docopt::new(&*docstr) {
             Ok(doc) => doc,
             Err(err) => {
+                let sp = usage_error_span(self.cx, docstr_span, &docstr);
                 let err = self.cx.struct_span_err(
-                    self.cx.call_site(),
-                    &format!("Invalid Docopt usage: {}", err));
+                    sp, &format!("Invalid Docopt usage: {}", err));
                 return Err(err);
             }
         };
@@ -209,12 +397,13 @@ impl<'a, 'b> MacParser<'a, 'b> {
             struct_info: struct_info,
             doc: doc,
             types: types,
+            converters: converters,
         })
     }
 
-    /// Parses a single string literal. On failure, an error is logged and
-    /// unit is returned.
-    fn parse_str(&mut self) -> PResult<'b, String> {
+    /// Parses a single string literal, returning it along with its span.
+    /// On failure, an error is logged and unit is returned.
+    fn parse_str(&mut self) -> PResult<'b, (String, codemap::Span)> {
         fn lit_is_str(lit: &ast::Lit) -> bool {
             match lit.node {
                 ast::LitKind::Str(_, _) => true,
@@ -240,18 +429,24 @@ impl<'a, 'b> MacParser<'a, 'b> {
             }
         };
         self.p.bump();
-        Ok(s)
+        Ok((s, exp.span))
     }
 
     /// Parses a type annotation in a `docopt` invocation of the form
-    /// `ident: Ty`.
+    /// `ident: Ty`, optionally followed by `= path::to::fn` to give a
+    /// conversion function.
     /// Note that this is a static method as it is used as a HOF.
     fn parse_type_annotation(p: &mut Parser<'b>)
-                             -> PResult<'b, (ast::Ident, P<ast::Ty>)> {
+            -> PResult<'b, (ast::Ident, P<ast::Ty>, Option<P<ast::Expr>>)> {
         let ident = try!(p.parse_ident());
         try!(p.expect(&token::Colon));
         let ty = p.parse_ty().unwrap();
-        Ok((ident, ty))
+        let conv = if p.eat(&token::Eq) {
+            Some(try!(p.parse_expr()))
+        } else {
+            None
+        };
+        Ok((ident, ty, conv))
     }
 
     /// Parses struct information, like visibility, name and deriving.
@@ -284,6 +479,32 @@ struct StructInfo {
     deriving: Vec<String>,
 }
 
+// Returns the span of the text in the usage string literal at `sp` that
+// the error in `doc` is about. Offsets into `doc` only match the source
+// code when the literal has no escapes, so otherwise (or when the error
+// has no location) the span of the whole literal is returned.
+fn usage_error_span(cx: &ExtCtxt, sp: codemap::Span, doc: &str)
+                   -> codemap::Span {
+    let loc = match docopt::parse::Parser::new(doc) {
+        Err(docopt::parse::UsageError { location: Some(loc), .. }) => loc,
+        _ => return sp,
+    };
+    let snippet = match cx.codemap().span_to_snippet(sp) {
+        Ok(snippet) => snippet,
+        Err(_) => return sp,
+    };
+    // Skip the opening `"` (or `r#"`, etc.).
+    let start = match snippet.find('"') {
+        Some(i) => i + 1,
+        None => return sp,
+    };
+    if !snippet[start..].starts_with(doc) {
+        return sp;
+    }
+    let lo = sp.lo + codemap::BytePos((start + loc.offset) as u32);
+    codemap::Span { lo: lo, hi: lo + codemap::BytePos(loc.len as u32), ..sp }
+}
+
 // Convenience functions for building intermediate values.
 
 fn ident(s: &str) -> ast::Ident {
@@ -324,3 +545,15 @@ fn ty_vec_string(cx: &ExtCtxt) -> P<ast::Ty> {
         }]
     })
 }
+
+/// Whether `ty` is `OneOf<T>` or `Option<OneOf<T>>` (possibly with a path,
+/// like `docopt::OneOf<T>`).
+fn is_one_of(ty: &ast::Ty) -> bool {
+    let ty = pprust::ty_to_string(ty).replace(" ", "");
+    let inner = match ty.find('<') {
+        Some(i) if ty[..i].ends_with("Option") => &ty[i + 1..],
+        _ => &ty[..],
+    };
+    let path = inner.split('<').next().unwrap_or("");
+    path.rsplit("::").next() == Some("OneOf")
+}
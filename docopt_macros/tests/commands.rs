@@ -0,0 +1,30 @@
+#![feature(plugin)]
+#![plugin(docopt_macros)]
+
+extern crate rustc_serialize;
+
+extern crate docopt;
+
+docopt!(Args derive Debug, "
+Usage: cargo (build | test | run) [--release]
+", cmd: Command);
+
+#[derive(RustcDecodable, Debug, PartialEq)]
+enum Command { Build, Test, Run }
+
+fn decode(argv: &[&str]) -> Args {
+    Args::docopt().argv(argv.iter()).decode().unwrap()
+}
+
+#[test]
+fn command_enum() {
+    let args = decode(&["cargo", "build"]);
+    assert_eq!(args.cmd, Command::Build);
+    assert!(args.cmd_build);
+
+    let args = decode(&["cargo", "test", "--release"]);
+    assert_eq!(args.cmd, Command::Test);
+    assert!(args.flag_release);
+
+    assert_eq!(decode(&["cargo", "run"]).cmd, Command::Run);
+}
@@ -0,0 +1,59 @@
+#![feature(plugin)]
+#![plugin(docopt_macros)]
+
+extern crate rustc_serialize;
+
+extern crate docopt;
+
+use std::str::FromStr;
+
+use docopt::Error;
+
+// `Level` doesn't implement `Decodable`, so it can only be used through a
+// conversion function.
+#[derive(Debug, PartialEq)]
+enum Level { Low, High }
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s {
+            "low" => Ok(Level::Low),
+            "high" => Ok(Level::High),
+            _ => Err(format!("unknown level '{}'", s)),
+        }
+    }
+}
+
+docopt!(Args derive Debug RustcDecodable PartialEq, "
+Usage: prog [--level=<level>] <n>
+
+Options:
+    --level=<level>  How much [default: low].
+", flag_level: Level = Level::from_str, arg_n: u32);
+
+fn decode(argv: &[&str]) -> Result<Args, Error> {
+    Args::docopt().argv(argv.iter()).decode()
+}
+
+#[test]
+fn converted_field() {
+    let args = decode(&["prog", "5"]).unwrap();
+    assert_eq!(args.flag_level, Level::Low);
+    assert_eq!(args.arg_n, 5);
+
+    let args = decode(&["prog", "--level", "high", "7"]).unwrap();
+    assert_eq!(args, Args { flag_level: Level::High, arg_n: 7 });
+}
+
+#[test]
+fn converted_field_error() {
+    match decode(&["prog", "--level", "max", "1"]) {
+        Err(Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not convert struct field 'flag_level': \
+                             \"unknown level 'max'\"");
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
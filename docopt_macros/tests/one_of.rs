@@ -0,0 +1,34 @@
+#![feature(plugin)]
+#![plugin(docopt_macros)]
+
+extern crate rustc_serialize;
+
+extern crate docopt;
+
+use docopt::OneOf;
+
+docopt!(Args derive Debug, "
+Usage: prog [--verbose] [--stdin | --file=<f>]
+
+Options:
+    --verbose   Be loud.
+    --stdin     Read stdin.
+    --file=<f>  Read a file.
+", input: Option<OneOf<Input>>);
+
+#[derive(RustcDecodable, Debug, PartialEq)]
+enum Input { Stdin, File(String) }
+
+fn decode(argv: &[&str]) -> Args {
+    Args::docopt().argv(argv.iter()).decode().unwrap()
+}
+
+#[test]
+fn one_of_enum() {
+    assert_eq!(decode(&["prog", "--stdin"]).input, Some(OneOf(Input::Stdin)));
+    let args = decode(&["prog", "--file", "a.txt", "--verbose"]);
+    assert_eq!(args.input, Some(OneOf(Input::File("a.txt".to_string()))));
+    assert_eq!(args.flag_file, "a.txt");
+    assert!(args.flag_verbose);
+    assert_eq!(decode(&["prog"]).input, None);
+}
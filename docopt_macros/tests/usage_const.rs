@@ -0,0 +1,31 @@
+#![feature(plugin)]
+#![plugin(docopt_macros)]
+
+extern crate rustc_serialize;
+
+extern crate docopt;
+
+docopt!(Args, "
+Usage: prog [--quiet] <file>
+
+Options:
+    --quiet  Be quiet.
+");
+
+#[test]
+fn usage_const() {
+    assert_eq!(Args::USAGE, "
+Usage: prog [--quiet] <file>
+
+Options:
+    --quiet  Be quiet.
+");
+    assert_eq!(Args::USAGE, Args::docopt().source_text());
+}
+
+#[test]
+fn help() {
+    assert!(Args::help().starts_with("Usage: prog [--quiet] <file>"));
+    assert!(Args::help().ends_with("--quiet  Be quiet."));
+    assert_eq!(Args::help(), Args::docopt().help_string());
+}
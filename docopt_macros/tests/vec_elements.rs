@@ -0,0 +1,33 @@
+#![feature(plugin)]
+#![plugin(docopt_macros)]
+
+extern crate rustc_serialize;
+
+extern crate docopt;
+
+use docopt::Error;
+
+docopt!(Args derive Debug, "
+Usage: sum <n>...
+", arg_n: Vec<u32>);
+
+fn decode(argv: &[&str]) -> Result<Args, Error> {
+    Args::docopt().argv(argv.iter()).decode()
+}
+
+#[test]
+fn vec_u32() {
+    assert_eq!(decode(&["sum", "1", "2", "30"]).unwrap().arg_n,
+               vec![1, 2, 30]);
+}
+
+#[test]
+fn vec_u32_invalid_element() {
+    match decode(&["sum", "1", "two", "3"]) {
+        Err(Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode 'two' to u32 for '<n>' \
+                             (element 2 of 3).");
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
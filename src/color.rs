@@ -0,0 +1,79 @@
+use std::ffi::OsString;
+
+const HEADER: &'static str = "\x1b[1;4m";
+const FLAG: &'static str = "\x1b[32m";
+const RESET: &'static str = "\x1b[0m";
+
+/// Returns whether help should be colored.
+///
+/// Color is only used when writing to a terminal, and never when the
+/// `NO_COLOR` environment variable is set to a non-empty value.
+pub fn enabled(is_tty: bool, no_color: Option<OsString>) -> bool {
+    is_tty && no_color.map_or(true, |v| v.is_empty())
+}
+
+/// Returns whether stdout is a terminal.
+#[cfg(unix)]
+pub fn stdout_is_tty() -> bool {
+    use std::os::raw::c_int;
+    extern "C" { fn isatty(fd: c_int) -> c_int; }
+    unsafe { isatty(1) == 1 }
+}
+
+/// Returns whether stdout is a terminal.
+#[cfg(not(unix))]
+pub fn stdout_is_tty() -> bool {
+    false
+}
+
+/// Colors the section headers (like `Usage:` and `Options:`) and the flags
+/// of each option description in `doc` with ANSI escape sequences.
+pub fn colorize(doc: &str) -> String {
+    let mut out = String::with_capacity(doc.len());
+    for (i, line) in doc.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let indent = line.len() - line.trim_left().len();
+        let (space, rest) = line.split_at(indent);
+        out.push_str(space);
+        if let Some(end) = header_end(rest) {
+            out.push_str(&format!("{}{}{}", HEADER, &rest[..end], RESET));
+            let rest = &rest[end..];
+            let trimmed = rest.trim_left();
+            if trimmed.starts_with('-') {
+                out.push_str(&rest[..rest.len() - trimmed.len()]);
+                push_option(&mut out, trimmed);
+            } else {
+                out.push_str(rest);
+            }
+        } else if rest.starts_with('-') {
+            push_option(&mut out, rest);
+        } else {
+            out.push_str(rest);
+        }
+    }
+    out
+}
+
+// Colors the flags of an option description, which end at the first two
+// consecutive spaces.
+fn push_option(out: &mut String, line: &str) {
+    let end = line.find("  ").unwrap_or(line.len());
+    out.push_str(&format!("{}{}{}{}", FLAG, &line[..end], RESET, &line[end..]));
+}
+
+// Returns the end of a section header like `Usage:` at the start of `line`.
+fn header_end(line: &str) -> Option<usize> {
+    let colon = match line.find(':') {
+        None => return None,
+        Some(i) => i,
+    };
+    let name = line[..colon].to_lowercase();
+    let words = name.split_whitespace().count();
+    if name == "usage" || (name.ends_with("options") && words <= 2) {
+        Some(colon + 1)
+    } else {
+        None
+    }
+}
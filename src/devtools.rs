@@ -0,0 +1,1215 @@
+//! Tools for authors of Docopt usage strings.
+//!
+//! These are not needed to parse command line arguments, so they are only
+//! available when the `devtools` feature is enabled.
+
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use regex::Regex;
+use rustc_serialize::json::Json;
+
+use dopt::{Docopt, Error};
+use parse::{Argument, Atom, Location, Options, Parser, UsageError, Zero, One};
+use parse::Atom::{Short, Long};
+
+/// A description of a command line interface in the style of a builder API.
+///
+/// This is meant to be populated from an existing definition (e.g., one
+/// written for another argument parser) so that it can be converted to a
+/// Docopt usage string with `from_clap_like`.
+#[derive(Clone, Debug, Default)]
+pub struct CliSpec {
+    /// The program name, or the subcommand name for nested specs.
+    pub name: String,
+    /// A one line description, shown above the usage patterns.
+    pub about: Option<String>,
+    /// Flags, options and positional arguments.
+    ///
+    /// Arguments of the top-level spec are accepted by every subcommand.
+    pub args: Vec<ArgSpec>,
+    /// Subcommands. Each one produces its own usage pattern.
+    pub subcommands: Vec<CliSpec>,
+}
+
+/// A single flag, option or positional argument in a `CliSpec`.
+///
+/// An argument with neither a `short` nor a `long` name is positional.
+#[derive(Clone, Debug, Default)]
+pub struct ArgSpec {
+    /// The name of the argument. For options, this is used as the name of
+    /// the value placeholder.
+    pub name: String,
+    /// A short flag name, e.g., `'v'` for `-v`.
+    pub short: Option<char>,
+    /// A long flag name without dashes, e.g., `"verbose"` for `--verbose`.
+    pub long: Option<String>,
+    /// Whether the option takes a value.
+    pub takes_value: bool,
+    /// Whether the argument may be given more than once.
+    pub multiple: bool,
+    /// Whether the argument must be given.
+    pub required: bool,
+    /// The default value of an option that takes a value.
+    pub default: Option<String>,
+    /// The allowed values of an option that takes a value.
+    pub choices: Vec<String>,
+    /// The help text shown in the options section.
+    pub help: String,
+}
+
+impl ArgSpec {
+    fn is_positional(&self) -> bool {
+        self.short.is_none() && self.long.is_none()
+    }
+
+    fn placeholder(&self) -> String {
+        format!("<{}>", self.name.to_lowercase().replace(" ", "-"))
+    }
+
+    fn atom(&self) -> Atom {
+        match (&self.long, self.short) {
+            (&Some(ref long), _) => Atom::new(&*format!("--{}", long)),
+            (&None, Some(short)) => Atom::new(&*format!("-{}", short)),
+            (&None, None) => Atom::new(&*self.placeholder()),
+        }
+    }
+
+    // The flag as it appears in a usage pattern, e.g., `--out=<file>`.
+    fn pattern(&self) -> String {
+        let mut pat = match (&self.long, self.short) {
+            (&Some(ref long), _) => format!("--{}", long),
+            (&None, Some(short)) => format!("-{}", short),
+            (&None, None) => self.placeholder(),
+        };
+        if self.takes_value && !self.is_positional() {
+            pat.push(if self.long.is_some() { '=' } else { ' ' });
+            pat.push_str(&self.placeholder());
+        }
+        if self.multiple {
+            pat.push_str("...");
+        }
+        if !self.required {
+            pat = format!("[{}]", pat);
+        }
+        pat
+    }
+
+    // The flag column of the options section, e.g., `-o, --out=<file>`.
+    fn flags_column(&self) -> String {
+        let mut col = String::new();
+        if let Some(short) = self.short {
+            col.push_str(&format!("-{}", short));
+            if self.long.is_some() {
+                col.push_str(", ");
+            }
+        }
+        if let Some(ref long) = self.long {
+            col.push_str(&format!("--{}", long));
+        }
+        if self.takes_value {
+            col.push(if self.long.is_some() { '=' } else { ' ' });
+            col.push_str(&self.placeholder());
+        }
+        if self.multiple {
+            col.push_str(" ...");
+        }
+        col
+    }
+
+    // The description column of the options section.
+    fn description(&self) -> String {
+        let mut desc = self.help.trim().to_string();
+        if !self.choices.is_empty() {
+            if !desc.is_empty() { desc.push(' '); }
+            desc.push_str(&format!("[choices: {}]", self.choices.join(" ")));
+        }
+        if let Some(ref default) = self.default {
+            if !desc.is_empty() { desc.push(' '); }
+            desc.push_str(&format!("[default: {}]", default));
+        }
+        desc
+    }
+}
+
+/// Converts a builder-style description of a command line interface into
+/// a Docopt usage string.
+///
+/// Each subcommand (and each nested subcommand) gets its own usage pattern.
+/// Required options appear in the patterns explicitly, while optional ones
+/// are covered by the `[options]` shortcut. All options are listed in a
+/// single, aligned `Options:` section along with their defaults and choices.
+///
+/// The generated usage string is parsed with `Docopt::new` before it is
+/// returned. If it fails to parse, or if the parsed result doesn't agree
+/// with `spec` (e.g., an option lost its argument), then a `Usage` error is
+/// returned.
+pub fn from_clap_like(spec: CliSpec) -> Result<String, Error> {
+    let mut leaves = vec![];
+    collect_leaves(&spec, vec![], vec![], &mut leaves);
+    let help = ArgSpec {
+        name: "help".into(),
+        long: Some("help".into()),
+        help: "Show this screen.".into(),
+        ..ArgSpec::default()
+    };
+    let help_short = ArgSpec { short: Some('h'), ..help.clone() };
+
+    let mut options: Vec<&ArgSpec> = vec![];
+    let mut positionals: Vec<&ArgSpec> = vec![];
+    let mut commands: Vec<&str> = vec![];
+    for &(ref path, ref args) in &leaves {
+        for name in path {
+            if !commands.contains(name) {
+                commands.push(*name);
+            }
+        }
+        for arg in args {
+            let list = if arg.is_positional() {
+                &mut positionals
+            } else {
+                &mut options
+            };
+            if !list.iter().any(|a| a.atom() == arg.atom()) {
+                list.push(*arg);
+            }
+        }
+    }
+
+    let mut doc = String::new();
+    if let Some(ref about) = spec.about {
+        doc.push_str(about.trim());
+        doc.push_str("\n\n");
+    }
+    doc.push_str("Usage:\n");
+    for &(ref path, ref args) in &leaves {
+        let mut line = format!("  {}", spec.name);
+        for name in path {
+            line.push(' ');
+            line.push_str(name);
+        }
+        if args.iter().any(|a| !a.is_positional() && !a.required) {
+            line.push_str(" [options]");
+        }
+        for arg in args.iter().filter(|a| !a.is_positional() && a.required) {
+            line.push(' ');
+            line.push_str(&arg.pattern());
+        }
+        for arg in args.iter().filter(|a| a.is_positional()) {
+            line.push(' ');
+            line.push_str(&arg.pattern());
+        }
+        doc.push_str(&line);
+        doc.push('\n');
+    }
+    doc.push_str(&format!("  {} --help\n", spec.name));
+
+    if !options.iter().any(|a| a.long.as_ref().map_or(false, |l| l == "help")) {
+        if options.iter().any(|a| a.short == Some('h')) {
+            options.push(&help);
+        } else {
+            options.push(&help_short);
+        }
+    }
+    let width = options.iter()
+                       .map(|a| a.flags_column().chars().count())
+                       .fold(0, cmp::max);
+    doc.push_str("\nOptions:\n");
+    for arg in &options {
+        let col = arg.flags_column();
+        let desc = arg.description();
+        if desc.is_empty() {
+            doc.push_str(&format!("  {}\n", col));
+        } else {
+            let pad = width - col.chars().count() + 2;
+            doc.push_str(&format!("  {}{}{}\n", col, repeat(' ', pad), desc));
+        }
+    }
+
+    try!(check_equivalent(&doc, &options, &positionals, &commands));
+    Ok(doc)
+}
+
+fn collect_leaves<'a>(
+    spec: &'a CliSpec,
+    path: Vec<&'a str>,
+    mut args: Vec<&'a ArgSpec>,
+    leaves: &mut Vec<(Vec<&'a str>, Vec<&'a ArgSpec>)>,
+) {
+    args.extend(spec.args.iter());
+    if spec.subcommands.is_empty() {
+        leaves.push((path, args));
+        return;
+    }
+    for sub in &spec.subcommands {
+        let mut subpath = path.clone();
+        subpath.push(&*sub.name);
+        collect_leaves(sub, subpath, args.clone(), leaves);
+    }
+}
+
+fn check_equivalent(
+    doc: &str,
+    options: &[&ArgSpec],
+    positionals: &[&ArgSpec],
+    commands: &[&str],
+) -> Result<(), Error> {
+    macro_rules! mismatch(
+        ($($arg:tt)*) => (
+            return Err(Error::Usage(format!(
+                "Generated usage does not match the spec: {}\n\n{}",
+                format!($($arg)*), doc)))
+        )
+    );
+    let dopt = match Docopt::new(doc) {
+        Ok(dopt) => dopt,
+        Err(err) => mismatch!("{}", err),
+    };
+    let descs = &dopt.parser().descs;
+    for arg in options {
+        let atom = arg.atom();
+        let opts = match descs.find(&atom) {
+            None => mismatch!("missing option '{}'", atom),
+            Some(opts) => opts,
+        };
+        if let Some(short) = arg.short {
+            if descs.resolve(&Atom::new(&*format!("-{}", short))) != atom {
+                mismatch!("'-{}' is not a synonym for '{}'", short, atom)
+            }
+        }
+        match (arg.takes_value, &opts.arg) {
+            (false, &Zero) => {}
+            (true, &One(ref default))
+            | (true, &Argument::Optional(ref default)) => {
+                if default != &arg.default {
+                    mismatch!("default of '{}' is {:?} instead of {:?}",
+                              atom, default, arg.default)
+                }
+            }
+            (true, &Zero) => mismatch!("'{}' lost its argument", atom),
+            (false, &One(_)) | (false, &Argument::Optional(_)) =>
+                mismatch!("'{}' gained an argument", atom),
+        }
+        if arg.multiple != opts.repeats {
+            mismatch!("repetition of '{}' is {} instead of {}",
+                      atom, opts.repeats, arg.multiple)
+        }
+    }
+    for arg in positionals {
+        let atom = arg.atom();
+        match descs.find(&atom) {
+            None => mismatch!("missing argument '{}'", atom),
+            Some(opts) if opts.repeats != arg.multiple => {
+                mismatch!("repetition of '{}' is {} instead of {}",
+                          atom, opts.repeats, arg.multiple)
+            }
+            Some(_) => {}
+        }
+    }
+    for name in commands {
+        if !descs.contains_key(&Atom::new(name)) {
+            mismatch!("missing command '{}'", name)
+        }
+    }
+    Ok(())
+}
+
+/// The differences between the command line interfaces described by two
+/// usage strings, as found by `diff_grammars`.
+///
+/// Its `Display` impl writes a Markdown list suitable for release notes,
+/// and `to_json` gives a machine-readable form for checks in CI.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GrammarDiff {
+    /// Every change, with options in sorted order followed by usage
+    /// patterns in the order they're written.
+    pub changes: Vec<Change>,
+}
+
+/// A single change between two usage strings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// What changed.
+    pub kind: ChangeKind,
+    /// Whether a command line accepted by the old usage string may now be
+    /// rejected or mean something else. See `diff_grammars` for the policy.
+    pub breaking: bool,
+}
+
+/// The kinds of changes found by `diff_grammars`.
+///
+/// Options are named by their canonical flag, which is the long flag if
+/// there is one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeKind {
+    /// A new option.
+    OptionAdded {
+        /// The new option.
+        name: String,
+    },
+    /// An option that no longer exists.
+    OptionRemoved {
+        /// The removed option.
+        name: String,
+    },
+    /// An option whose canonical flag changed while keeping one of its
+    /// synonyms, e.g., `-q, --quiet` to `-q, --silent`.
+    OptionRenamed {
+        /// The old canonical flag.
+        old: String,
+        /// The new canonical flag.
+        new: String,
+    },
+    /// A new synonym for an existing option.
+    AliasAdded {
+        /// The option.
+        name: String,
+        /// The new synonym.
+        alias: String,
+    },
+    /// A synonym that no longer exists.
+    AliasRemoved {
+        /// The option.
+        name: String,
+        /// The removed synonym.
+        alias: String,
+    },
+    /// A changed `[default: ...]` value.
+    DefaultChanged {
+        /// The option.
+        name: String,
+        /// The old default, if there was one.
+        old: Option<String>,
+        /// The new default, if there is one.
+        new: Option<String>,
+    },
+    /// A change in whether an option takes an argument or may be repeated.
+    ArityChanged {
+        /// The option.
+        name: String,
+        /// The old arity, e.g., `a switch`.
+        old: String,
+        /// The new arity, e.g., `one argument`.
+        new: String,
+    },
+    /// A change in the values listed with `[choices: ...]`.
+    ChoicesChanged {
+        /// The option.
+        name: String,
+        /// Choices that are new.
+        added: Vec<String>,
+        /// Choices that no longer exist.
+        removed: Vec<String>,
+    },
+    /// A new usage pattern.
+    PatternAdded {
+        /// The pattern without the program name.
+        pattern: String,
+    },
+    /// A usage pattern that no longer exists.
+    PatternRemoved {
+        /// The pattern without the program name.
+        pattern: String,
+    },
+    /// A change in the help text of an option, not counting its default or
+    /// choices.
+    DescriptionChanged {
+        /// The option.
+        name: String,
+        /// The old help text.
+        old: String,
+        /// The new help text.
+        new: String,
+    },
+}
+
+impl GrammarDiff {
+    /// Returns true if any change is breaking.
+    pub fn is_breaking(&self) -> bool {
+        self.changes.iter().any(|c| c.breaking)
+    }
+
+    /// Returns a JSON object with a `breaking` boolean for the whole diff
+    /// and a `changes` array. Each change has a `kind` (e.g.,
+    /// `option_removed`), a `breaking` boolean, a `summary` in the same
+    /// form as the `Display` output and the fields of its `ChangeKind`.
+    pub fn to_json(&self) -> String {
+        let changes = self.changes.iter().map(|c| c.json()).collect();
+        let mut obj = BTreeMap::new();
+        obj.insert("breaking".to_string(), Json::Boolean(self.is_breaking()));
+        obj.insert("changes".to_string(), Json::Array(changes));
+        Json::Object(obj).to_string()
+    }
+}
+
+impl fmt::Display for GrammarDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in &self.changes {
+            try!(writeln!(f, "- {}", c));
+        }
+        Ok(())
+    }
+}
+
+impl Change {
+    fn json(&self) -> Json {
+        fn s(x: &str) -> Json { Json::String(x.to_string()) }
+        fn opt(x: &Option<String>) -> Json {
+            x.as_ref().map_or(Json::Null, |x| s(x))
+        }
+        fn list(xs: &[String]) -> Json {
+            Json::Array(xs.iter().map(|x| s(x)).collect())
+        }
+
+        let (kind, fields) = match self.kind {
+            ChangeKind::OptionAdded { ref name } => {
+                ("option_added", vec![("name", s(name))])
+            }
+            ChangeKind::OptionRemoved { ref name } => {
+                ("option_removed", vec![("name", s(name))])
+            }
+            ChangeKind::OptionRenamed { ref old, ref new } => {
+                ("option_renamed", vec![("old", s(old)), ("new", s(new))])
+            }
+            ChangeKind::AliasAdded { ref name, ref alias } => {
+                ("alias_added", vec![("name", s(name)), ("alias", s(alias))])
+            }
+            ChangeKind::AliasRemoved { ref name, ref alias } => {
+                ("alias_removed",
+                 vec![("name", s(name)), ("alias", s(alias))])
+            }
+            ChangeKind::DefaultChanged { ref name, ref old, ref new } => {
+                ("default_changed",
+                 vec![("name", s(name)), ("old", opt(old)), ("new", opt(new))])
+            }
+            ChangeKind::ArityChanged { ref name, ref old, ref new } => {
+                ("arity_changed",
+                 vec![("name", s(name)), ("old", s(old)), ("new", s(new))])
+            }
+            ChangeKind::ChoicesChanged { ref name, ref added, ref removed } => {
+                ("choices_changed",
+                 vec![("name", s(name)),
+                      ("added", list(added)),
+                      ("removed", list(removed))])
+            }
+            ChangeKind::PatternAdded { ref pattern } => {
+                ("pattern_added", vec![("pattern", s(pattern))])
+            }
+            ChangeKind::PatternRemoved { ref pattern } => {
+                ("pattern_removed", vec![("pattern", s(pattern))])
+            }
+            ChangeKind::DescriptionChanged { ref name, ref old, ref new } => {
+                ("description_changed",
+                 vec![("name", s(name)), ("old", s(old)), ("new", s(new))])
+            }
+        };
+        let mut obj: BTreeMap<String, Json> =
+            fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+        obj.insert("kind".to_string(), s(kind));
+        obj.insert("breaking".to_string(), Json::Boolean(self.breaking));
+        obj.insert("summary".to_string(), s(&self.to_string()));
+        Json::Object(obj)
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn or_none(x: &Option<String>) -> String {
+            x.as_ref().map_or("none".to_string(), |x| format!("`{}`", x))
+        }
+        fn code(xs: &[String]) -> String {
+            xs.iter().map(|x| format!("`{}`", x)).collect::<Vec<_>>().join(", ")
+        }
+
+        if self.breaking {
+            try!(write!(f, "**Breaking:** "));
+        }
+        match self.kind {
+            ChangeKind::OptionAdded { ref name } => {
+                write!(f, "Added option `{}`.", name)
+            }
+            ChangeKind::OptionRemoved { ref name } => {
+                write!(f, "Removed option `{}`.", name)
+            }
+            ChangeKind::OptionRenamed { ref old, ref new } => {
+                write!(f, "Renamed option `{}` to `{}`.", old, new)
+            }
+            ChangeKind::AliasAdded { ref name, ref alias } => {
+                write!(f, "Added `{}` as a synonym for `{}`.", alias, name)
+            }
+            ChangeKind::AliasRemoved { ref name, ref alias } => {
+                write!(f, "Removed `{}` as a synonym for `{}`.", alias, name)
+            }
+            ChangeKind::DefaultChanged { ref name, ref old, ref new } => {
+                write!(f, "Changed the default of `{}` from {} to {}.",
+                       name, or_none(old), or_none(new))
+            }
+            ChangeKind::ArityChanged { ref name, ref old, ref new } => {
+                write!(f, "Changed `{}` from {} to {}.", name, old, new)
+            }
+            ChangeKind::ChoicesChanged { ref name, ref added, ref removed } => {
+                try!(write!(f, "Changed the choices of `{}`:", name));
+                if !added.is_empty() {
+                    try!(write!(f, " added {}", code(added)));
+                    if !removed.is_empty() {
+                        try!(write!(f, ";"));
+                    }
+                }
+                if !removed.is_empty() {
+                    try!(write!(f, " removed {}", code(removed)));
+                }
+                write!(f, ".")
+            }
+            ChangeKind::PatternAdded { ref pattern } => {
+                write!(f, "Added usage `{}`.", pattern)
+            }
+            ChangeKind::PatternRemoved { ref pattern } => {
+                write!(f, "Removed usage `{}`.", pattern)
+            }
+            ChangeKind::DescriptionChanged { ref name, .. } => {
+                write!(f, "Changed the description of `{}`.", name)
+            }
+        }
+    }
+}
+
+/// Compares the command line interfaces described by two usage strings.
+///
+/// Options are compared by their canonical flag, taking synonyms into
+/// account, and usage patterns are compared as written (ignoring the
+/// program name and differences in whitespace). Choices are read from a
+/// `[choices: ...]` annotation in an option's description, as written by
+/// `from_clap_like`.
+///
+/// A change is breaking when a command line accepted by the old usage
+/// string may be rejected by the new one, or may mean something else.
+/// That is:
+///
+/// * Removing an option, a synonym, a choice or a usage pattern is
+///   breaking. Renaming an option is too, since its old name is removed.
+///   (Changing a usage pattern is reported as a removal and an addition.)
+/// * Changing a default is breaking, since it changes the meaning of
+///   command lines that don't give the option.
+/// * Changing whether an option takes an argument is breaking, as is
+///   making a repeatable option non-repeatable. Making an option
+///   repeatable isn't.
+/// * Adding an option, a synonym, a choice or a usage pattern isn't
+///   breaking, and neither is changing an option's description.
+///
+/// A `Usage` error is returned if either usage string is invalid.
+pub fn diff_grammars(old: &str, new: &str) -> Result<GrammarDiff, Error> {
+    let (old, new) = (try!(Docopt::new(old)), try!(Docopt::new(new)));
+    let (old, new) = (old.parser(), new.parser());
+    let (old_opts, new_opts) = (flag_options(old), flag_options(new));
+    let mut changes = vec![];
+    {
+        let mut push = |kind: ChangeKind, breaking: bool| {
+            changes.push(Change { kind: kind, breaking: breaking });
+        };
+
+        // Maps each new option that replaces an old one to the old one. An
+        // option is replaced when its canonical flag is gone but one of its
+        // synonyms now belongs to a new option.
+        let mut renamed: HashMap<Atom, Atom> = HashMap::new();
+        for (atom, names) in &old_opts {
+            if new_opts.contains_key(atom) {
+                continue;
+            }
+            let found = new_opts.iter().find(|&(new_atom, new_names)| {
+                !old_opts.contains_key(new_atom)
+                && names.iter().any(|n| new_names.contains(n))
+            });
+            if let Some((new_atom, _)) = found {
+                renamed.insert(new_atom.clone(), atom.clone());
+            }
+        }
+        let mut all: Vec<&Atom> =
+            old_opts.keys().chain(new_opts.keys()).collect();
+        all.sort_by(|a, b| a.to_string().cmp(&b.to_string()));
+        all.dedup();
+        for atom in all {
+            if renamed.values().any(|a| a == atom) {
+                continue;
+            }
+            let old_atom = renamed.get(atom).unwrap_or(atom);
+            let name = atom.to_string();
+            let old_names = old_opts.get(old_atom);
+            let new_names = new_opts.get(atom);
+            let (old_names, new_names) = match (old_names, new_names) {
+                (None, None) => unreachable!(),
+                (Some(_), None) => {
+                    push(ChangeKind::OptionRemoved { name: name }, true);
+                    continue;
+                }
+                (None, Some(_)) => {
+                    push(ChangeKind::OptionAdded { name: name }, false);
+                    continue;
+                }
+                (Some(old), Some(new)) => (old, new),
+            };
+            if old_atom != atom {
+                push(ChangeKind::OptionRenamed {
+                    old: old_atom.to_string(),
+                    new: name.clone(),
+                }, true);
+            }
+            for alias in new_names {
+                if !old_names.contains(alias) && alias != atom {
+                    push(ChangeKind::AliasAdded {
+                        name: name.clone(),
+                        alias: alias.to_string(),
+                    }, false);
+                }
+            }
+            for alias in old_names {
+                if !new_names.contains(alias) && alias != old_atom {
+                    push(ChangeKind::AliasRemoved {
+                        name: name.clone(),
+                        alias: alias.to_string(),
+                    }, true);
+                }
+            }
+
+            let (o, n) = (old.descs.get(old_atom), new.descs.get(atom));
+            if arity(o) != arity(n) {
+                let breaking = o.arg.has_arg() != n.arg.has_arg() || !n.repeats;
+                push(ChangeKind::ArityChanged {
+                    name: name.clone(),
+                    old: arity(o).to_string(),
+                    new: arity(n).to_string(),
+                }, breaking);
+            }
+            let defaults = match (&o.arg, &n.arg) {
+                (&One(ref od), &One(ref nd)) => Some((od, nd)),
+                (&Argument::Optional(ref od), &Argument::Optional(ref nd)) => {
+                    Some((od, nd))
+                }
+                _ => None,
+            };
+            if let Some((od, nd)) = defaults {
+                if od != nd {
+                    push(ChangeKind::DefaultChanged {
+                        name: name.clone(),
+                        old: od.clone(),
+                        new: nd.clone(),
+                    }, true);
+                }
+            }
+            let (oc, nc) = (&o.choices, &n.choices);
+            let added: Vec<String> =
+                nc.iter().filter(|c| !oc.contains(c)).cloned().collect();
+            let removed: Vec<String> =
+                oc.iter().filter(|c| !nc.contains(c)).cloned().collect();
+            if !added.is_empty() || !removed.is_empty() {
+                let breaking = !removed.is_empty();
+                push(ChangeKind::ChoicesChanged {
+                    name: name.clone(),
+                    added: added,
+                    removed: removed,
+                }, breaking);
+            }
+            let (oh, nh) = (plain_help(&o.help), plain_help(&n.help));
+            if oh != nh {
+                push(ChangeKind::DescriptionChanged {
+                    name: name.clone(),
+                    old: oh,
+                    new: nh,
+                }, false);
+            }
+        }
+
+        let (old_pats, new_pats) = (patterns(old), patterns(new));
+        for pat in &old_pats {
+            if !new_pats.contains(pat) {
+                push(ChangeKind::PatternRemoved { pattern: pat.clone() }, true);
+            }
+        }
+        for pat in &new_pats {
+            if !old_pats.contains(pat) {
+                push(ChangeKind::PatternAdded { pattern: pat.clone() }, false);
+            }
+        }
+    }
+    Ok(GrammarDiff { changes: changes })
+}
+
+/// The style rules checked by `lint`.
+///
+/// The default checks every rule, taking each convention from its first
+/// use in the usage string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleSet {
+    /// How long flags are joined to their argument in option descriptions.
+    /// `None` turns the rule off.
+    pub value_attachment: Option<Attachment>,
+    /// How the arguments of options are named. `None` turns the rule off.
+    ///
+    /// Positional arguments aren't checked, since renaming them changes
+    /// their keys.
+    pub placeholders: Option<Placeholders>,
+    /// Whether a `[default: ...]` must come after the help text on its
+    /// line. Only other annotations, like `[choices: ...]` or
+    /// `[default-mode: ...]`, may follow it.
+    pub defaults_last: bool,
+    /// Whether the description of the option with the longest flags in a
+    /// section must start exactly two spaces after them.
+    pub two_space_separator: bool,
+    /// Whether the descriptions of the options in a section must all start
+    /// in the same column.
+    pub aligned_descriptions: bool,
+}
+
+impl Default for RuleSet {
+    fn default() -> RuleSet {
+        RuleSet {
+            value_attachment: Some(Attachment::Consistent),
+            placeholders: Some(Placeholders::Consistent),
+            defaults_last: true,
+            two_space_separator: true,
+            aligned_descriptions: true,
+        }
+    }
+}
+
+/// How a long flag is joined to its argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attachment {
+    /// Whichever is used first.
+    Consistent,
+    /// With an equals sign, e.g., `--out=FILE`.
+    Equals,
+    /// With a space, e.g., `--out FILE`.
+    Space,
+}
+
+/// How the argument of an option is named.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placeholders {
+    /// Whichever is used first.
+    Consistent,
+    /// Lowercase in angle brackets, e.g., `<file>`.
+    Angle,
+    /// Uppercase, e.g., `FILE`.
+    Caps,
+}
+
+/// The rules that `lint` checks. See `RuleSet`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    /// `RuleSet::value_attachment`.
+    ValueAttachment,
+    /// `RuleSet::placeholders`.
+    Placeholders,
+    /// `RuleSet::defaults_last`.
+    DefaultsLast,
+    /// `RuleSet::two_space_separator`.
+    Separator,
+    /// `RuleSet::aligned_descriptions`.
+    Alignment,
+}
+
+/// A style problem found by `lint`.
+///
+/// Its `Display` impl shows the offending line in the same way as a
+/// `Usage` error, followed by the suggested rewrite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lint {
+    /// The rule that isn't followed.
+    pub rule: Rule,
+    /// A description of the problem.
+    pub message: String,
+    /// Where the problem is.
+    pub location: Location,
+    /// The offending line, rewritten to follow the rule.
+    pub suggestion: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let err = UsageError {
+            message: self.message.clone(),
+            location: Some(self.location.clone()),
+        };
+        let pad = repeat(' ', self.location.line.to_string().len());
+        write!(f, "{}\n{} = suggestion: {}", err, pad, self.suggestion.trim())
+    }
+}
+
+/// Checks the style of the option descriptions in a usage string.
+///
+/// This is meant for projects with many usage strings written by different
+/// people, to keep them consistent. Only the rules enabled in `rules` are
+/// checked. Problems are returned in the order they appear, each with a
+/// rewrite of the offending line that fixes it.
+///
+/// Sections are runs of option descriptions, ended by a blank line or by
+/// any other line that isn't indented deeper than them.
+///
+/// A `Usage` error is returned if the usage string is invalid.
+pub fn lint(doc: &str, rules: &RuleSet) -> Result<Vec<Lint>, Error> {
+    let dopt = try!(Docopt::new(doc));
+    let p = dopt.parser();
+    let entries = option_entries(p);
+    let mut lints = vec![];
+    if let Some(style) = rules.value_attachment {
+        lint_attachment(p, &entries, style, &mut lints);
+    }
+    if let Some(style) = rules.placeholders {
+        lint_placeholders(p, &entries, style, &mut lints);
+    }
+    if rules.defaults_last {
+        lint_defaults(p, &entries, &mut lints);
+    }
+    let mut start = 0;
+    while start < entries.len() {
+        let section = entries[start].section;
+        let n = entries[start..].iter()
+                                .take_while(|e| e.section == section)
+                                .count();
+        lint_columns(p, &entries[start..start + n], rules, &mut lints);
+        start += n;
+    }
+    lints.sort_by_key(|l| l.location.offset);
+    Ok(lints)
+}
+
+// An option description line.
+struct Entry<'a> {
+    // The line in `Parser::full_doc`, and its offset there.
+    text: &'a str,
+    offset: usize,
+    // The byte range of the flags in `text`.
+    flags: (usize, usize),
+    // Where the description starts in `text`, if there is one.
+    help: Option<usize>,
+    // Entries with the same number are in the same section.
+    section: usize,
+}
+
+impl<'a> Entry<'a> {
+    // The first flag, to name the option in messages.
+    fn name(&self) -> &'a str {
+        let flags = &self.text[self.flags.0..self.flags.1];
+        flags.split(&[' ', ',', '=', '['][..]).next().unwrap_or(flags)
+    }
+
+    // The column (in characters) of the byte offset `i` in `text`.
+    fn column(&self, i: usize) -> usize {
+        self.text[..i].chars().count()
+    }
+
+    // The line with the text between `start` and `end` replaced.
+    fn replace(&self, start: usize, end: usize, with: &str) -> String {
+        format!("{}{}{}", &self.text[..start], with, &self.text[end..])
+    }
+
+    fn lint(&self, p: &Parser, rule: Rule, span: (usize, usize),
+            message: String, suggestion: String) -> Lint {
+        Lint {
+            rule: rule,
+            message: message,
+            location: p.locate(self.offset + span.0, span.1),
+            suggestion: suggestion,
+        }
+    }
+}
+
+// Finds the option description lines outside of the usage section.
+fn option_entries(p: &Parser) -> Vec<Entry> {
+    lazy_static! {
+        static ref OPTIONS: Regex = regex!(r"^\s*(?i:options:)\s*");
+    }
+    let doc = &*p.full_doc;
+    let usage = doc.find(&*p.usage).map_or((0, 0), |i| (i, i + p.usage.len()));
+    let mut entries = vec![];
+    let (mut offset, mut section, mut indent) = (0, 0, None);
+    for text in doc.split('\n') {
+        let start = offset;
+        offset += text.len() + 1;
+        if start < usage.1 && start + text.len() > usage.0 {
+            continue;
+        }
+        let line_indent = text.len() - text.trim_left().len();
+        let fstart = OPTIONS.find(text).map_or(line_indent, |m| m.end());
+        if !text[fstart..].starts_with('-') {
+            let continued = indent.map_or(false, |i| {
+                !text.trim().is_empty() && line_indent > i
+            });
+            if !continued && indent.is_some() {
+                section += 1;
+                indent = None;
+            }
+            continue;
+        }
+        let rest = &text[fstart..];
+        let fend = fstart + rest.find("  ")
+                                .unwrap_or_else(|| rest.trim_right().len());
+        let hstart = text.len() - text[fend..].trim_left().len();
+        entries.push(Entry {
+            text: text,
+            offset: start,
+            flags: (fstart, fend),
+            help: if hstart < text.len() { Some(hstart) } else { None },
+            section: section,
+        });
+        indent = Some(line_indent);
+    }
+    entries
+}
+
+// A flag followed by an argument in an option description, e.g.,
+// `--out=FILE`.
+struct FlagArg<'a> {
+    flag: &'a str,
+    // The text joining them and its offset in the line.
+    sep: (usize, &'a str),
+    // The argument and its offset in the line.
+    arg: (usize, &'a str),
+}
+
+fn flag_args<'a>(e: &Entry<'a>) -> Vec<FlagArg<'a>> {
+    lazy_static! {
+        static ref FLAG_ARG: Regex = regex!(r"(?x)
+            (?P<flag>--?[^\s=,\[]+)
+            (?P<sep>[=\x20]|\[=)
+            (?P<arg><[^>\s]+>|\p{Lu}+)
+            (?:[\s,.\]]|$)
+        ");
+    }
+    let flags = &e.text[e.flags.0..e.flags.1];
+    FLAG_ARG.captures_iter(flags).map(|caps| {
+        let (sep, arg) = (caps.name("sep").unwrap(), caps.name("arg").unwrap());
+        FlagArg {
+            flag: caps.name("flag").unwrap().as_str(),
+            sep: (e.flags.0 + sep.start(), sep.as_str()),
+            arg: (e.flags.0 + arg.start(), arg.as_str()),
+        }
+    }).collect()
+}
+
+fn lint_attachment(p: &Parser, entries: &[Entry], style: Attachment,
+                   lints: &mut Vec<Lint>) {
+    let mut want = match style {
+        Attachment::Consistent => None,
+        Attachment::Equals => Some("="),
+        Attachment::Space => Some(" "),
+    };
+    for e in entries {
+        for fa in flag_args(e) {
+            let (start, sep) = fa.sep;
+            if !fa.flag.starts_with("--") || sep == "[=" {
+                continue;
+            }
+            let want = match want {
+                Some(want) => want,
+                None => {
+                    want = Some(sep);
+                    continue;
+                }
+            };
+            if sep == want {
+                continue;
+            }
+            let describe = |s| if s == "=" { "'='" } else { "a space" };
+            lints.push(e.lint(
+                p, Rule::ValueAttachment, (start, sep.len()),
+                format!("'{}' is joined to its argument with {} instead \
+                         of {}.", fa.flag, describe(sep), describe(want)),
+                e.replace(start, start + sep.len(), want)));
+        }
+    }
+}
+
+fn lint_placeholders(p: &Parser, entries: &[Entry], style: Placeholders,
+                     lints: &mut Vec<Lint>) {
+    let mut want = match style {
+        Placeholders::Consistent => None,
+        style => Some(style),
+    };
+    for e in entries {
+        for fa in flag_args(e) {
+            let (start, arg) = fa.arg;
+            let angle = arg.starts_with('<');
+            let want = match want {
+                Some(want) => want,
+                None => {
+                    want = Some(if angle {
+                        Placeholders::Angle
+                    } else {
+                        Placeholders::Caps
+                    });
+                    continue;
+                }
+            };
+            let fixed = match want {
+                Placeholders::Angle if angle => arg.to_lowercase(),
+                Placeholders::Angle => format!("<{}>", arg.to_lowercase()),
+                _ if angle => {
+                    // Only uppercase letters are allowed in this style.
+                    arg.chars().filter(|c| c.is_alphabetic())
+                       .flat_map(|c| c.to_uppercase()).collect()
+                }
+                _ => continue,
+            };
+            if fixed == arg || fixed.is_empty() {
+                continue;
+            }
+            lints.push(e.lint(
+                p, Rule::Placeholders, (start, arg.len()),
+                format!("The argument of '{}' should be written '{}' \
+                         instead of '{}'.", fa.flag, fixed, arg),
+                e.replace(start, start + arg.len(), &fixed)));
+        }
+    }
+}
+
+fn lint_defaults(p: &Parser, entries: &[Entry], lints: &mut Vec<Lint>) {
+    lazy_static! {
+        static ref DEFAULT: Regex =
+            regex!(r"\[(?i:default):(?:[^\[\]]|\[[^\[\]]*\])*\]");
+    }
+    for e in entries {
+        let h = match e.help {
+            None => continue,
+            Some(h) => h,
+        };
+        let m = match DEFAULT.find(&e.text[h..]) {
+            None => continue,
+            Some(m) => m,
+        };
+        let before = &e.text[h..h + m.start()];
+        let after = &e.text[h + m.end()..];
+        if plain_help(after).is_empty() {
+            continue;
+        }
+        let help: Vec<&str> = vec![before.trim(), after.trim(), m.as_str()]
+                              .into_iter().filter(|s| !s.is_empty()).collect();
+        lints.push(e.lint(
+            p, Rule::DefaultsLast, (h + m.start(), m.as_str().len()),
+            format!("The default of '{}' should come at the end of its \
+                     description.", e.name()),
+            e.replace(h, e.text.len(), &help.join(" "))));
+    }
+}
+
+// Checks the columns of the descriptions in a single section.
+fn lint_columns(p: &Parser, section: &[Entry], rules: &RuleSet,
+                lints: &mut Vec<Lint>) {
+    let entries: Vec<(&Entry, usize)> =
+        section.iter().filter_map(|e| e.help.map(|h| (e, h))).collect();
+    // Moves the description of `e` to column `col`, or as close to it as
+    // the flags allow.
+    let realign = |e: &Entry, h: usize, col: usize| {
+        let col = cmp::max(col, e.column(e.flags.1) + 2);
+        let pad = repeat(' ', col - e.column(e.flags.1));
+        e.replace(e.flags.1, h, &pad)
+    };
+    let mut target = None;
+    let mut flagged = None;
+    if rules.two_space_separator {
+        let widest = entries.iter().max_by_key(|&&(e, _)| e.column(e.flags.1));
+        if let Some(&(e, h)) = widest {
+            let col = e.column(e.flags.1) + 2;
+            target = Some(col);
+            if e.column(h) != col {
+                lints.push(e.lint(
+                    p, Rule::Separator, (e.flags.1, h - e.flags.1),
+                    format!("The description of '{}' should start two \
+                             spaces after its flags.", e.name()),
+                    realign(e, h, col)));
+                flagged = Some(e.offset);
+            }
+        }
+    }
+    if !rules.aligned_descriptions || entries.is_empty() {
+        return;
+    }
+    let target = target.unwrap_or_else(|| {
+        // The most common column, or the first one if there's a tie.
+        let mut counts: Vec<(usize, usize)> = vec![];
+        for &(e, h) in &entries {
+            let col = e.column(h);
+            match counts.iter().position(|&(c, _)| c == col) {
+                Some(i) => counts[i].1 += 1,
+                None => counts.push((col, 1)),
+            }
+        }
+        let mut best = counts[0];
+        for &count in &counts[1..] {
+            if count.1 > best.1 {
+                best = count;
+            }
+        }
+        best.0
+    });
+    for &(e, h) in &entries {
+        if Some(e.offset) == flagged || e.column(h) == target {
+            continue;
+        }
+        let word = e.text[h..].find(char::is_whitespace)
+                              .unwrap_or(e.text.len() - h);
+        lints.push(e.lint(
+            p, Rule::Alignment, (h, word),
+            format!("The description of '{}' starts in column {} instead \
+                     of {}.", e.name(), e.column(h) + 1, target + 1),
+            realign(e, h, target)));
+    }
+}
+
+// Maps the canonical atom of each flag to all of its names.
+fn flag_options(p: &Parser) -> HashMap<Atom, Vec<Atom>> {
+    let mut opts: HashMap<Atom, Vec<Atom>> = HashMap::new();
+    for atom in p.descs.keys() {
+        if let Short(_) | Long(_) = *atom {
+            opts.insert(atom.clone(), vec![atom.clone()]);
+        }
+    }
+    for (from, to) in p.descs.synonyms() {
+        if let Some(names) = opts.get_mut(to) {
+            names.push(from.clone());
+        }
+    }
+    opts
+}
+
+fn arity(opts: &Options) -> &'static str {
+    match (&opts.arg, opts.repeats) {
+        (&Zero, false) => "a switch",
+        (&Zero, true) => "a counted flag",
+        (&One(_), false) => "one argument",
+        (&Argument::Optional(_), false) => "an optional argument",
+        (_, true) => "repeated arguments",
+    }
+}
+
+// Returns `help` without its annotations, with whitespace normalized.
+fn plain_help(help: &str) -> String {
+    lazy_static! {
+        static ref ANNOTATION: Regex =
+            regex!(r"(?ix)\[(?:default|default-mode|choices|sep):
+                      (?:[^\[\]]|\[[^\[\]]*\])*\]");
+    }
+    let help = ANNOTATION.replace_all(help, "");
+    help.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Returns each usage pattern without the program name, with whitespace
+// normalized.
+fn patterns(p: &Parser) -> Vec<String> {
+    let mut pats = vec![];
+    for line in p.usage_block[p.usage_prog_offset..].lines() {
+        let line = line.trim();
+        if !line.starts_with(&*p.program) {
+            continue;
+        }
+        let pat = line[p.program.len()..].split_whitespace()
+                                         .collect::<Vec<_>>().join(" ");
+        pats.push(pat);
+    }
+    pats
+}
+
+fn repeat(c: char, n: usize) -> String {
+    ::std::iter::repeat(c).take(n).collect()
+}
@@ -1,17 +1,32 @@
 use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::fmt::{self, Debug};
-use std::io::{self, Write};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::iter::FromIterator;
+use std::ops::Index;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
 use regex::{Captures, Regex};
 use rustc_serialize::Decodable;
-
-use parse::Parser;
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::hex::FromHex;
+use rustc_serialize::json::Json;
+
+use color;
+use man::{self, ManMeta};
+use parse::{ArgvSettings, Atom, Parser};
+use parse::Atom::{Short, Long, Positional};
+use shellwords::shellwords;
 use synonym::SynonymMap;
 
 use self::Value::{Switch, Counted, Plain, List};
-use self::Error::{Usage, Argv, NoMatch, Decode, WithProgramUsage, Help, Version};
+use self::Error::{
+    Usage, Argv, Split, NoMatch, Decode, WithProgramUsage, Help, Version,
+    Complete, Dispatched,
+};
 
 use cap_or_empty;
 
@@ -59,6 +74,12 @@ pub enum Error {
     /// using flags that aren't defined in the usage string.
     Argv(String),
 
+    /// Splitting a string into arguments with `shellwords` failed.
+    ///
+    /// This happens when a quote isn't closed or when the string ends with
+    /// a backslash.
+    Split(String),
+
     /// The given argv parsed successfully, but it did not match any example
     /// usage of the program.
     ///
@@ -83,6 +104,20 @@ pub enum Error {
     ///
     /// The version is included as a payload to this variant.
     Version(String),
+
+    /// Parsing stopped because the hidden `--complete` flag asked for the
+    /// completions of a value.
+    ///
+    /// The payload is the list of candidates, one per line. See
+    /// `Docopt::complete_values`.
+    Complete(String),
+
+    /// Parsing stopped because the first positional argument wasn't a
+    /// known command, and the function set with
+    /// `Docopt::on_unknown_command` handled it.
+    ///
+    /// The payload is the name of the command.
+    Dispatched(String),
 }
 
 impl Error {
@@ -93,8 +128,8 @@ impl Error {
     /// failing to decode or parse.
     pub fn fatal(&self) -> bool {
         match *self {
-            Help | Version(..) => false,
-            Usage(..) | Argv(..) | NoMatch | Decode(..) => true,
+            Help | Version(..) | Complete(..) | Dispatched(..) => false,
+            Usage(..) | Argv(..) | Split(..) | NoMatch | Decode(..) => true,
             WithProgramUsage(ref b, _) => b.fatal(),
         }
     }
@@ -110,6 +145,9 @@ impl Error {
             werr!("{}\n", self);
             ::std::process::exit(1)
         } else {
+            if let Dispatched(..) = *self {
+                ::std::process::exit(0)
+            }
             let _ = writeln!(&mut io::stdout(), "{}", self);
             ::std::process::exit(0)
         }
@@ -128,8 +166,10 @@ impl fmt::Display for Error {
                 }
             }
             Help => write!(f, ""),
+            Dispatched(ref s) => write!(f, "Handled unknown command '{}'.", s),
             NoMatch => write!(f, "Invalid arguments."),
-            Usage(ref s) | Argv(ref s) | Decode(ref s) | Version(ref s) => {
+            Usage(ref s) | Argv(ref s) | Split(ref s) | Decode(ref s)
+            | Version(ref s) | Complete(ref s) => {
                 write!(f, "{}", s)
             }
         }
@@ -141,11 +181,14 @@ impl StdError for Error {
         match *self {
             Usage(..) => "invalid usage string",
             Argv(..) => "failed to parse specified argv",
+            Split(..) => "failed to split argument string",
             NoMatch => "could not match specified argv",
             Decode(..) => "failed to decode",
             WithProgramUsage(..) => "failed to parse specified argv",
             Help => "help message requested",
             Version(..) => "version message requested",
+            Complete(..) => "completions requested",
+            Dispatched(..) => "unknown command handled",
         }
     }
 
@@ -157,16 +200,142 @@ impl StdError for Error {
     }
 }
 
+/// Controls how the option descriptions are laid out in the help message
+/// shown for `--help`.
+///
+/// This is set with `Docopt::help_layout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HelpLayout {
+    /// Show the Docopt string as it was written. This is the default.
+    AsWritten,
+    /// Within each block of option descriptions, show the options that
+    /// every usage pattern requires first, followed by the optional ones.
+    /// Each group keeps its order from the Docopt string.
+    RequiredFirst,
+}
+
+/// Controls how much of the Docopt string accompanies an argv error.
+///
+/// This has no effect on `--help`, which always shows the full Docopt
+/// string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HelpStyle {
+    /// Show the full Docopt string after the error message.
+    Full,
+    /// Show only the `Usage:` section (with its original indentation),
+    /// followed by a hint pointing at `--help`.
+    UsageOnly,
+    /// Like `UsageOnly`, but when there are many usage patterns, show only
+    /// the ones closest to the given argv. The number of patterns shown is
+    /// set with `Docopt::max_usage_alternatives`.
+    Elided,
+}
+
+/// The encoding of byte strings decoded into a `Vec<u8>` field.
+///
+/// This is set for each key with `Docopt::bytes_encoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BytesEncoding {
+    /// Hexadecimal, e.g., `deadbeef`. Upper and lower case are accepted.
+    Hex,
+    /// Base64 with the standard or URL-safe alphabet, e.g., `3q2+7w==`.
+    Base64,
+}
+
+/// What to do when a key is given more than once for a map field.
+///
+/// This is set with `Docopt::duplicate_keys`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Fail with a `Decode` error naming the duplicate. This is the default.
+    Error,
+    /// Use the last value given for the key.
+    LastWins,
+}
+
 /// The main Docopt type, which is constructed with a Docopt usage string.
 ///
 /// This can be used to match command line arguments to produce a `ArgvMap`.
+///
+/// A `Docopt` is cheap to clone and is `Send` and `Sync`, so the usage only
+/// needs to be parsed once no matter how many argvs are matched against it.
 #[derive(Clone, Debug)]
 pub struct Docopt {
-    p: Parser,
+    // The parsed usage is shared by clones, so that `argv` is cheap.
+    p: Arc<Parser>,
     argv: Option<Vec<String>>,
+    default_argv: Option<Vec<String>>,
+    rest_as_string: Vec<String>,
     options_first: bool,
+    plus_minus_toggles: bool,
+    strict_short_stacks: bool,
+    // Set by `value_separators`, without `=`.
+    value_separators: Vec<char>,
+    slash_flags: bool,
+    ignore_case: Vec<Atom>,
+    aliases: Vec<(Atom, Atom)>,
+    collect_unknown: Option<String>,
+    // Set by `parse_with_trailing_var_arg`.
+    trailing_var_arg: Vec<Vec<Atom>>,
     help: bool,
+    help_flags: Vec<String>,
     version: Option<String>,
+    help_style: Option<HelpStyle>,
+    help_layout: HelpLayout,
+    // The help message for `HelpLayout::RequiredFirst`.
+    help_doc: Option<String>,
+    // The error for the first `%include` line that needs a resolver (see
+    // `include_resolver`), if any.
+    unresolved_include: Option<String>,
+    max_usage_alternatives: usize,
+    colored_help: bool,
+    bytes_encodings: Vec<(String, BytesEncoding)>,
+    duplicate_keys: DuplicateKeys,
+    thousands_separator: Option<char>,
+    sanitize_help: bool,
+    validators: Vec<Validator>,
+    max_repeats: Vec<(String, usize)>,
+    completers: Vec<Completer>,
+    unknown_command: Option<UnknownCommand>,
+}
+
+// A function registered with `Docopt::validate` along with the canonical
+// key whose values it checks.
+#[derive(Clone)]
+struct Validator {
+    key: String,
+    check: Arc<Fn(&str) -> Result<(), String> + Send + Sync>,
+}
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Validator({:?})", self.key)
+    }
+}
+
+// A function registered with `Docopt::complete_values` along with the
+// canonical key whose candidates it lists.
+#[derive(Clone)]
+struct Completer {
+    key: String,
+    candidates: Arc<Fn() -> Vec<String> + Send + Sync>,
+}
+
+impl fmt::Debug for Completer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Completer({:?})", self.key)
+    }
+}
+
+// A function registered with `Docopt::on_unknown_command`.
+#[derive(Clone)]
+struct UnknownCommand(
+    Arc<Fn(&str, &[String]) -> Option<Result<(), Error>> + Send + Sync>);
+
+impl fmt::Debug for UnknownCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UnknownCommand")
+    }
 }
 
 impl Docopt {
@@ -175,19 +344,179 @@ impl Docopt {
     /// The `Docopt` value returned may be used immediately to parse command
     /// line arguments with a default configuration.
     ///
+    /// A positional argument may be written as `<file>` or as `FILE`. Both
+    /// spellings can be mixed in one usage string and refer to the same
+    /// argument. Its value is stored under the spelling used first, but can
+    /// be looked up with either. As in the reference implementation, a word
+    /// made only of uppercase letters is always an argument, never a
+    /// command. (An uppercase word right after a flag that takes an
+    /// argument, like `FILE` in `--input FILE`, is that flag's argument.)
+    ///
     /// If there was a problem parsing the usage string, a `Usage` error
-    /// is returned.
+    /// is returned. When possible, its message ends with the line and
+    /// column of the problem and an excerpt of the line pointing at it.
+    ///
+    /// The `%include` lines of an options or arguments section are left for
+    /// `include_resolver`. Until they are resolved, parsing argv returns a
+    /// `Usage` error naming the line of the first one.
     pub fn new<S>(usage: S) -> Result<Docopt, Error>
             where S: ::std::ops::Deref<Target=str> {
         Parser::new(usage.deref())
-               .map_err(Usage)
-               .map(|p| Docopt {
-                   p: p,
-                   argv: None,
-                   options_first: false,
-                   help: true,
-                   version: None,
-                })
+               .map_err(|e| Usage(e.to_string()))
+               .map(Docopt::from_parser)
+    }
+
+    // Returns a `Docopt` with the default configuration for `p`.
+    fn from_parser(p: Parser) -> Docopt {
+        Docopt {
+            unresolved_include: unresolved_include(&p.full_doc),
+            p: Arc::new(p),
+            argv: None,
+            default_argv: None,
+            rest_as_string: vec![],
+            options_first: false,
+            plus_minus_toggles: false,
+            strict_short_stacks: false,
+            value_separators: vec![],
+            slash_flags: false,
+            ignore_case: vec![],
+            aliases: vec![],
+            collect_unknown: None,
+            trailing_var_arg: vec![],
+            help: true,
+            help_flags: vec!["--help".into()],
+            version: None,
+            help_style: None,
+            help_layout: HelpLayout::AsWritten,
+            help_doc: None,
+            max_usage_alternatives: 4,
+            colored_help: false,
+            bytes_encodings: vec![],
+            duplicate_keys: DuplicateKeys::Error,
+            thousands_separator: None,
+            sanitize_help: false,
+            validators: vec![],
+            max_repeats: vec![],
+            completers: vec![],
+            unknown_command: None,
+        }
+    }
+
+    /// Parse the Docopt usage string given and check that it is consistent.
+    ///
+    /// This is like `new`, except it also returns a `Usage` error if a flag
+    /// appears in a usage pattern without an option description, or if an
+    /// option description isn't used by any usage pattern. (Using
+    /// `[options]` in a pattern counts as using every option description.)
+    /// This catches typos like `--ouput` in the options section.
+    pub fn new_strict<S>(usage: S) -> Result<Docopt, Error>
+            where S: ::std::ops::Deref<Target=str> {
+        let dopt = try!(Docopt::new(usage));
+        try!(dopt.p.check_descs().map_err(Usage));
+        Ok(dopt)
+    }
+
+    /// Parse the Docopt usage string in the file at `path`.
+    ///
+    /// A line of the form `@include FILE` is replaced by the contents of
+    /// `FILE`, which is resolved relative to the directory of the file the
+    /// line is in. This makes it possible to share the option descriptions
+    /// of common flags between programs:
+    ///
+    /// ```text
+    /// Usage: tool [options] <file>
+    ///
+    /// Options:
+    ///   --dry-run  Don't write anything.
+    ///   @include common-options.txt
+    /// ```
+    ///
+    /// Included files may include other files. (`@include` lines have no
+    /// special meaning in a string given to `new`.) Then any `%include
+    /// FILE` lines are resolved as with `include_resolver`, by reading
+    /// `FILE` relative to the directory of `path`.
+    ///
+    /// If a file can't be read or includes itself, a `Usage` error is
+    /// returned.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Docopt, Error> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let doc = try!(read_usage(path, &mut vec![]));
+        Docopt::with_includes(doc, |name| read_file(&dir.join(name)))
+    }
+
+    /// Resolves the `%include NAME` lines of the Docopt string with
+    /// `resolve` and parses the result.
+    ///
+    /// An include must be on a line of its own in an options or arguments
+    /// section, i.e., a section whose header contains `options:` or
+    /// `arguments:`. The line is replaced by the text that `resolve`
+    /// returns for `NAME`, which may include more text in turn. `NAME` is
+    /// given to `resolve` as written, so it can name a file, or a static
+    /// string when there is no file system:
+    ///
+    /// ```rust
+    /// use docopt::{Docopt, Error};
+    ///
+    /// let doc = "
+    /// Usage: tool [options] <file>
+    ///
+    /// Options:
+    ///   --dry-run  Don't write anything.
+    ///   %include common
+    /// ";
+    /// let dopt = Docopt::new(doc).unwrap().include_resolver(|name| {
+    ///     match name {
+    ///         "common" => Ok("  -v, --verbose  Be loud.".to_string()),
+    ///         _ => Err(Error::Usage(format!("no such text: {}", name))),
+    ///     }
+    /// }).unwrap();
+    /// let vals = dopt.argv(&["tool", "-v", "a.txt"]).parse().unwrap();
+    /// assert!(vals.get_bool("--verbose"));
+    /// ```
+    ///
+    /// A `Usage` error naming the line of the include is returned if
+    /// `resolve` fails, if an include is misplaced, or if includes are
+    /// nested too deeply or in a cycle. If the resolved text doesn't parse,
+    /// then the location in the error names the included text and the
+    /// line in it.
+    ///
+    /// The text with every include resolved is what gets parsed, so it's
+    /// also what `--help` shows and what `source_text` returns. The other
+    /// options set on this `Docopt` are kept, but those that name flags
+    /// (like `validate` or `alias`) only know the flags of the text before
+    /// its includes were resolved. So call this first.
+    pub fn include_resolver<F>(self, resolve: F) -> Result<Docopt, Error>
+            where F: Fn(&str) -> Result<String, Error> {
+        let (mut doc, mut origins) = (String::new(), vec![]);
+        try!(expand_includes(&self.p.full_doc, None, &resolve, &mut vec![],
+                             &mut doc, &mut origins));
+        let p = try!(Parser::new(&doc).map_err(|mut e| {
+            if let Some(ref mut loc) = e.location {
+                if let Some(&(ref file, line)) = origins.get(loc.line - 1) {
+                    loc.file = file.clone();
+                    loc.line = line;
+                }
+            }
+            Usage(e.to_string())
+        }));
+        let layout = self.help_layout;
+        Ok(Docopt {
+            unresolved_include: None,
+            p: Arc::new(p),
+            ..self
+        }.help_layout(layout))
+    }
+
+    /// Parse the Docopt usage string given and resolve its `%include NAME`
+    /// lines with `resolve`.
+    ///
+    /// This is a shortcut for `Docopt::new(usage)` followed by
+    /// `include_resolver(resolve)`.
+    pub fn with_includes<S, F>(usage: S, resolve: F) -> Result<Docopt, Error>
+            where S: ::std::ops::Deref<Target=str>,
+                  F: Fn(&str) -> Result<String, Error> {
+        Docopt::new(usage).and_then(|dopt| dopt.include_resolver(resolve))
     }
 
     /// Parse and decode the given argv.
@@ -198,7 +527,86 @@ impl Docopt {
     /// For details on how decoding works, please see the documentation for
     /// `ArgvMap`.
     pub fn decode<D>(&self) -> Result<D, Error> where D: Decodable {
-        self.parse().and_then(|vals| vals.decode())
+        let vals = try!(self.parse());
+        Decodable::decode(&mut Decoder {
+            vals: vals,
+            stack: vec!(),
+            bytes_encodings: self.bytes_encodings.clone(),
+            duplicate_keys: self.duplicate_keys,
+            thousands_separator: self.thousands_separator,
+            defaults_only: false,
+            group: false,
+            group_seen: false,
+            optional_group: false,
+            group_absent: false,
+            last_key: None,
+            last_missing: false,
+        })
+    }
+
+    /// Checks that every `[default: ...]` value can be decoded into the
+    /// type of its field in `D`.
+    ///
+    /// A default like `[default: abc]` for a field of type `u32` is a bug in
+    /// the usage string, but it would otherwise only be reported when the
+    /// program is run without that option. This method decodes `D` as if no
+    /// arguments were given and returns the `Decode` error of the first
+    /// default that fails. Fields without a default are not checked, so
+    /// this can be called from a test. (A field without a default that
+    /// can't be decoded from an empty value, like a `Parsed<u32>`, ends
+    /// the check, since the fields after it can't be decoded without it.)
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate docopt;
+    /// # extern crate rustc_serialize;
+    /// # fn main() {
+    /// use docopt::Docopt;
+    ///
+    /// #[derive(RustcDecodable)]
+    /// struct Args {
+    ///     flag_jobs: u32,
+    /// }
+    ///
+    /// let doc = "
+    /// Usage: build [--jobs=<n>]
+    ///
+    /// Options:
+    ///     --jobs=<n>  Parallel jobs [default: many].
+    /// ";
+    /// let err = Docopt::new(doc).unwrap().check_defaults::<Args>();
+    /// assert!(err.is_err());
+    /// # }
+    /// ```
+    pub fn check_defaults<D>(&self) -> Result<(), Error> where D: Decodable {
+        let vals = ArgvMap {
+            map: self.p.default_values(),
+            key_order: vec![],
+            flag_order: vec![],
+            positional_order: vec![],
+        };
+        let mut d = Decoder {
+            vals: vals,
+            stack: vec!(),
+            bytes_encodings: self.bytes_encodings.clone(),
+            duplicate_keys: self.duplicate_keys,
+            thousands_separator: self.thousands_separator,
+            defaults_only: true,
+            group: false,
+            group_seen: false,
+            optional_group: false,
+            group_absent: false,
+            last_key: None,
+            last_missing: false,
+        };
+        match <D as Decodable>::decode(&mut d) {
+            // A field without a default whose type can't be decoded from
+            // nothing, like a `Parsed` one, can't be checked.
+            Err(_) if d.last_missing => Ok(()),
+            Err(err) => Err(err),
+            Ok(_) => Ok(()),
+        }
     }
 
     /// Parse command line arguments and try to match them against a usage
@@ -216,24 +624,85 @@ impl Docopt {
     /// enabled by default), then `Help` or `Version` errors are returned
     /// if `--help` or `--version` is present.
     pub fn parse(&self) -> Result<ArgvMap, Error> {
-        let argv = self.argv.clone().unwrap_or_else(Docopt::get_argv);
-        let vals = try!(
-            self.p.parse_argv(argv, self.options_first)
-                .map_err(|s| self.err_with_usage(Argv(s)))
-                .and_then(|argv|
-                    match self.p.matches(&argv) {
-                        Some(m) => Ok(ArgvMap { map: m }),
-                        None => Err(self.err_with_usage(NoMatch)),
-                    }));
-        if self.help && vals.get_bool("--help") {
-            return Err(self.err_with_full_doc(Help));
+        if let Some(ref msg) = self.unresolved_include {
+            return Err(Usage(msg.clone()))
+        }
+        let mut argv = self.argv.clone().unwrap_or_else(Docopt::get_argv);
+        if argv.is_empty() {
+            if let Some(ref default) = self.default_argv {
+                argv = default.clone();
+            }
+        }
+        if !self.completers.is_empty()
+                && argv.first().map_or(false, |a| a == "--complete") {
+            return Err(self.complete(&argv[1..]));
+        }
+        let argv = self.compat_argv(argv);
+        let mut vals = try!(
+            self.p.parse_argv(argv.clone(), &self.argv_settings())
+                .map_err(|s| self.err_with_usage(Argv(s), &argv))
+                .and_then(|parsed|
+                    match self.p.matches(&parsed) {
+                        Some(m) => Ok((ArgvMap {
+                            map: m,
+                            key_order: self.p.key_order().iter()
+                                       .map(|a| a.to_string()).collect(),
+                            flag_order: parsed.flag_order().to_vec(),
+                            positional_order: parsed.positional_order(),
+                        }, parsed.unknown().to_vec())),
+                        None => Err(self.err_with_usage(NoMatch, &argv)),
+                    })
+                .map(|(mut vals, unknown)| {
+                    if let Some(ref key) = self.collect_unknown {
+                        vals.add_unknown(key, unknown);
+                    }
+                    vals
+                })
+                .map_err(|err| self.unknown_command(&argv).unwrap_or(err)));
+        for key in &self.rest_as_string {
+            if let Some(v) = vals.map.find_mut(key) {
+                let joined = match *v {
+                    List(ref words) if words.is_empty() => Plain(None),
+                    List(ref words) => Plain(Some(words.join(" "))),
+                    _ => continue,
+                };
+                *v = joined;
+            }
+        }
+        if self.help && self.wants_help(&vals) {
+            return Err(self.err_with_help());
         }
         match self.version {
-            Some(ref v) if vals.get_bool("--version") => {
+            Some(ref v) if given_switch(&vals, "--version") => {
                 return Err(Version(v.clone()))
             }
             _ => {},
         }
+        for &(ref key, max) in &self.max_repeats {
+            match vals.find(key) {
+                Some(&Counted(n)) if n > max as u64 => {
+                    let err = Argv(format!(
+                        "'{}' was given {} times, but at most {} {} allowed.",
+                        key, n, max, if max == 1 { "is" } else { "are" }));
+                    return Err(self.err_with_usage(err, &argv));
+                }
+                _ => {}
+            }
+        }
+        for validator in &self.validators {
+            let values = match vals.find(&validator.key) {
+                Some(&Plain(Some(ref s))) => vec![&**s],
+                Some(&List(ref vs)) => vs.iter().map(|s| &**s).collect(),
+                _ => continue,
+            };
+            for value in values {
+                if let Err(msg) = (validator.check)(value) {
+                    let err = Argv(format!("Invalid value '{}' for '{}': {}",
+                                           value, validator.key, msg));
+                    return Err(self.err_with_usage(err, &argv));
+                }
+            }
+        }
         Ok(vals)
     }
 
@@ -245,11 +714,388 @@ impl Docopt {
     /// The `argv` given *must* be the full set of `argv` passed to the
     /// program. e.g., `["cp", "src", "dest"]` is right while `["src", "dest"]`
     /// is wrong.
-    pub fn argv<I, S>(mut self, argv: I) -> Docopt
+    ///
+    /// This doesn't consume `self`, and the parsed usage is shared rather
+    /// than copied, so a single `Docopt` can be used to parse many argvs,
+    /// even from several threads at once:
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let dopt = Docopt::new("Usage: prog <name>").unwrap();
+    /// for name in &["a", "b"] {
+    ///     let vals = dopt.argv(&["prog", name]).parse().unwrap();
+    ///     assert_eq!(vals.get_str("<name>"), *name);
+    /// }
+    /// ```
+    pub fn argv<I, S>(&self, argv: I) -> Docopt
                where I: IntoIterator<Item=S>, S: AsRef<str> {
-        self.argv = Some(
+        let mut dopt = self.clone();
+        dopt.argv = Some(
             argv.into_iter().skip(1).map(|s| s.as_ref().to_owned()).collect()
         );
+        dopt
+    }
+
+    /// Sets the encoding of the value of `key` when it's decoded into a
+    /// `Vec<u8>`.
+    ///
+    /// When set, a single value (i.e., not a list) of `key` that is
+    /// decoded into a sequence is first decoded from `encoding` into bytes.
+    /// For example, with `bytes_encoding("--key", BytesEncoding::Hex)`,
+    /// `--key 00ff` decodes into a `flag_key` field of type `Vec<u8>` as
+    /// `vec![0, 255]`. Invalid input results in a `Decode` error. Other
+    /// keys are decoded as usual. Setting an encoding for the same key
+    /// again replaces it.
+    ///
+    /// This only applies to `decode` on `Docopt`, not `ArgvMap::decode`.
+    pub fn bytes_encoding(mut self, key: &str, encoding: BytesEncoding)
+                         -> Docopt {
+        self.bytes_encodings.retain(|&(ref k, _)| k != key);
+        self.bytes_encodings.push((key.to_string(), encoding));
+        self
+    }
+
+    /// Sets what happens when a key is given more than once for a field
+    /// decoded into a map. The default is `DuplicateKeys::Error`.
+    ///
+    /// This only applies to `decode` on `Docopt`, not `ArgvMap::decode`.
+    pub fn duplicate_keys(mut self, policy: DuplicateKeys) -> Docopt {
+        self.duplicate_keys = policy;
+        self
+    }
+
+    /// Allows integers to be written with `sep` between groups of three
+    /// digits, e.g., `1,000,000` with `thousands_separator(',')`.
+    ///
+    /// The groups must be complete, so `1,0000` is a `Decode` error.
+    /// Underscores between digits, as in `1_000_000`, are always allowed,
+    /// but can't be mixed with `sep` in the same value.
+    ///
+    /// If `sep` is `_`, `-`, `+` or a digit, then a `Usage` error is
+    /// returned, since those already mean something in an integer.
+    ///
+    /// This only applies to `decode` on `Docopt`, not `ArgvMap::decode`.
+    pub fn thousands_separator(mut self, sep: char)
+                              -> Result<Docopt, Error> {
+        if sep == '_' || sep == '-' || sep == '+' || sep.is_digit(10) {
+            return Err(Usage(format!(
+                "Cannot use '{}' as a thousands separator.", sep)));
+        }
+        self.thousands_separator = Some(sep);
+        Ok(self)
+    }
+
+    /// Captures the values of a repeated positional argument as one string.
+    ///
+    /// For a pattern like `prog run <script>...`, the values of `<script>`
+    /// are joined with a single space into a plain value instead of being
+    /// returned as a list. So `prog run echo "a b" c` gives `<script>` the
+    /// value `echo a b c`, which can be decoded into a `String`. If no
+    /// values were given, then the value is absent.
+    ///
+    /// Note that arguments that look like flags are still parsed as flags.
+    /// Use `--` or `options_first` to capture them too.
+    pub fn rest_as_string(mut self, key: &str) -> Docopt {
+        self.rest_as_string.push(key.into());
+        self
+    }
+
+    /// Collects unknown flags instead of returning an error for them.
+    ///
+    /// Every flag in argv that isn't in the Docopt string is appended, as
+    /// it was given, to the list value of `into_key`. This is useful for a
+    /// wrapper that passes the flags it doesn't know on to another program.
+    /// Flags in the Docopt string are parsed as usual. If `into_key` names
+    /// a repeated positional argument, then the unknown flags are added
+    /// after its values.
+    ///
+    /// Note that the argument of an unknown flag can only be collected
+    /// with it when it is given as `--flag=value`. In `--flag value`, the
+    /// `value` is a positional argument.
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let vals = Docopt::new("Usage: wrap [-v] <cmd>")
+    ///                   .unwrap()
+    ///                   .collect_unknown("<passthrough>")
+    ///                   .argv(&["wrap", "--jobs=4", "-v", "build", "-x"])
+    ///                   .parse()
+    ///                   .unwrap();
+    /// assert!(vals.get_bool("-v"));
+    /// assert_eq!(vals.get_str("<cmd>"), "build");
+    /// assert_eq!(vals.get_vec("<passthrough>"), vec!["--jobs=4", "-x"]);
+    /// ```
+    pub fn collect_unknown(mut self, into_key: &str) -> Docopt {
+        self.collect_unknown = Some(into_key.into());
+        self
+    }
+
+    /// Set the arguments to use when none are given.
+    ///
+    /// If the argv has nothing after the program name, then `extra` is used
+    /// in its place. This makes it easy to have a default command, e.g.,
+    /// `default_argv(vec!["status".to_string()])` makes `prog` behave like
+    /// `prog status`. Unlike `argv`, `extra` must *not* include the program
+    /// name.
+    ///
+    /// This works with both the argv set by `argv` and the argv of the
+    /// current process.
+    pub fn default_argv(mut self, extra: Vec<String>) -> Docopt {
+        self.default_argv = Some(extra);
+        self
+    }
+
+    /// Set the argv to be used for Docopt parsing from a single string.
+    ///
+    /// The string is split into arguments with `shellwords`, and the result
+    /// is used as in `argv`. So the first word must be the program name.
+    /// This is mostly useful for writing tests:
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let vals = Docopt::new("Usage: prog <name>")
+    ///                   .and_then(|d| d.argv_str("prog 'John Doe'"))
+    ///                   .and_then(|d| d.parse())
+    ///                   .unwrap();
+    /// assert_eq!(vals.get_str("<name>"), "John Doe");
+    /// ```
+    ///
+    /// If the string can't be split, then a `Split` error is returned.
+    pub fn argv_str(self, argv: &str) -> Result<Docopt, Error> {
+        shellwords(argv).map(|words| self.argv(words))
+    }
+
+    /// Splits `line` into arguments and parses them.
+    ///
+    /// This is meant for interactive programs like a REPL, where each line
+    /// typed by the user is parsed with the same `Docopt`. The Docopt
+    /// string is only parsed once, by `new`, so this is cheap. Unlike
+    /// `argv_str`, the line does *not* start with the program name.
+    ///
+    /// The line is split with `shellwords`, so quoted strings like `"a b"`
+    /// or `'c d'` are single arguments:
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let dopt = Docopt::new("Usage: prog add <name> <url>").unwrap();
+    /// let vals = dopt.parse_line("add 'my site' http://a.b").unwrap();
+    /// assert_eq!(vals.get_str("<name>"), "my site");
+    /// assert!(dopt.parse_line("add 'my site").is_err());
+    /// ```
+    ///
+    /// If the line can't be split, then a `Split` error is returned.
+    /// Otherwise, errors are as in `parse`.
+    pub fn parse_line(&self, line: &str) -> Result<ArgvMap, Error> {
+        let words = try!(shellwords(line));
+        let mut dopt = self.clone();
+        dopt.argv = Some(words);
+        dopt.parse()
+    }
+
+    /// Parses argv with `key` as a trailing variable argument.
+    ///
+    /// `key` must be a repeated positional argument, like `<args>` in
+    /// `prog exec <cmd> <args>...`. Once the positional arguments and
+    /// commands before it in a usage pattern have been given, every
+    /// remaining argument is a value of `key`, even one that looks like a
+    /// flag. So there's no need for `--` to pass flags on to `<cmd>`:
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let dopt = Docopt::new("Usage: prog [-v] exec <cmd> <args>...")
+    ///                   .unwrap()
+    ///                   .argv(&["prog", "-v", "exec", "ls", "-l", "-v"]);
+    /// let vals = dopt.parse_with_trailing_var_arg("<args>").unwrap();
+    /// assert!(vals.get_bool("-v"));
+    /// assert_eq!(vals.get_vec("<args>"), vec!["-l", "-v"]);
+    /// ```
+    ///
+    /// Flags before `key` are parsed as usual. The positional arguments
+    /// before `key` should not be optional, since all of them must be
+    /// given for the rest of argv to be captured.
+    ///
+    /// Note that this is decided while argv is split into flags and
+    /// positional arguments, before it's matched against the usage
+    /// patterns. As soon as the positional arguments given so far are the
+    /// ones before `key` in *any* usage pattern, the rest of argv is taken
+    /// as positional arguments, even if that pattern doesn't match in the
+    /// end.
+    ///
+    /// If `key` isn't a repeated positional argument, then a `Usage` error
+    /// is returned. Otherwise, errors are as in `parse`.
+    pub fn parse_with_trailing_var_arg(&self, key: &str)
+                                      -> Result<ArgvMap, Error> {
+        let atom = self.p.descs.resolve(&Atom::new(key));
+        let repeated = match (&atom, self.p.descs.find(&atom)) {
+            (&Positional(_), Some(opts)) => opts.repeats,
+            _ => false,
+        };
+        if !repeated {
+            return Err(Usage(format!(
+                "'{}' is not a repeated positional argument.", key)));
+        }
+        let mut dopt = self.clone();
+        dopt.trailing_var_arg = self.p.positionals_before(&atom);
+        dopt.parse()
+    }
+
+    /// Checks each value of `key` with `check` when parsing.
+    ///
+    /// After argv is matched, `check` is called with the value of `key`, or
+    /// with each of its values if it's repeated. (This includes a value
+    /// from `[default: ...]`.) Switches, counts and missing values aren't
+    /// checked. If `check` returns an error message, then parsing fails
+    /// with an `Argv` error naming the key and the value, followed by the
+    /// usage like other argv errors.
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let doc = "Usage: serve --port=<n>";
+    /// let dopt = Docopt::new(doc).and_then(|d| d.validate("--port", |v| {
+    ///     match v.parse::<u16>() {
+    ///         Ok(n) if n > 0 => Ok(()),
+    ///         _ => Err("must be a number from 1 to 65535".to_string()),
+    ///     }
+    /// })).unwrap();
+    /// assert!(dopt.argv(&["serve", "--port=0"]).parse().is_err());
+    /// ```
+    ///
+    /// If `key` isn't in the Docopt string, then a `Usage` error is
+    /// returned, since that's almost certainly a typo.
+    ///
+    /// When a flag only accepts a fixed set of values, it's simpler to list
+    /// them in its option description with `[choices: ...]`, e.g.,
+    /// `--format=<fmt>  Output format [choices: json yaml toml]`. Any other
+    /// value is then an `Argv` error listing the choices.
+    pub fn validate<F>(mut self, key: &str, check: F) -> Result<Docopt, Error>
+            where F: Fn(&str) -> Result<(), String> + Send + Sync + 'static {
+        let atom = Atom::new(key);
+        if !self.p.descs.contains_key(&atom) {
+            return Err(Usage(format!(
+                "Cannot add a validator for unknown key '{}'.", key)));
+        }
+        self.validators.push(Validator {
+            key: self.p.descs.resolve(&atom).to_string(),
+            check: Arc::new(check),
+        });
+        Ok(self)
+    }
+
+    /// Limits how many times `key` may be given when it's counted.
+    ///
+    /// This applies to flags and commands that may be repeated and don't
+    /// take an argument, like `-v...`. If one is given more than `max`
+    /// times, then parsing fails with an `Argv` error naming it and the
+    /// limit, followed by the usage like other argv errors. Setting a limit
+    /// for the same key again replaces it.
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let dopt = Docopt::new("Usage: prog [-v...]").unwrap()
+    ///                   .max_repeats("-v", 3);
+    /// assert_eq!(dopt.argv(&["prog", "-vvv"]).parse().unwrap()
+    ///                .get_count("-v"), 3);
+    /// assert!(dopt.argv(&["prog", "-vvvv"]).parse().is_err());
+    /// ```
+    pub fn max_repeats(mut self, key: &str, max: usize) -> Docopt {
+        self.max_repeats.retain(|&(ref k, _)| k != key);
+        self.max_repeats.push((key.to_string(), max));
+        self
+    }
+
+    /// Lists the possible values of `key` with `candidates` when completing.
+    ///
+    /// This is for values that are only known at runtime, like the names of
+    /// the profiles in a config file. When at least one key has a source of
+    /// candidates, parsing checks for a hidden flag: if argv is `prog
+    /// --complete KEY [PREFIX]`, then parsing stops with a `Complete` error
+    /// holding the candidates for `KEY` that start with `PREFIX`, one per
+    /// line. Like `Help`, `exit` prints it to stdout and exits successfully.
+    /// A key without a source of candidates has none.
+    ///
+    /// A shell completion script can then ask the program itself for the
+    /// values of the flag before the cursor. The bash functions in
+    /// `completions/docopt-wordlist.bash` do this for the programs listed
+    /// in `$DOCOPT_WORDLIST_COMPLETE`.
+    ///
+    /// ```rust
+    /// use docopt::{Docopt, Error};
+    ///
+    /// let doc = "Usage: deploy --profile=<name>";
+    /// let dopt = Docopt::new(doc).and_then(|d| {
+    ///     d.complete_values("--profile", || {
+    ///         vec!["dev".to_string(), "prod".to_string()]
+    ///     })
+    /// }).unwrap();
+    /// match dopt.argv(&["deploy", "--complete", "--profile", "p"]).parse() {
+    ///     Err(Error::Complete(s)) => assert_eq!(s, "prod"),
+    ///     r => panic!("{:?}", r),
+    /// }
+    /// ```
+    ///
+    /// If `key` isn't in the Docopt string, then a `Usage` error is
+    /// returned, since that's almost certainly a typo.
+    pub fn complete_values<F>(mut self, key: &str, candidates: F)
+                             -> Result<Docopt, Error>
+            where F: Fn() -> Vec<String> + Send + Sync + 'static {
+        let atom = Atom::new(key);
+        if !self.p.descs.contains_key(&atom) {
+            return Err(Usage(format!(
+                "Cannot add completions for unknown key '{}'.", key)));
+        }
+        self.completers.push(Completer {
+            key: self.p.descs.resolve(&atom).to_string(),
+            candidates: Arc::new(candidates),
+        });
+        Ok(self)
+    }
+
+    /// Hands an unknown command to `handle` when argv doesn't match.
+    ///
+    /// If argv doesn't match any usage pattern, and its first positional
+    /// argument isn't one of the commands in the Docopt string, then
+    /// `handle` is called with it and the arguments that follow it. This
+    /// makes it possible to dispatch to external commands, the way `git foo`
+    /// runs `git-foo`. Only the flags before the command are parsed.
+    /// Everything after it is left to `handle`, so unknown flags there are
+    /// not an error.
+    ///
+    /// If `handle` returns `None`, the command is left unhandled and the
+    /// usual error is returned. If it returns `Some(Ok(()))`, then parsing
+    /// stops with a `Dispatched` error, which `exit` treats as success
+    /// without printing anything. If it returns `Some(Err(err))`, then `err`
+    /// is returned as is.
+    ///
+    /// ```rust
+    /// use docopt::{Docopt, Error};
+    ///
+    /// let dopt = Docopt::new("Usage: git [-v] (add | commit) <args>...")
+    ///                   .unwrap()
+    ///                   .on_unknown_command(|name, args| match name {
+    ///                       "foo" => {
+    ///                           // e.g., run `git-foo` with `args`
+    ///                           assert_eq!(args, ["--bar"]);
+    ///                           Some(Ok(()))
+    ///                       }
+    ///                       _ => None,
+    ///                   });
+    /// match dopt.argv(&["git", "-v", "foo", "--bar"]).parse() {
+    ///     Err(Error::Dispatched(name)) => assert_eq!(name, "foo"),
+    ///     r => panic!("expected dispatch, got {:?}", r),
+    /// }
+    /// assert!(dopt.argv(&["git", "bar"]).parse().unwrap_err().fatal());
+    /// ```
+    pub fn on_unknown_command<F>(mut self, handle: F) -> Docopt
+            where F: Fn(&str, &[String]) -> Option<Result<(), Error>>
+                     + Send + Sync + 'static {
+        self.unknown_command = Some(UnknownCommand(Arc::new(handle)));
         self
     }
 
@@ -264,6 +1110,131 @@ impl Docopt {
         self
     }
 
+    /// Enables `+x` and `-x` toggles for short flags.
+    ///
+    /// When enabled, a short flag that takes no argument and isn't repeated
+    /// is set to `true` by `+x` and to `false` by `-x`. If a toggle is given
+    /// more than once, the last one wins. Toggles may be stacked, as in
+    /// `+xy`. Other flags behave as usual, and an argument like `+x` is
+    /// positional when this is disabled.
+    ///
+    /// Since `-x` now also counts as giving the flag, a usage pattern that
+    /// requires `-x` is satisfied by either form.
+    pub fn plus_minus_toggles(mut self, yes: bool) -> Docopt {
+        self.plus_minus_toggles = yes;
+        self
+    }
+
+    /// Rejects a short flag that takes an argument in the middle of a
+    /// stack of short flags.
+    ///
+    /// By default, the rest of a stack after a flag that takes an argument
+    /// is its argument, so `-oab` gives `-o` the value `ab`, as `getopt`
+    /// does. When enabled, this is an `Argv` error if the rest of the stack
+    /// consists only of other short flags, since it's likely that the flags
+    /// were meant and the argument was forgotten. An attached value that
+    /// isn't made of flags, as in `-ovalue`, is still allowed, as is `-abo
+    /// value`.
+    pub fn strict_short_stacks(mut self, yes: bool) -> Docopt {
+        self.strict_short_stacks = yes;
+        self
+    }
+
+    /// Sets the characters that may join a long flag to its argument.
+    ///
+    /// `=` always works. With `value_separators(&['=', ':'])`, the
+    /// argument `--output:C:\tmp` is the same as `--output=C:\tmp`. Only
+    /// the first separator counts, so `--output=a:b` is left as it is. A
+    /// flag is only split on another separator if the Docopt string has a
+    /// long flag by that name that takes an argument.
+    ///
+    /// This is for porting programs whose users are used to other
+    /// conventions, such as those on Windows. See also `slash_flags`.
+    pub fn value_separators(mut self, seps: &[char]) -> Docopt {
+        self.value_separators =
+            seps.iter().cloned().filter(|&c| c != '=').collect();
+        self
+    }
+
+    /// Enables flags written with a leading `/`, as on Windows.
+    ///
+    /// When enabled, `/name` is translated to `--name`, and `/name:value`
+    /// or `/name=value` to `--name=value`, but only if the Docopt string
+    /// has a long flag named `--name`. Anything else that starts with `/`
+    /// is left as it is, so absolute paths like `/usr/bin` still work as
+    /// positional arguments (as long as they don't name a flag).
+    ///
+    /// Arguments after `--`, and values of flags given as separate
+    /// arguments (as in `--output /quiet`), are never translated.
+    pub fn slash_flags(mut self, yes: bool) -> Docopt {
+        self.slash_flags = yes;
+        self
+    }
+
+    /// Matches the given flags case insensitively.
+    ///
+    /// Each flag is written as in the Docopt string, e.g., `--verbose` or
+    /// `-v`. A flag in argv that isn't known exactly is matched against
+    /// these flags ignoring case, so `--Verbose` and `--VERBOSE` both give
+    /// `--verbose`. All other flags stay case sensitive, and a flag that is
+    /// known exactly (like `-V` when both `-v` and `-V` exist) always wins.
+    pub fn ignore_case_for<I, S>(mut self, flags: I) -> Docopt
+            where I: IntoIterator<Item=S>, S: AsRef<str> {
+        self.ignore_case = flags.into_iter()
+                                .map(|s| Atom::new(s.as_ref()))
+                                .collect();
+        self
+    }
+
+    /// Accepts the flag `old` in argv as another name for the flag `new`.
+    ///
+    /// This is for renaming a flag without breaking scripts that use the
+    /// old name. Only `new` needs to be in the Docopt string (so `--help`
+    /// only shows the new name), and giving `old` sets the value of `new`
+    /// as if `new` had been given instead:
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let dopt = Docopt::new("Usage: prog [--new-name=<n>]")
+    ///                   .and_then(|d| d.alias("--old-name", "--new-name"))
+    ///                   .unwrap();
+    /// let vals = dopt.argv(&["prog", "--old-name=x"]).parse().unwrap();
+    /// assert_eq!(vals.get_str("--new-name"), "x");
+    /// ```
+    ///
+    /// Giving both `old` and `new` is an `Argv` error. Nothing is printed
+    /// when `old` is used, so it's up to the caller to warn about it if
+    /// desired.
+    ///
+    /// If `old` isn't a flag or is already in the Docopt string, or if
+    /// `new` isn't a flag in the Docopt string, then a `Usage` error is
+    /// returned.
+    pub fn alias(mut self, old: &str, new: &str) -> Result<Docopt, Error> {
+        let (old_atom, new_atom) = (Atom::new(old), Atom::new(new));
+        match (&old_atom, &new_atom) {
+            (&Short(_), &Short(_)) | (&Short(_), &Long(_))
+            | (&Long(_), &Short(_)) | (&Long(_), &Long(_)) => {}
+            _ => {
+                return Err(Usage(format!(
+                    "Cannot alias '{}' to '{}', since only flags can have \
+                     aliases.", old, new)));
+            }
+        }
+        if self.p.descs.contains_key(&old_atom) {
+            return Err(Usage(format!(
+                "Cannot alias '{}', since it's already in the Docopt string.",
+                old)));
+        }
+        if !self.p.descs.contains_key(&new_atom) {
+            return Err(Usage(format!(
+                "Cannot alias '{}' to unknown flag '{}'.", old, new)));
+        }
+        let new_atom = self.p.descs.resolve(&new_atom);
+        self.aliases.push((old_atom, new_atom));
+        Ok(self)
+    }
+
     /// Enables automatic handling of `--help`.
     ///
     /// When this is enabled and `--help` appears anywhere in the arguments,
@@ -271,7 +1242,8 @@ impl Docopt {
     /// method on the error value to conveniently quit the program (which will
     /// print the full usage string to stdout).
     ///
-    /// Note that for this to work, `--help` must be a valid pattern.
+    /// Note that for this to work, `--help` must be a valid pattern. If
+    /// the Docopt string gives `--help` an argument, then it is left alone.
     ///
     /// When disabled, there is no special handling of `--help`.
     pub fn help(mut self, yes: bool) -> Docopt {
@@ -279,6 +1251,19 @@ impl Docopt {
         self
     }
 
+    /// Sets the flags that trigger automatic help. The default is
+    /// `--help`.
+    ///
+    /// A flag only triggers help if the Docopt string uses it for help,
+    /// i.e., it takes no argument and it isn't a synonym for a flag other
+    /// than `--help` or one of `flags`. So with `help_flags(&["-h",
+    /// "--help"])`, a program with `-h, --host=<host>` still gets the host
+    /// from `-h`, while `--help` shows help.
+    pub fn help_flags(mut self, flags: &[&str]) -> Docopt {
+        self.help_flags = flags.iter().map(|&f| f.into()).collect();
+        self
+    }
+
     /// Enables automatic handling of `--version`.
     ///
     /// When this is enabled and `--version` appears anywhere in the arguments,
@@ -287,26 +1272,424 @@ impl Docopt {
     /// convenient quit the program (which will print the version to stdout).
     ///
     /// When disabled (a `None` value), there is no special handling of
-    /// `--version`.
+    /// `--version`. Nor is there if the Docopt string gives `--version` an
+    /// argument.
     pub fn version(mut self, version: Option<String>) -> Docopt {
         self.version = version;
         self
     }
 
+    /// Sets how much of the Docopt string is shown when argv fails to parse.
+    ///
+    /// By default, argv errors are followed by the usage patterns. With
+    /// `HelpStyle::Full`, the entire Docopt string is shown instead. With
+    /// `HelpStyle::UsageOnly`, only the `Usage:` section is shown, followed
+    /// by a line like `Try 'prog --help' for more information.`
+    ///
+    /// In all cases, `--help` continues to show the full Docopt string.
+    pub fn help_style(mut self, style: HelpStyle) -> Docopt {
+        self.help_style = Some(style);
+        self
+    }
+
+    /// Sets how option descriptions are laid out in the help message shown
+    /// for `--help`.
+    ///
+    /// With `HelpLayout::RequiredFirst`, the options that must be given
+    /// for any usage pattern to match are moved to the top of each block
+    /// of option descriptions, so that users see the essential flags
+    /// first. An option description moves along with the lines that
+    /// continue it. Other text is left in place.
+    pub fn help_layout(mut self, layout: HelpLayout) -> Docopt {
+        self.help_layout = layout;
+        self.help_doc = match layout {
+            HelpLayout::AsWritten => None,
+            HelpLayout::RequiredFirst => Some(required_first_help(&self.p)),
+        };
+        self
+    }
+
+    /// Sets the maximum number of usage patterns shown with
+    /// `HelpStyle::Elided`.
+    ///
+    /// The patterns that best match the given argv are shown, in the order
+    /// they appear in the Docopt string, followed by a line saying how many
+    /// were left out. The default is `4`.
+    pub fn max_usage_alternatives(mut self, n: usize) -> Docopt {
+        self.max_usage_alternatives = n;
+        self
+    }
+
+    /// Colors the help message shown for `--help`.
+    ///
+    /// The section headers (like `Usage:` and `Options:`) and the flags of
+    /// each option description are colored with ANSI escape sequences. This
+    /// only happens when stdout is a terminal and the `NO_COLOR`
+    /// environment variable isn't set. Error messages are never colored.
+    pub fn colored_help(mut self) -> Docopt {
+        self.colored_help = true;
+        self
+    }
+
+    /// Strips terminal escape sequences and control characters from the
+    /// Docopt string before it is shown in help or error messages.
+    ///
+    /// This is useful when the usage string isn't fully trusted, e.g., when
+    /// it was copied from a terminal or assembled from external input. Tabs
+    /// and newlines are kept.
+    pub fn sanitize_help(mut self, yes: bool) -> Docopt {
+        self.sanitize_help = yes;
+        self
+    }
+
+    /// Returns the number of positional arguments required by the most
+    /// permissive and the least permissive usage patterns, as `(min, max)`.
+    ///
+    /// Optional positional arguments aren't counted, and a repeated one
+    /// (e.g., `<file>...`) counts once. Commands aren't positional
+    /// arguments. So, for `prog <a> [<b>]` and `prog <c> <d>...`, this
+    /// returns `(1, 2)`. Alternatives within a pattern count like separate
+    /// patterns, so `prog (<a> | <b> <c>)` also returns `(1, 2)`. Any argv
+    /// with fewer than `min` positional arguments is certain not to match.
+    pub fn required_positional_count(&self) -> (usize, usize) {
+        self.p.required_positional_count()
+    }
+
+    /// Returns just the `Usage:` section of the Docopt string.
+    ///
+    /// This is the same text that follows argv errors by default. It does
+    /// not include option descriptions or any other text, so it is useful
+    /// as a compact hint.
+    pub fn usage(&self) -> &str {
+        self.p.usage.trim()
+    }
+
+    /// Returns the required options, positional arguments and commands that
+    /// are missing from `argv`.
+    ///
+    /// `argv` is matched as far as possible against the usage pattern that
+    /// it comes closest to, and the atoms of that pattern that aren't inside
+    /// `[...]` and weren't given are returned in the order they appear.
+    /// This is useful for prompting for the rest of a partial command line.
+    ///
+    /// Like with `argv`, the first element of `argv` is the program name.
+    /// If `argv` can't be parsed at all (e.g., it has an unknown flag), then
+    /// the result is as if only the program name was given.
+    pub fn missing_required<S: AsRef<str>>(&self, argv: &[S]) -> Vec<Atom> {
+        let argv = self.compat_argv(
+            argv.iter().skip(1).map(|s| s.as_ref().to_owned()).collect());
+        let parse = |argv| self.p.parse_argv(argv, &self.argv_settings());
+        match parse(argv) {
+            Ok(parsed) => self.p.missing_required(&parsed),
+            Err(_) => match parse(vec![]) {
+                Ok(parsed) => self.p.missing_required(&parsed),
+                Err(_) => vec![],
+            },
+        }
+    }
+
+    /// Returns the Docopt string exactly as it was given to `new`.
+    ///
+    /// Before it's parsed, a leading byte order mark is removed and `\r\n`
+    /// line endings are replaced by `\n`, but only in an internal copy, so
+    /// this is always byte for byte the same as the original. (Use
+    /// `help_string` for the text that's actually shown.)
+    pub fn source_text(&self) -> &str {
+        &self.p.source
+    }
+
+    /// Returns the full help message shown for `--help`.
+    ///
+    /// This is the Docopt string with surrounding whitespace removed (and
+    /// with the line endings and byte order mark handled as described in
+    /// `source_text`), laid out as set with `help_layout`, before any
+    /// coloring or sanitizing set with `colored_help` or `sanitize_help`.
+    pub fn help_string(&self) -> &str {
+        match (self.help_layout, &self.help_doc) {
+            (HelpLayout::RequiredFirst, &Some(ref doc)) => doc,
+            _ => self.p.full_doc.trim(),
+        }
+    }
+
+    /// Returns the version message shown for `--version`, if one was set
+    /// with `version`.
+    pub fn version_string(&self) -> Option<&str> {
+        self.version.as_ref().map(|v| &**v)
+    }
+
+    /// Returns every flag in the Docopt string, sorted.
+    ///
+    /// Both spellings of flags declared together (e.g., `-q, --quiet`) are
+    /// included.
+    pub fn flags(&self) -> Vec<String> {
+        let descs = &self.p.descs;
+        let mut flags: Vec<String> =
+            descs.keys().chain(descs.synonyms().map(|(from, _)| from))
+                 .filter(|a| match **a {
+                     Short(_) | Long(_) => true,
+                     _ => false,
+                 })
+                 .map(|a| a.to_string())
+                 .collect();
+        flags.sort();
+        flags
+    }
+
+    /// Returns every positional argument in the Docopt string, sorted.
+    ///
+    /// Commands are not included.
+    pub fn positionals(&self) -> Vec<String> {
+        let mut args: Vec<String> =
+            self.p.descs.keys()
+                .filter(|a| match **a { Positional(_) => true, _ => false })
+                .map(|a| a.to_string())
+                .collect();
+        args.sort();
+        args
+    }
+
+    /// Renders a man page in roff format.
+    ///
+    /// The page has a NAME section made from the first line of the Docopt
+    /// string, a SYNOPSIS section with each usage pattern, a DESCRIPTION
+    /// section with any other text before the usage patterns, and an
+    /// OPTIONS section with each option description (including its
+    /// default value). The title, section, version and date come from
+    /// `meta`.
+    ///
+    /// This makes it easy to support a hidden `--man` flag that prints the
+    /// page, which can then be viewed with `man -l -`.
+    pub fn man_page(&self, meta: &ManMeta) -> String {
+        man::man_page(&self.p, meta)
+    }
+
     #[doc(hidden)]
     // Exposed for use in `docopt_macros`.
     pub fn parser(&self) -> &Parser {
         &self.p
     }
 
-    fn err_with_usage(&self, e: Error) -> Error {
-        WithProgramUsage(
-            Box::new(e), self.p.usage.trim().into())
+    // The settings for matching argv with `Parser::parse_argv`.
+    fn argv_settings(&self) -> ArgvSettings {
+        ArgvSettings {
+            options_first: self.options_first,
+            plus_minus_toggles: self.plus_minus_toggles,
+            strict_short_stacks: self.strict_short_stacks,
+            ignore_case: &self.ignore_case,
+            aliases: &self.aliases,
+            collect_unknown: self.collect_unknown.is_some(),
+            trailing: &self.trailing_var_arg,
+        }
+    }
+
+    // Translates the flags in `argv` written with the conventions enabled
+    // by `value_separators` and `slash_flags` to the usual ones. Arguments
+    // after `--` or in a trailing variable argument are left as they are.
+    fn compat_argv(&self, argv: Vec<String>) -> Vec<String> {
+        if self.value_separators.is_empty() && !self.slash_flags {
+            return argv
+        }
+        let long_flag = |name: &str| self.p.descs.find(&Long(name.into()));
+        let mut translated = Vec::with_capacity(argv.len());
+        let mut argv = argv.into_iter();
+        let mut takes_value = false;
+        let mut positional = vec![];
+        while let Some(arg) = argv.next() {
+            if takes_value {
+                takes_value = false;
+                translated.push(arg);
+                continue;
+            }
+            if arg == "--" || self.in_trailing_var_arg(&positional) {
+                translated.push(arg);
+                translated.extend(argv);
+                break;
+            }
+            let arg = if self.slash_flags && arg.starts_with('/') {
+                let name = arg[1..].split(&[':', '='][..]).next().unwrap();
+                match long_flag(name) {
+                    None => arg,
+                    Some(_) if name.len() + 1 == arg.len() => {
+                        format!("--{}", name)
+                    }
+                    Some(_) => format!("--{}={}", name, &arg[name.len() + 2..]),
+                }
+            } else if arg.starts_with("--") {
+                let sep = arg[2..].find(|c| {
+                    c == '=' || self.value_separators.contains(&c)
+                }).map(|i| i + 2);
+                let sep = sep.map(|i| (i, arg[i..].chars().next().unwrap()));
+                match sep {
+                    Some((i, c)) if c != '=' => {
+                        match long_flag(&arg[2..i]) {
+                            Some(opts) if opts.arg.has_arg() => {
+                                let value = &arg[i + c.len_utf8()..];
+                                format!("{}={}", &arg[..i], value)
+                            }
+                            _ => arg,
+                        }
+                    }
+                    _ => arg,
+                }
+            } else {
+                arg
+            };
+            if !arg.starts_with('-') && self.options_first {
+                translated.push(arg);
+                translated.extend(argv);
+                break;
+            }
+            // A flag that takes an argument, given without it, is followed
+            // by its value. In a stack of short flags, only the last one
+            // can be given without it, since the rest of the stack is the
+            // value of any other.
+            takes_value = if arg.starts_with("--") {
+                !arg.contains('=')
+                && long_flag(&arg[2..]).map_or(false, |o| o.arg.requires_arg())
+            } else if arg.starts_with('-') {
+                let shorts: Vec<char> = arg[1..].chars().collect();
+                shorts.iter().position(|&c| {
+                    self.p.descs.find(&Short(c))
+                                .map_or(false, |o| o.arg.requires_arg())
+                }).map_or(false, |i| i + 1 == shorts.len())
+            } else {
+                false
+            };
+            if !arg.starts_with('-') {
+                positional.push(arg.clone());
+            }
+            translated.push(arg);
+        }
+        translated
+    }
+
+    // Whether the positional arguments given so far are the ones before
+    // the key set with `parse_with_trailing_var_arg`. This mirrors the
+    // check the argv tokenizer does.
+    fn in_trailing_var_arg(&self, positional: &[String]) -> bool {
+        self.trailing_var_arg.iter().any(|before| {
+            before.len() == positional.len()
+            && before.iter().zip(positional).all(|(a, s)| match *a {
+                Atom::Command(ref c) => c == s,
+                _ => true,
+            })
+        })
+    }
+
+    // Calls the function set with `on_unknown_command` if the first
+    // positional argument in `argv` isn't a known command. Returns `None`
+    // if it doesn't handle it, or if the usage has no commands at all.
+    fn unknown_command(&self, argv: &[String]) -> Option<Error> {
+        let handle = match self.unknown_command {
+            None => return None,
+            Some(UnknownCommand(ref handle)) => handle,
+        };
+        let has_commands = self.p.descs.keys().any(|atom| match *atom {
+            Atom::Command(ref name) => name != "--",
+            _ => false,
+        });
+        if !has_commands {
+            return None
+        }
+        let settings = ArgvSettings {
+            options_first: true,
+            collect_unknown: false,
+            trailing: &[],
+            ..self.argv_settings()
+        };
+        let parsed = match self.p.parse_argv(argv.to_vec(), &settings) {
+            Err(_) => return None,
+            Ok(parsed) => parsed,
+        };
+        let (name, rest) = match parsed.first_positional() {
+            None => return None,
+            Some(found) => found,
+        };
+        if self.p.descs.contains_key(&Atom::Command(name.to_string())) {
+            return None
+        }
+        match handle(name, rest) {
+            None => None,
+            Some(Ok(())) => Some(Dispatched(name.to_string())),
+            Some(Err(err)) => Some(err),
+        }
+    }
+
+    // Lists the candidates for `--complete KEY [PREFIX]`.
+    fn complete(&self, args: &[String]) -> Error {
+        let key = match args.first() {
+            None => return Complete(String::new()),
+            Some(key) => self.p.descs.resolve(&Atom::new(key)).to_string(),
+        };
+        let prefix = args.get(1).map_or("", |s| &**s);
+        let mut found = vec![];
+        for completer in self.completers.iter().filter(|c| c.key == key) {
+            for candidate in (completer.candidates)() {
+                if candidate.starts_with(prefix) {
+                    found.push(candidate);
+                }
+            }
+        }
+        Complete(found.join("\n"))
+    }
+
+    fn err_with_usage(&self, e: Error, argv: &[String]) -> Error {
+        match self.help_style {
+            None => self.err_with_text(e, self.p.usage.trim().into()),
+            Some(HelpStyle::Full) => self.err_with_full_doc(e),
+            Some(HelpStyle::UsageOnly) => {
+                let usage = format!(
+                    "{}\n\nTry '{} --help' for more information.",
+                    self.p.usage_block, self.p.program);
+                self.err_with_text(e, usage)
+            }
+            Some(HelpStyle::Elided) => {
+                let usage = format!(
+                    "{}\n\nTry '{} --help' for more information.",
+                    self.p.elided_usage(argv, self.max_usage_alternatives),
+                    self.p.program);
+                self.err_with_text(e, usage)
+            }
+        }
+    }
+
+    // Whether one of the help flags was given. A help flag that's a synonym
+    // for some other flag, like `-h` in `-h, --host=<host>`, doesn't count.
+    fn wants_help(&self, vals: &ArgvMap) -> bool {
+        self.help_flags.iter().any(|f| {
+            match vals.canonical(f) {
+                None => false,
+                Some(k) => {
+                    (k == "--help" || self.help_flags.iter().any(|h| h == k))
+                    && given_switch(vals, k)
+                }
+            }
+        })
+    }
+
+    fn err_with_help(&self) -> Error {
+        let no_color = ::std::env::var_os("NO_COLOR");
+        if !self.colored_help
+                || !color::enabled(color::stdout_is_tty(), no_color) {
+            return self.err_with_text(Help, self.help_string().into());
+        }
+        let mut doc = self.help_string().to_string();
+        if self.sanitize_help {
+            doc = strip_control(&doc);
+        }
+        WithProgramUsage(Box::new(Help), color::colorize(&doc))
     }
 
     fn err_with_full_doc(&self, e: Error) -> Error {
-        WithProgramUsage(
-            Box::new(e), self.p.full_doc.trim().into())
+        self.err_with_text(e, self.p.full_doc.trim().into())
+    }
+
+    fn err_with_text(&self, e: Error, text: String) -> Error {
+        if self.sanitize_help {
+            WithProgramUsage(Box::new(e), strip_control(&text))
+        } else {
+            WithProgramUsage(Box::new(e), text)
+        }
     }
 
     fn get_argv() -> Vec<String> {
@@ -325,9 +1708,41 @@ impl Docopt {
 pub struct ArgvMap {
     #[doc(hidden)]
     pub map: SynonymMap<String, Value>,
+    // The keys in the order they appear in the usage patterns.
+    key_order: Vec<String>,
+    // The flags in the order they appear in argv.
+    flag_order: Vec<Atom>,
+    // The positional arguments and commands in the order they appear in
+    // argv.
+    positional_order: Vec<String>,
 }
 
 impl ArgvMap {
+    /// Builds a map from `(key, value)` pairs, as if they were the result
+    /// of parsing argv.
+    ///
+    /// This is useful for testing code that consumes an `ArgvMap` without
+    /// going through a usage string. Keys are in the traditional Docopt
+    /// format, e.g., `<arg>` or `--flag`, and are used in the order given
+    /// (which `to_json` preserves). No synonyms are added, and a later pair
+    /// with the same key replaces an earlier one.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use docopt::{ArgvMap, Value};
+    ///
+    /// let vals = ArgvMap::from_pairs(vec![
+    ///     ("--verbose", Value::Switch(true)),
+    ///     ("<file>", Value::Plain(Some("a.txt".to_string()))),
+    /// ]);
+    /// assert!(vals.get_bool("--verbose"));
+    /// assert_eq!(vals.get_str("<file>"), "a.txt");
+    /// ```
+    pub fn from_pairs(pairs: Vec<(&str, Value)>) -> ArgvMap {
+        pairs.into_iter().collect()
+    }
+
     /// Tries to decode the map of values into a struct.
     ///
     /// This method should always be called to decode a `ArgvMap` into
@@ -337,7 +1752,18 @@ impl ArgvMap {
     /// three prefixes: `flag_`, `arg_` and `cmd_` which respectively
     /// correspond to short/long flags, positional arguments and commands.
     ///
-    /// If a Docopt item has a `-` in its name, then it is converted to an `_`.
+    /// If a Docopt item has a `-` or a `.` in its name, then it is converted
+    /// to an `_`.
+    ///
+    /// A field named just `cmd` may be an enum. It is decoded to the variant
+    /// whose name matches the command that was given, ignoring case and
+    /// underscores (so `SelfUpdate` matches `self-update`). Exactly one of
+    /// the variants must have been given, which is easy to guarantee with a
+    /// pattern like `(build | test | run)`.
+    ///
+    /// Similarly, an enum wrapped in `OneOf` is decoded from whichever one
+    /// of a group of mutually exclusive flags, arguments or commands was
+    /// given. See `OneOf` for details.
     ///
     /// # Example
     ///
@@ -377,8 +1803,31 @@ impl ArgvMap {
     ///
     /// In this example, only the `bool` type was used, but any type satisfying
     /// the `Decodable` trait is valid.
+    ///
+    /// A pair, like `(String, i64)`, is decoded from an argument of the form
+    /// `key=value`, which is split on the first `=`. So a repeated option
+    /// like `-D <define>` can be decoded into a `Vec<(String, i64)>`.
+    ///
+    /// The same option can also be decoded into a map, like
+    /// `HashMap<String, String>` or `BTreeMap<String, i64>`. Then an
+    /// argument without an `=` maps its key to an empty string, and a key
+    /// given more than once is a `Decode` error (see
+    /// `Docopt::duplicate_keys`).
     pub fn decode<T: Decodable>(self) -> Result<T, Error> {
-        Decodable::decode(&mut Decoder { vals: self, stack: vec!() })
+        Decodable::decode(&mut Decoder {
+            vals: self,
+            stack: vec!(),
+            bytes_encodings: vec![],
+            duplicate_keys: DuplicateKeys::Error,
+            thousands_separator: None,
+            defaults_only: false,
+            group: false,
+            group_seen: false,
+            optional_group: false,
+            group_absent: false,
+            last_key: None,
+            last_missing: false,
+        })
     }
 
     /// Finds the value corresponding to `key` and calls `as_bool()` on it.
@@ -393,6 +1842,18 @@ impl ArgvMap {
         self.find(key).map_or(0, |v| v.as_count())
     }
 
+    /// Returns the number of values captured for `key`.
+    ///
+    /// This is the length of a list, the number of occurrences of a counted
+    /// flag, `1` for a present plain value or a `true` switch, and `0`
+    /// otherwise. If the key does not exist, `0` is returned.
+    ///
+    /// This is like `get_count`, but returns a `usize` so that it can be
+    /// compared with lengths directly.
+    pub fn count(&self, key: &str) -> usize {
+        self.find(key).map_or(0, |v| v.as_count() as usize)
+    }
+
     /// Finds the value corresponding to `key` and calls `as_str()` on it.
     /// If the key does not exist, `""` is returned.
     pub fn get_str(&self, key: &str) -> &str {
@@ -405,32 +1866,236 @@ impl ArgvMap {
         self.find(key).map(|v| v.as_vec()).unwrap_or(vec!())
     }
 
+    /// Finds the values corresponding to `key` like `get_vec`, and parses
+    /// each of them with `FromStr`.
+    ///
+    /// If a value can't be parsed, then a `Decode` error naming the value
+    /// and its position in the list is returned. If the key does not exist,
+    /// an empty `Vec` is returned.
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let dopt = Docopt::new("Usage: prog <port>...").unwrap();
+    /// let vals = dopt.argv(&["prog", "80", "443"]).parse().unwrap();
+    /// let ports: Vec<u16> = vals.get_typed_vec("<port>").unwrap();
+    /// assert_eq!(ports, vec![80, 443]);
+    /// ```
+    pub fn get_typed_vec<T>(&self, key: &str) -> Result<Vec<T>, Error>
+            where T: FromStr, <T as FromStr>::Err: fmt::Display {
+        let vs = self.get_vec(key);
+        let mut typed = Vec::with_capacity(vs.len());
+        for (i, v) in vs.iter().enumerate() {
+            match v.parse() {
+                Ok(t) => typed.push(t),
+                Err(err) => {
+                    return Err(Decode(format!(
+                        "Could not decode '{}' for '{}' (element {} of {}): \
+                         {}", v, key, i + 1, vs.len(), err)));
+                }
+            }
+        }
+        Ok(typed)
+    }
+
     /// Return the raw value corresponding to some `key`.
     ///
-    /// `key` should be a string in the traditional Docopt format. e.g.,
-    /// `<arg>` or `--flag`.
-    pub fn find(&self, key: &str) -> Option<&Value> {
-        self.map.find(&key.into())
+    /// `key` should be a string in the traditional Docopt format. e.g.,
+    /// `<arg>` or `--flag`.
+    ///
+    /// A positional argument can be looked up with either of its spellings,
+    /// so `FILE` finds the value of `<file>` and vice versa. (This is true
+    /// of every lookup method, including decoding into an `arg_FILE` or
+    /// `arg_file` field.)
+    pub fn find(&self, key: &str) -> Option<&Value> {
+        self.map.find(&key.into()).or_else(|| {
+            positional_spelling(key).and_then(|k| self.map.find(&k))
+        })
+    }
+
+    /// Like `find`, but returns the value for editing in place.
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let dopt = Docopt::new("Usage: prog <file>...").unwrap();
+    /// let mut vals = dopt.argv(&["prog", "b", "a"]).parse().unwrap();
+    /// vals.find_mut("<file>").unwrap().as_list_mut().unwrap().sort();
+    /// assert_eq!(vals.get_vec("<file>"), vec!["a", "b"]);
+    /// ```
+    pub fn find_mut(&mut self, key: &str) -> Option<&mut Value> {
+        let key = if self.map.contains_key(&key.into()) {
+            key.to_string()
+        } else {
+            match positional_spelling(key) {
+                None => return None,
+                Some(k) => k,
+            }
+        };
+        self.map.find_mut(&key)
+    }
+
+    /// Returns the canonical spelling of `key`.
+    ///
+    /// When an option description declares synonyms like `-q, --quiet`,
+    /// values are stored under one of them (the long flag, if there is one)
+    /// and the others resolve to it. Every lookup method accepts any of the
+    /// synonyms. If `key` isn't a known key or synonym, `None` is returned.
+    pub fn canonical(&self, key: &str) -> Option<&str> {
+        self.map.canonical(&key.into()).or_else(|| {
+            positional_spelling(key).and_then(|k| self.map.canonical(&k))
+        }).map(|k| &**k)
+    }
+
+    /// Returns the flags given in argv, in the order they were given.
+    ///
+    /// Each flag appears once for every time it was given, so `-v --quiet
+    /// -v` gives `-v`, `--quiet` and `-v`. Stacked short flags like `-vq`
+    /// are split up, and a flag with a synonym is recorded under the same
+    /// spelling as its value (the long flag, if there is one). Positional
+    /// arguments and commands aren't included.
+    ///
+    /// This is empty for a map built with `from_pairs`.
+    pub fn order(&self) -> &[Atom] {
+        &self.flag_order
+    }
+
+    /// Returns the name of the command that was given, or `None` if no
+    /// command was given.
+    ///
+    /// This makes it easy to `match` on subcommands. If more than one
+    /// command was given (e.g., `remote` and `add` in `prog remote add`),
+    /// the first one in argv is returned. (For a map built with
+    /// `from_pairs`, it's the first one in the pairs.)
+    ///
+    /// ```rust
+    /// use docopt::Docopt;
+    ///
+    /// let dopt = Docopt::new("Usage: prog (add | rm) <name>").unwrap();
+    /// let vals = dopt.argv(&["prog", "rm", "x"]).parse().unwrap();
+    /// match vals.active_command() {
+    ///     Some("add") => { /* ... */ }
+    ///     Some("rm") => { /* ... */ }
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn active_command(&self) -> Option<&str> {
+        let given = |key: &&String| {
+            !key.is_empty() && !key.starts_with('-')
+            && positional_spelling(key).is_none()
+            && self.map.find(*key).map_or(false, |v| match *v {
+                Switch(b) => b,
+                Counted(n) => n > 0,
+                Plain(_) | List(_) => false,
+            })
+        };
+        self.positional_order.iter().find(&given)
+            .or_else(|| self.key_order.iter().find(&given))
+            .map(|key| &**key)
+    }
+
+    /// Return the number of values, not including synonyms.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Checks that each key in `expected` has a value of the given kind,
+    /// without looking at what the value is.
+    ///
+    /// This makes for terse tests of how a Docopt string is parsed:
+    ///
+    /// ```rust
+    /// use docopt::{Docopt, ValueKind};
+    ///
+    /// let vals = Docopt::new("Usage: prog [-v...] [--out=<f>] <in>...")
+    ///                   .and_then(|d| d.argv(&["prog", "a"]).parse())
+    ///                   .unwrap();
+    /// vals.assert_shape(&[
+    ///     ("-v", ValueKind::Counted),
+    ///     ("--out", ValueKind::Plain),
+    ///     ("<in>", ValueKind::List),
+    /// ]).unwrap();
+    /// ```
+    ///
+    /// Keys not in `expected` aren't checked. If any key is missing or has
+    /// a value of another kind, then the error lists every such key.
+    pub fn assert_shape(&self, expected: &[(&str, ValueKind)])
+                       -> Result<(), String> {
+        let mut mismatches = vec![];
+        for &(key, kind) in expected {
+            match self.find(key) {
+                None => {
+                    mismatches.push(format!("'{}' is missing (expected {:?})",
+                                            key, kind))
+                }
+                Some(v) if v.kind() != kind => {
+                    mismatches.push(format!("'{}' is {:?} (expected {:?})",
+                                            key, v.kind(), kind))
+                }
+                Some(_) => {}
+            }
+        }
+        if mismatches.is_empty() {
+            return Ok(())
+        }
+        Err(format!("{} of {} keys have the wrong shape:\n  {}",
+                    mismatches.len(), expected.len(),
+                    mismatches.join("\n  ")))
     }
 
-    /// Return the number of values, not including synonyms.
-    pub fn len(&self) -> usize {
-        self.map.len()
+    /// Returns a JSON object mapping each Docopt key to its value.
+    ///
+    /// Switches become booleans, counts become numbers, absent plain values
+    /// become `null`, present plain values become strings (so an empty
+    /// argument is `""`) and lists become arrays of strings.
+    ///
+    /// Keys appear in the order they're first used in the usage patterns,
+    /// followed by any remaining keys in sorted order. Synonyms are omitted,
+    /// so the output is deterministic.
+    pub fn to_json(&self) -> String {
+        let mut keys: Vec<&String> = vec![];
+        for k in &self.key_order {
+            if let Some(k) = self.map.canonical(k) {
+                if !keys.contains(&k) {
+                    keys.push(k);
+                }
+            }
+        }
+        let mut rest: Vec<&String> =
+            self.map.keys().filter(|k| !keys.contains(k)).collect();
+        rest.sort();
+        keys.extend(rest);
+
+        let fields: Vec<String> = keys.iter().map(|k| {
+            format!("{}:{}", Json::String((*k).clone()), self.map.get(k).json())
+        }).collect();
+        format!("{{{}}}", fields.join(","))
     }
 
     /// Converts a Docopt key to a struct field name.
-    /// This makes a half-hearted attempt at making the key a valid struct
-    /// field name (like replacing `-` with `_`), but it does not otherwise
-    /// guarantee that the result is a valid struct field name.
+    ///
+    /// Flags get a `flag_` prefix, positional arguments get an `arg_` prefix
+    /// and commands get a `cmd_` prefix. Hyphens and dots in the name become
+    /// underscores, while case is kept. For example, `--dry-run` becomes
+    /// `flag_dry_run`, `<input-file>` becomes `arg_input_file`, `FILE`
+    /// becomes `arg_FILE` and `self.update` becomes `cmd_self_update`.
+    /// The `--` in a pattern like `prog [--] <args>...` becomes
+    /// `cmd_double_dash`.
+    ///
+    /// This does not otherwise guarantee that the result is a valid struct
+    /// field name.
     #[doc(hidden)]
     pub fn key_to_struct_field(name: &str) -> String {
+        if name == "--" {
+            return "cmd_double_dash".into();
+        }
         lazy_static! {
             static ref RE: Regex = regex!(
                 r"^(?:--?(?P<flag>\S+)|(?:(?P<argu>\p{Lu}+)|<(?P<argb>[^>]+)>)|(?P<cmd>\S+))$"
             );
         }
         fn sanitize(name: &str) -> String {
-            name.replace("-", "_")
+            name.replace("-", "_").replace(".", "_")
         }
 
         RE.replace(name, |cap: &Captures| {
@@ -461,8 +2126,19 @@ impl ArgvMap {
     }
 
     /// Converts a struct field name to a Docopt key.
+    ///
+    /// This is the inverse of `key_to_struct_field`, with underscores
+    /// becoming hyphens. Since `key_to_struct_field` maps hyphens, dots
+    /// and underscores to the same character, the key returned may not be
+    /// the one the field was made from (e.g., `cmd_self_update` becomes
+    /// `self-update`), but converting it back always gives the same field.
+    /// When decoding, a field whose key isn't found is matched against
+    /// every key with `key_to_struct_field` instead.
     #[doc(hidden)]
     pub fn struct_field_to_key(field: &str) -> String {
+        if field == "cmd_double_dash" {
+            return "--".into();
+        }
         lazy_static! {
             static ref FLAG: Regex = regex!(r"^flag_");
             static ref ARG: Regex = regex!(r"^arg_");
@@ -496,6 +2172,52 @@ impl ArgvMap {
             };
         desanitize(&*name)
     }
+
+    // Appends the unknown flags collected from argv to the list value of
+    // `key`. Any other value of `key` is replaced.
+    fn add_unknown(&mut self, key: &str, unknown: Vec<String>) {
+        let key = self.canonical(key).unwrap_or(key).to_string();
+        if let Some(&mut List(ref mut vs)) = self.map.find_mut(&key) {
+            vs.extend(unknown);
+            return
+        }
+        self.map.insert(key, List(unknown));
+    }
+}
+
+/// Builds a map from `(key, value)` pairs, like `ArgvMap::from_pairs`.
+impl<'a> FromIterator<(&'a str, Value)> for ArgvMap {
+    fn from_iter<I>(pairs: I) -> ArgvMap
+            where I: IntoIterator<Item=(&'a str, Value)> {
+        let mut map = SynonymMap::new();
+        let mut order = vec![];
+        for (key, val) in pairs {
+            order.push(key.to_string());
+            map.insert(key.to_string(), val);
+        }
+        ArgvMap {
+            map: map,
+            key_order: order,
+            flag_order: vec![],
+            positional_order: vec![],
+        }
+    }
+}
+
+/// Returns the raw value corresponding to some `key`, like `find`.
+///
+/// # Panics
+///
+/// If `key` isn't a key (or a synonym of a key) in the map.
+impl<'a> Index<&'a str> for ArgvMap {
+    type Output = Value;
+
+    fn index(&self, key: &'a str) -> &Value {
+        match self.find(key) {
+            Some(v) => v,
+            None => panic!("Unknown ArgvMap key: '{}'", key),
+        }
+    }
 }
 
 impl fmt::Debug for ArgvMap {
@@ -541,7 +2263,12 @@ pub enum Value {
     /// means `false`.
     Switch(bool),
 
-    /// The number of occurrences of a repeated flag.
+    /// The number of occurrences of a repeated flag or command.
+    ///
+    /// A command is repeated when it appears more than once in a pattern,
+    /// as in `prog go [go]`, or is followed by `...`. Like a counted flag,
+    /// it can be decoded into an integer struct field, e.g., `cmd_go:
+    /// usize`. Giving it more times than the pattern allows is no match.
     Counted(u64),
 
     /// A positional or flag argument.
@@ -549,15 +2276,49 @@ pub enum Value {
     /// This is `None` when the positional argument or flag is not present.
     /// Note that it is possible to have `Some("")` for a present but empty
     /// argument.
+    ///
+    /// A long flag written as `--color[=WHEN]` may be given without its
+    /// argument. A bare `--color` then yields the `[default: ...]` from its
+    /// option description, or `None` if there is no default.
     Plain(Option<String>),
 
     /// A List of positional or flag arguments.
     ///
     /// This list may be empty when no arguments or flags are present.
+    ///
+    /// A flag whose option description contains `[sep: X]` (e.g.,
+    /// `--path=<p>  Paths [sep: :]`) always has a list value. Its argument
+    /// is split on the single character `X`, so `--path a:b:c` yields
+    /// three elements.
     List(Vec<String>),
 }
 
+/// The kind of a `Value`, without its contents.
+///
+/// This is used with `ArgvMap::assert_shape`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    /// A `Value::Switch`.
+    Switch,
+    /// A `Value::Counted`.
+    Counted,
+    /// A `Value::Plain`, whether present or not.
+    Plain,
+    /// A `Value::List`.
+    List,
+}
+
 impl Value {
+    /// Returns the kind of this value.
+    pub fn kind(&self) -> ValueKind {
+        match *self {
+            Switch(_) => ValueKind::Switch,
+            Counted(_) => ValueKind::Counted,
+            Plain(_) => ValueKind::Plain,
+            List(_) => ValueKind::List,
+        }
+    }
+
     /// Returns the value as a bool.
     ///
     /// Counted repetitions are `false` if `0` and `true` otherwise.
@@ -609,6 +2370,39 @@ impl Value {
             List(ref vs) => vs.iter().map(|s| &**s).collect(),
         }
     }
+
+    /// Returns the list of strings for editing in place.
+    ///
+    /// Only a `List` has one. For anything else, `None` is returned.
+    pub fn as_list_mut(&mut self) -> Option<&mut Vec<String>> {
+        match *self {
+            List(ref mut vs) => Some(vs),
+            _ => None,
+        }
+    }
+
+    /// Returns the plain string for editing in place.
+    ///
+    /// Only a `Plain` value has one, which may be set to `None` or replaced.
+    /// For anything else, `None` is returned.
+    pub fn as_plain_mut(&mut self) -> Option<&mut Option<String>> {
+        match *self {
+            Plain(ref mut s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn json(&self) -> Json {
+        match *self {
+            Switch(b) => Json::Boolean(b),
+            Counted(n) => Json::U64(n),
+            Plain(None) => Json::Null,
+            Plain(Some(ref s)) => Json::String(s.clone()),
+            List(ref vs) => {
+                Json::Array(vs.iter().map(|s| Json::String(s.clone())).collect())
+            }
+        }
+    }
 }
 
 /// Decoder for `ArgvMap` into your own `Decodable` types.
@@ -634,6 +2428,27 @@ impl Value {
 pub struct Decoder {
     vals: ArgvMap,
     stack: Vec<DecoderItem>,
+    bytes_encodings: Vec<(String, BytesEncoding)>,
+    duplicate_keys: DuplicateKeys,
+    thousands_separator: Option<char>,
+    // Set by `Docopt::check_defaults`, where values without a default are
+    // empty and may decode to anything.
+    defaults_only: bool,
+    // Set by `OneOf` until its enum variant is read.
+    group: bool,
+    // Set when a `OneOf` is decoded, which tells an `Option` of a `OneOf`
+    // apart from an `Option` of a missing key.
+    group_seen: bool,
+    // Set while decoding an `Option` of a field without a key, which is
+    // `None` if it's a `OneOf` and none of its group was given.
+    optional_group: bool,
+    // Set when an optional `OneOf` turns out to be absent.
+    group_absent: bool,
+    // A description of the key of the last value read, which is added to
+    // errors raised by `Decodable` implementations outside this crate.
+    last_key: Option<String>,
+    // Whether the last value read was absent (i.e., `Plain(None)`).
+    last_missing: bool,
 }
 
 #[derive(Debug)]
@@ -641,6 +2456,29 @@ struct DecoderItem {
     key: String,
     struct_field: String,
     val: Option<Value>,
+    /// The position (starting at `1`) of this item in its list and the
+    /// length of the list, when decoding the elements of a sequence.
+    elem: Option<(usize, usize)>,
+    /// The key of a `key=value` pair, when decoding its value.
+    pair_key: Option<String>,
+}
+
+impl DecoderItem {
+    // Describes the item for error messages.
+    fn describe(&self) -> String {
+        let mut details = vec![];
+        if let Some((i, n)) = self.elem {
+            details.push(format!("element {} of {}", i, n));
+        }
+        if let Some(ref k) = self.pair_key {
+            details.push(format!("key '{}'", k));
+        }
+        if details.is_empty() {
+            format!("'{}'", self.key)
+        } else {
+            format!("'{}' ({})", self.key, details.join(", "))
+        }
+    }
 }
 
 macro_rules! derr(
@@ -649,12 +2487,146 @@ macro_rules! derr(
 
 impl Decoder {
     fn push(&mut self, struct_field: &str) {
-        let key = ArgvMap::struct_field_to_key(struct_field);
+        if struct_field == "cmd" {
+            // Resolved to a command when the enum variant is read.
+            self.stack.push(DecoderItem {
+                key: "cmd".into(),
+                struct_field: struct_field.into(),
+                val: None,
+                elem: None,
+                pair_key: None,
+            });
+            return;
+        }
+        let prefixed = ["flag_", "arg_", "cmd_"].iter().any(|p| {
+            struct_field.starts_with(p)
+        });
+        if !prefixed {
+            // Without a key, this can only be a `OneOf`, which is resolved
+            // to one of its group when the enum variant is read.
+            self.stack.push(DecoderItem {
+                key: struct_field.into(),
+                struct_field: struct_field.into(),
+                val: None,
+                elem: None,
+                pair_key: None,
+            });
+            return;
+        }
+        let mut key = ArgvMap::struct_field_to_key(struct_field);
+        if self.vals.find(&*key).is_none() {
+            let found = self.vals.map.keys().find(|k| {
+                ArgvMap::key_to_struct_field(k) == struct_field
+            });
+            if let Some(k) = found {
+                key = k.clone();
+            }
+        }
         self.stack.push(DecoderItem {
             key: key.clone(),
             struct_field: struct_field.into(),
             val: self.vals.find(&*key).cloned(),
+            elem: None,
+            pair_key: None,
+        });
+    }
+
+    // Returns the encoding set with `Docopt::bytes_encoding` for `key` or
+    // one of its synonyms, if any.
+    fn bytes_encoding(&self, key: &str) -> Option<BytesEncoding> {
+        let canonical = match self.vals.canonical(key) {
+            None => return None,
+            Some(canonical) => canonical,
+        };
+        self.bytes_encodings.iter()
+            .find(|&&(ref k, _)| self.vals.canonical(k) == Some(canonical))
+            .map(|&(_, enc)| enc)
+    }
+
+    // Finds the one variant in `names` that corresponds to a command that
+    // was given.
+    fn command_variant(&self, names: &[&str]) -> Result<usize, Error> {
+        fn simplify(name: &str) -> String {
+            to_lowercase(name).replace("_", "")
+        }
+        let given: Vec<String> = self.vals.map.iter()
+            .map(|(k, v)| (ArgvMap::key_to_struct_field(k), v))
+            .filter(|&(ref field, v)| field.starts_with("cmd_") && v.as_bool())
+            .map(|(field, _)| simplify(&field["cmd_".len()..]))
+            .collect();
+        let found: Vec<usize> = (0..names.len())
+            .filter(|&i| given.contains(&simplify(names[i])))
+            .collect();
+        match found.len() {
+            1 => Ok(found[0]),
+            0 => derr!("None of the commands {:?} were given \
+                        (for struct field 'cmd').", names),
+            _ => derr!("More than one of the commands {:?} were given \
+                        (for struct field 'cmd').", names),
+        }
+    }
+
+    // Finds the one variant in `names` that corresponds to an argument that
+    // was given, for the struct field `field`, and pushes its value for the
+    // variant's payload. `None` is returned when none of them were given
+    // and `optional` is set.
+    fn group_variant(&mut self, field: &str, names: &[&str], optional: bool)
+                    -> Result<Option<usize>, Error> {
+        fn simplify(name: &str) -> String {
+            to_lowercase(name).replace("_", "")
+        }
+        fn given(v: &Value) -> bool {
+            match *v {
+                Switch(b) => b,
+                Counted(n) => n > 0,
+                Plain(ref s) => s.is_some(),
+                List(ref vs) => !vs.is_empty(),
+            }
+        }
+        // The key for each variant. A variant whose name matches more than
+        // one key (like `--file` and `<file>`) uses the one that was given.
+        let mut keys: Vec<String> = vec![];
+        for name in names {
+            let mut matched: Vec<&String> = self.vals.map.keys().filter(|k| {
+                let field = ArgvMap::key_to_struct_field(k);
+                let bare = field.splitn(2, '_').nth(1).unwrap_or("");
+                simplify(bare) == simplify(name)
+            }).collect();
+            matched.sort();
+            let key = matched.iter()
+                             .find(|k| given(self.vals.map.get(k)))
+                             .or(matched.first());
+            match key {
+                Some(k) => keys.push((*k).clone()),
+                None => derr!("Could not find an argument for variant '{}' \
+                               (for struct field '{}').", name, field),
+            }
+        }
+        let found: Vec<usize> = (0..keys.len())
+            .filter(|&i| given(self.vals.map.get(&keys[i])))
+            .collect();
+        let i = match found.len() {
+            1 => found[0],
+            0 if optional => return Ok(None),
+            0 if self.defaults_only => 0,
+            0 => derr!("One of {} must be given (for struct field '{}').",
+                       quote_list(&keys, "or"), field),
+            _ => {
+                let both: Vec<String> =
+                    found.iter().map(|&i| keys[i].clone()).collect();
+                derr!("Only one of {} may be given (for struct field '{}').",
+                      quote_list(&both, "and"), field)
+            }
+        };
+        let val = self.vals.map.get(&keys[i]).clone();
+        self.stack.push(DecoderItem {
+            key: keys[i].clone(),
+            struct_field: field.into(),
+            val: Some(val),
+            elem: None,
+            pair_key: None,
         });
+        Ok(Some(i))
     }
 
     fn pop(&mut self) -> Result<DecoderItem, Error> {
@@ -664,15 +2636,19 @@ impl Decoder {
         }
     }
 
+    // Pops the next value along with a description of its key for errors.
     fn pop_key_val(&mut self) -> Result<(String, Value), Error> {
         let it = try!(self.pop());
+        let desc = it.describe();
+        self.last_key = Some(desc.clone());
+        self.last_missing = it.val == Some(Plain(None));
         match it.val {
             None => derr!(
                 "Could not find argument '{}' (from struct field '{}').
 Note that each struct field must have the right key prefix, which must
 be one of `cmd_`, `flag_` or `arg_`.",
                 it.key, it.struct_field),
-            Some(v) => Ok((it.key, v))
+            Some(v) => Ok((desc, v))
         }
     }
 
@@ -690,12 +2666,14 @@ be one of `cmd_`, `flag_` or `arg_`.",
                 if v.as_str().trim().is_empty() {
                     Ok("0".parse().unwrap()) // lol
                 } else {
-                    match v.as_str().parse() {
-                        Err(_) => {
-                            derr!("Could not decode '{}' to {} for '{}'.",
+                    let sep = self.thousands_separator;
+                    match strip_digit_separators(v.as_str(), sep)
+                              .and_then(|s| s.parse().ok()) {
+                        None => {
+                            derr!("Could not decode '{}' to {} for {}.",
                                   v.as_str(), expect, k)
                         }
-                        Ok(v) => Ok(v),
+                        Some(v) => Ok(v),
                     }
                 }
             }
@@ -706,9 +2684,10 @@ be one of `cmd_`, `flag_` or `arg_`.",
         let (k, v) = try!(self.pop_key_val());
         match v {
             Counted(n) => Ok(n as f64),
+            Plain(None) if self.defaults_only => Ok(0.0),
             _ => {
                 match v.as_str().parse() {
-                    Err(_) => derr!("Could not decode '{}' to {} for '{}'.",
+                    Err(_) => derr!("Could not decode '{}' to {} for {}.",
                                     v.as_str(), expect, k),
                     Ok(v) => Ok(v),
                 }
@@ -729,7 +2708,10 @@ impl ::rustc_serialize::Decoder for Decoder {
     type Error = Error;
 
     fn error(&mut self, err: &str) -> Error {
-        Decode(err.into())
+        match self.last_key {
+            None => Decode(err.into()),
+            Some(ref k) => Decode(format!("{} (for {})", err, k)),
+        }
     }
 
     fn read_nil(&mut self) -> Result<(), Error> {
@@ -765,7 +2747,8 @@ impl ::rustc_serialize::Decoder for Decoder {
         let vstr = v.as_str();
         match vstr.chars().count() {
             1 => Ok(vstr.chars().next().unwrap()),
-            _ => derr!("Could not decode '{}' into char for '{}'.", vstr, k),
+            0 if self.defaults_only && v == Plain(None) => Ok('\0'),
+            _ => derr!("Could not decode '{}' into char for {}.", vstr, k),
         }
     }
 
@@ -781,7 +2764,36 @@ impl ::rustc_serialize::Decoder for Decoder {
     fn read_enum_variant<T, F>(&mut self, names: &[&str], mut f: F)
                               -> Result<T, Error>
             where F: FnMut(&mut Decoder, usize) -> Result<T, Error> {
-        let v = to_lowercase(try!(self.pop_val()).as_str());
+        if self.stack.last().map_or(false, |it| it.struct_field == "cmd") {
+            try!(self.pop());
+            if self.defaults_only {
+                return f(self, 0);
+            }
+            let i = try!(self.command_variant(names));
+            return f(self, i);
+        }
+        if ::std::mem::replace(&mut self.group, false) {
+            let it = try!(self.pop());
+            let optional = ::std::mem::replace(&mut self.optional_group, false);
+            let depth = self.stack.len();
+            let i = match try!(self.group_variant(&it.struct_field, names,
+                                                  optional)) {
+                Some(i) => i,
+                None => {
+                    self.group_absent = true;
+                    derr!("None of the variants {:?} were given.", names)
+                }
+            };
+            // A variant without a payload leaves its value on the stack.
+            let decoded = f(self, i);
+            self.stack.truncate(depth);
+            return decoded;
+        }
+        let v = try!(self.pop_val());
+        if self.defaults_only && v == Plain(None) {
+            return f(self, 0);
+        }
+        let v = to_lowercase(v.as_str());
         let i =
             match names.iter().map(|&n| to_lowercase(n)).position(|n| n == v) {
                 Some(i) => i,
@@ -793,10 +2805,15 @@ impl ::rustc_serialize::Decoder for Decoder {
         f(self, i)
     }
 
-    fn read_enum_variant_arg<T, F>(&mut self, _: usize, _: F)
+    fn read_enum_variant_arg<T, F>(&mut self, i: usize, f: F)
                                   -> Result<T, Error>
             where F: FnOnce(&mut Decoder) -> Result<T, Error> {
-        unimplemented!()
+        // Only variants of a `OneOf` have a payload, which is the value of
+        // the argument that was given.
+        if i > 0 {
+            derr!("Could not decode a variant with more than one value.")
+        }
+        f(self)
     }
 
     fn read_enum_struct_variant<T, F>(&mut self, _: &[&str], _: F)
@@ -811,8 +2828,16 @@ impl ::rustc_serialize::Decoder for Decoder {
         unimplemented!()
     }
 
-    fn read_struct<T, F>(&mut self, _: &str, _: usize, f: F) -> Result<T, Error>
+    fn read_struct<T, F>(&mut self, name: &str, _: usize, f: F)
+                        -> Result<T, Error>
             where F: FnOnce(&mut Decoder) -> Result<T, Error> {
+        if name == ONE_OF {
+            self.group = true;
+            self.group_seen = true;
+            let decoded = f(self);
+            self.group = false;
+            return decoded;
+        }
         f(self)
     }
 
@@ -823,14 +2848,45 @@ impl ::rustc_serialize::Decoder for Decoder {
         f(self)
     }
 
-    fn read_tuple<T, F>(&mut self, _: usize, _: F) -> Result<T, Error>
+    fn read_tuple<T, F>(&mut self, len: usize, f: F) -> Result<T, Error>
             where F: FnOnce(&mut Decoder) -> Result<T, Error> {
-        unimplemented!()
+        // A pair is decoded from a `key=value` argument, which is split on
+        // the first `=`.
+        let it = try!(self.pop());
+        if len != 2 {
+            derr!("Could not decode {} into a tuple of {} values. Only pairs \
+                   are supported.", it.describe(), len)
+        }
+        let arg = match it.val {
+            Some(Plain(Some(ref s))) => s.clone(),
+            _ => derr!("Could not decode {} into a key=value pair.",
+                       it.describe()),
+        };
+        let (key, value) = match arg.find('=') {
+            Some(i) => (arg[..i].to_string(), arg[i + 1..].to_string()),
+            None => derr!("Could not decode '{}' into a key=value pair \
+                           for {}.", arg, it.describe()),
+        };
+        self.stack.push(DecoderItem {
+            key: it.key.clone(),
+            struct_field: it.struct_field.clone(),
+            val: Some(Plain(Some(value))),
+            elem: it.elem,
+            pair_key: Some(key.clone()),
+        });
+        self.stack.push(DecoderItem {
+            key: it.key,
+            struct_field: it.struct_field,
+            val: Some(Plain(Some(key))),
+            elem: it.elem,
+            pair_key: None,
+        });
+        f(self)
     }
 
-    fn read_tuple_arg<T, F>(&mut self, _: usize, _: F) -> Result<T, Error>
+    fn read_tuple_arg<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
             where F: FnOnce(&mut Decoder) -> Result<T, Error> {
-        unimplemented!()
+        f(self)
     }
 
     fn read_tuple_struct<T, F>(&mut self, _: &str, _: usize, _: F)
@@ -850,22 +2906,63 @@ impl ::rustc_serialize::Decoder for Decoder {
         let option =
             match self.stack.last() {
                 None => derr!("Could not decode value into unknown key."),
-                Some(it) => it.val.as_ref()
-                                  .map_or(false, |v| v.as_bool())
+                Some(it) => it.val.as_ref().map(|v| v.as_bool()),
             };
-        f(self, option)
+        match option {
+            Some(option) => f(self, option),
+            None => {
+                // A field without a key may be a `OneOf`, and whether any
+                // of its group was given is only known once the enum's
+                // variants are, so try to decode it first.
+                self.optional_group = true;
+                self.group_seen = false;
+                let decoded = f(self, true);
+                self.optional_group = false;
+                let seen = ::std::mem::replace(&mut self.group_seen, false);
+                if !seen || ::std::mem::replace(&mut self.group_absent, false) {
+                    return f(self, false);
+                }
+                decoded
+            }
+        }
     }
 
     fn read_seq<T, F>(&mut self, f: F) -> Result<T, Error>
             where F: FnOnce(&mut Decoder, usize) -> Result<T, Error> {
         let it = try!(self.pop());
+        if let (Some(enc), Some(&Plain(Some(ref s)))) =
+                (self.bytes_encoding(&it.key), it.val.as_ref()) {
+            let bytes = match enc {
+                BytesEncoding::Hex => s.from_hex().map_err(|e| e.to_string()),
+                BytesEncoding::Base64 => {
+                    s.from_base64().map_err(|e| e.to_string())
+                }
+            };
+            let bytes = match bytes {
+                Ok(bytes) => bytes,
+                Err(err) => derr!("Could not decode '{}' as {:?} for '{}': {}",
+                                  s, enc, it.key, err),
+            };
+            for (i, b) in bytes.iter().enumerate().rev() {
+                self.stack.push(DecoderItem {
+                    key: it.key.clone(),
+                    struct_field: it.struct_field.clone(),
+                    val: Some(Plain(Some(b.to_string()))),
+                    elem: Some((i + 1, bytes.len())),
+                    pair_key: None,
+                })
+            }
+            return f(self, bytes.len());
+        }
         let list = it.val.unwrap_or(List(vec!()));
         let vals = list.as_vec();
-        for val in vals.iter().rev() {
+        for (i, val) in vals.iter().enumerate().rev() {
             self.stack.push(DecoderItem {
                 key: it.key.clone(),
                 struct_field: it.struct_field.clone(),
                 val: Some(Plain(Some((*val).into()))),
+                elem: Some((i + 1, vals.len())),
+                pair_key: None,
             })
         }
         f(self, vals.len())
@@ -876,19 +2973,469 @@ impl ::rustc_serialize::Decoder for Decoder {
         f(self)
     }
 
-    fn read_map<T, F>(&mut self, _: F) -> Result<T, Error>
+    fn read_map<T, F>(&mut self, f: F) -> Result<T, Error>
             where F: FnOnce(&mut Decoder, usize) -> Result<T, Error> {
-        unimplemented!()
+        // Each argument is a `key=value` pair, split on the first `=`. An
+        // argument without an `=` is a key with an empty value.
+        let it = try!(self.pop());
+        let list = it.val.unwrap_or(List(vec!()));
+        let args = list.as_vec();
+        let mut pairs: Vec<(String, String, Option<(usize, usize)>)> = vec![];
+        for (i, arg) in args.iter().enumerate() {
+            let elem = DecoderItem {
+                key: it.key.clone(),
+                struct_field: it.struct_field.clone(),
+                val: None,
+                elem: Some((i + 1, args.len())),
+                pair_key: None,
+            };
+            let (key, value) = match arg.find('=') {
+                Some(i) => (&arg[..i], &arg[i + 1..]),
+                None => (*arg, ""),
+            };
+            if key.is_empty() {
+                derr!("Could not decode '{}' into a key=value pair for {}: \
+                       the key is empty.", arg, elem.describe())
+            }
+            if let Some(j) = pairs.iter().position(|p| p.0 == key) {
+                if self.duplicate_keys == DuplicateKeys::Error {
+                    derr!("Duplicate key '{}' in '{}' for {}.",
+                          key, arg, elem.describe())
+                }
+                pairs.remove(j);
+            }
+            pairs.push((key.to_string(), value.to_string(), elem.elem));
+        }
+        // Pushed in reverse, so that each key is popped before its value.
+        for (key, value, elem) in pairs.iter().cloned().rev() {
+            self.stack.push(DecoderItem {
+                key: it.key.clone(),
+                struct_field: it.struct_field.clone(),
+                val: Some(Plain(Some(value))),
+                elem: elem,
+                pair_key: Some(key.clone()),
+            });
+            self.stack.push(DecoderItem {
+                key: it.key.clone(),
+                struct_field: it.struct_field.clone(),
+                val: Some(Plain(Some(key))),
+                elem: elem,
+                pair_key: None,
+            });
+        }
+        f(self, pairs.len())
     }
 
-    fn read_map_elt_key<T, F>(&mut self, _: usize, _: F) -> Result<T, Error>
+    fn read_map_elt_key<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
             where F: FnOnce(&mut Decoder) -> Result<T, Error> {
-        unimplemented!()
+        f(self)
     }
 
-    fn read_map_elt_val<T, F>(&mut self, _: usize, _: F) -> Result<T, Error>
+    fn read_map_elt_val<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
             where F: FnOnce(&mut Decoder) -> Result<T, Error> {
-        unimplemented!()
+        f(self)
+    }
+}
+
+/// An enum decoded from whichever one of a group of mutually exclusive
+/// flags, arguments or commands was given.
+///
+/// For a usage pattern like `(--stdin | --file=<f>)`, a struct field can
+/// be `OneOf<Input>` with `enum Input { Stdin, File(String) }`. The field
+/// doesn't correspond to a key, so it can have any name. Variant names are
+/// matched against keys in the same way as commands for a `cmd` field, and
+/// a variant with a payload is decoded from the value of its key.
+///
+/// Exactly one of the variants must have been given (where a value from
+/// `[default: ...]` counts as given), or else decoding fails with an error
+/// naming the keys. For `Option<OneOf<T>>`, the field is `None` when none of
+/// them were given.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate docopt;
+/// # extern crate rustc_serialize;
+/// # fn main() {
+/// use docopt::{Docopt, OneOf};
+///
+/// #[derive(RustcDecodable, Debug, PartialEq)]
+/// enum Input { Stdin, File(String) }
+///
+/// #[derive(RustcDecodable)]
+/// struct Args {
+///     input: OneOf<Input>,
+/// }
+///
+/// let args: Args = Docopt::new("Usage: cat (--stdin | --file=<f>)")
+///     .and_then(|d| d.argv(vec!["cat", "--file=a.txt"]).decode())
+///     .unwrap();
+/// assert_eq!(*args.input, Input::File("a.txt".to_string()));
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OneOf<T>(pub T);
+
+// The struct name `OneOf` reads, which tells the `Decoder` to decode the
+// enum inside from a group of keys.
+const ONE_OF: &'static str = "docopt::OneOf";
+
+impl<T: Decodable> Decodable for OneOf<T> {
+    fn decode<D: ::rustc_serialize::Decoder>(d: &mut D)
+                                            -> Result<OneOf<T>, D::Error> {
+        d.read_struct(ONE_OF, 1, |d| T::decode(d)).map(OneOf)
+    }
+}
+
+impl<T> ::std::ops::Deref for OneOf<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// A value decoded from its string form with `FromStr`.
+///
+/// `rustc_serialize` only knows how to decode a handful of types, so a
+/// struct field of any other type that implements `FromStr` can be wrapped
+/// in `Parsed`, e.g., `arg_addr: Parsed<SocketAddr>`. This works for
+/// `Option<Parsed<T>>` and `Vec<Parsed<T>>` too. An absent value decodes
+/// to `None` without being parsed.
+///
+/// If parsing fails, the error from `FromStr` is returned as a `Decode`
+/// error along with the name of the argument.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate docopt;
+/// # extern crate rustc_serialize;
+/// # fn main() {
+/// use std::net::{IpAddr, SocketAddr};
+/// use docopt::{Docopt, Parsed};
+///
+/// #[derive(RustcDecodable)]
+/// struct Args {
+///     arg_addr: Parsed<SocketAddr>,
+///     flag_allow: Option<Parsed<IpAddr>>,
+/// }
+///
+/// let args: Args = Docopt::new("Usage: serve [--allow=<ip>] <addr>")
+///     .and_then(|d| d.argv(vec!["serve", "127.0.0.1:80"]).decode())
+///     .unwrap();
+/// assert_eq!(args.arg_addr.port(), 80);
+/// assert!(args.flag_allow.is_none());
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Parsed<T>(pub T);
+
+impl<T> Decodable for Parsed<T>
+        where T: FromStr, <T as FromStr>::Err: fmt::Display {
+    fn decode<D: ::rustc_serialize::Decoder>(d: &mut D)
+                                            -> Result<Parsed<T>, D::Error> {
+        let s = try!(d.read_str());
+        match s.parse() {
+            Ok(v) => Ok(Parsed(v)),
+            Err(err) => {
+                Err(d.error(&format!("Could not decode '{}': {}", s, err)))
+            }
+        }
+    }
+}
+
+impl<T> ::std::ops::Deref for Parsed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+// Whether `key` is a flag without an argument that was given. A flag of
+// the same name that takes an argument is left alone.
+fn given_switch(vals: &ArgvMap, key: &str) -> bool {
+    match vals.find(key) {
+        Some(&Switch(b)) => b,
+        Some(&Counted(n)) => n > 0,
+        _ => false,
+    }
+}
+
+// Removes ANSI escape sequences (CSI and OSC) and any other control
+// characters except for tabs and newlines.
+fn strip_control(s: &str) -> String {
+    lazy_static! {
+        static ref CONTROL: Regex = regex!(
+            r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)?|\x1b.?|[\x00-\x08\x0b-\x1f\x7f\x{80}-\x{9f}]"
+        );
+    }
+    CONTROL.replace_all(s, "").into_owned()
+}
+
+// Removes the separators from an integer written like `1_000` (with
+// underscores between digits) or, if `thousands` is set, like `1,000`.
+// Returns `None` if they're misplaced or both kinds are used.
+fn strip_digit_separators(s: &str, thousands: Option<char>) -> Option<String> {
+    let by_thousands = thousands.map_or(false, |c| s.contains(c));
+    if s.contains('_') {
+        if by_thousands {
+            return None
+        }
+        let chars: Vec<char> = s.chars().collect();
+        for (i, &c) in chars.iter().enumerate() {
+            let digit = |j: usize| chars.get(j).map_or(false, |c| {
+                c.is_digit(10)
+            });
+            if c == '_' && (i == 0 || !digit(i - 1) || !digit(i + 1)) {
+                return None
+            }
+        }
+        return Some(s.replace('_', ""))
+    }
+    let sep = match thousands {
+        Some(sep) if by_thousands => sep,
+        _ => return Some(s.to_string()),
+    };
+    let unsigned = s.trim_left_matches(|c| c == '-' || c == '+');
+    let sign = &s[..s.len() - unsigned.len()];
+    if sign.len() > 1 {
+        return None
+    }
+    let groups: Vec<&str> = unsigned.split(sep).collect();
+    let valid = groups.iter().enumerate().all(|(i, g)| {
+        let len_ok = if i == 0 { 1 <= g.len() && g.len() <= 3 }
+                     else { g.len() == 3 };
+        len_ok && g.chars().all(|c| c.is_digit(10))
+    });
+    if valid { Some(format!("{}{}", sign, groups.concat())) } else { None }
+}
+
+// Returns the other spelling of a positional argument key, i.e., `FILE`
+// for `<file>` and `<file>` for `FILE`.
+fn positional_spelling(key: &str) -> Option<String> {
+    let upper = |s: &str| !s.is_empty() && s.chars().all(char::is_uppercase);
+    if key.starts_with('<') && key.ends_with('>') && key.len() > 2 {
+        let name = &key[1..key.len() - 1];
+        if upper(name) {
+            Some(name.to_string())
+        } else {
+            Some(name.to_uppercase())
+        }
+    } else if upper(key) {
+        Some(format!("<{}>", key.to_lowercase()))
+    } else {
+        None
+    }
+}
+
+// Reads the Docopt string in the file at `path`, resolving `@include`
+// lines. `stack` holds the files currently being read, which is used to
+// detect an include cycle.
+fn read_usage(path: &Path, stack: &mut Vec<PathBuf>) -> Result<String, Error> {
+    let key = path.canonicalize().unwrap_or(path.to_path_buf());
+    if stack.contains(&key) {
+        return Err(Usage(format!("'{}' includes itself.", path.display())));
+    }
+    let text = try!(read_file(path));
+    let dir = path.parent().unwrap_or(Path::new(""));
+    stack.push(key);
+    let mut doc = String::with_capacity(text.len());
+    for line in text.lines() {
+        let trimmed = line.trim();
+        let rest = if trimmed.starts_with("@include") {
+            &trimmed["@include".len()..]
+        } else {
+            ""
+        };
+        if rest.starts_with(char::is_whitespace) {
+            let included = try!(read_usage(&dir.join(rest.trim()), stack));
+            doc.push_str(included.trim_right_matches('\n'));
+        } else {
+            doc.push_str(line);
+        }
+        doc.push('\n');
+    }
+    stack.pop();
+    Ok(doc)
+}
+
+// Reads the file at `path` for `Docopt::from_path`.
+fn read_file(path: &Path) -> Result<String, Error> {
+    let mut text = String::new();
+    try!(File::open(path)
+              .and_then(|mut f| f.read_to_string(&mut text))
+              .map_err(|e| {
+                  Usage(format!("Could not read '{}': {}", path.display(), e))
+              }));
+    Ok(text)
+}
+
+// Returns the help message with the required options first in each block
+// of option descriptions (see `HelpLayout::RequiredFirst`).
+fn required_first_help(p: &Parser) -> String {
+    let doc = p.full_doc.trim();
+    let start = doc.find(&*p.usage_block)
+                   .map_or(doc.len(), |i| i + p.usage_block.len());
+    let required = p.required_atoms();
+    let is_required = |line: &str| {
+        let flags = line.find("  ").map_or(line, |i| &line[..i]).trim();
+        let first = flags.split(|c| c == ' ' || c == ',' || c == '=')
+                         .next().unwrap();
+        required.contains(&p.descs.resolve(&Atom::new(first)))
+    };
+    let indent = |line: &str| line.len() - line.trim_left().len();
+
+    let mut lines: Vec<&str> = vec![&doc[..start]];
+    // Each option description in the current block, with the lines that
+    // continue it.
+    let mut block: Vec<Vec<&str>> = vec![];
+    fn flush<'a, F>(block: &mut Vec<Vec<&'a str>>, lines: &mut Vec<&'a str>,
+                    is_required: &F) where F: Fn(&str) -> bool {
+        let (req, opt): (Vec<_>, Vec<_>) =
+            block.drain(..).partition(|desc| is_required(desc[0].trim()));
+        for desc in req.into_iter().chain(opt) {
+            lines.extend(desc);
+        }
+    }
+    // The first piece is the rest of the last line of the usage section.
+    for line in doc[start..].split('\n').skip(1) {
+        let trimmed = line.trim();
+        if trimmed.starts_with('-') {
+            block.push(vec![line]);
+            continue
+        }
+        let continues = block.last().map_or(false, |desc| {
+            !trimmed.is_empty() && indent(line) > indent(desc[0])
+        });
+        if continues {
+            block.last_mut().unwrap().push(line);
+        } else {
+            flush(&mut block, &mut lines, &is_required);
+            lines.push(line);
+        }
+    }
+    flush(&mut block, &mut lines, &is_required);
+    lines.join("\n")
+}
+
+// How deeply `%include` lines may be nested.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Appends `text` to `doc` with its `%include` lines resolved. `file` is the
+// name `text` was included as, if any. For each line added to `doc`, the
+// name it came from and its line number there are added to `origins`.
+fn expand_includes<F>(text: &str, file: Option<&str>, resolve: &F,
+                      stack: &mut Vec<String>, doc: &mut String,
+                      origins: &mut Vec<(Option<String>, usize)>)
+                     -> Result<(), Error>
+        where F: Fn(&str) -> Result<String, Error> {
+    // Included text continues the section it's included in.
+    let mut allowed = file.is_some();
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        allowed = includes_allowed(line, allowed);
+        let name = match include_name(trimmed) {
+            None => {
+                doc.push_str(line);
+                doc.push('\n');
+                origins.push((file.map(String::from), i + 1));
+                continue
+            }
+            Some(name) => name,
+        };
+        let place = match file {
+            None => format!("line {}", i + 1),
+            Some(f) => format!("line {} of '{}'", i + 1, f),
+        };
+        if name.is_empty() {
+            return Err(Usage(format!("'%include' needs a name ({}).", place)));
+        }
+        if !allowed {
+            return Err(Usage(format!(
+                "'%include {}' is only allowed in an options or arguments \
+                 section ({}).", name, place)));
+        }
+        if stack.iter().any(|s| s == name) {
+            return Err(Usage(format!(
+                "'{}' includes itself ({}).", name, place)));
+        }
+        if stack.len() >= MAX_INCLUDE_DEPTH {
+            return Err(Usage(format!(
+                "Includes are nested more than {} deep at '%include {}' \
+                 ({}).", MAX_INCLUDE_DEPTH, name, place)));
+        }
+        let included = try!(resolve(name).map_err(|e| {
+            Usage(format!("Could not include '{}' ({}): {}", name, place, e))
+        }));
+        stack.push(name.to_string());
+        try!(expand_includes(&included, Some(name), resolve, stack, doc,
+                             origins));
+        stack.pop();
+    }
+    Ok(())
+}
+
+// Returns whether `%include` lines are allowed after `line`, given whether
+// they were allowed before it. They are allowed in the sections whose
+// header contains `options:` or `arguments:`.
+fn includes_allowed(line: &str, allowed: bool) -> bool {
+    lazy_static! {
+        static ref INCLUDE_SECTION: Regex = regex!(
+            r"(?i)(?:options|arguments):"
+        );
+    }
+    let trimmed = line.trim();
+    let is_header = !line.starts_with(char::is_whitespace)
+                    && !trimmed.is_empty()
+                    && !trimmed.starts_with('-')
+                    && !trimmed.starts_with('%');
+    if is_header { INCLUDE_SECTION.is_match(line) } else { allowed }
+}
+
+// Returns the error for the first `%include` line of `doc` that is in an
+// options or arguments section, if any.
+fn unresolved_include(doc: &str) -> Option<String> {
+    let mut allowed = false;
+    for (i, line) in doc.lines().enumerate() {
+        allowed = includes_allowed(line, allowed);
+        match include_name(line.trim()) {
+            Some(name) if allowed => {
+                return Some(format!(
+                    "'%include {}' on line {} is not resolved. Includes are \
+                     resolved with 'Docopt::include_resolver'.", name, i + 1))
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Returns the name in an `%include NAME` line, which is empty if it's
+// missing. `line` must be trimmed.
+fn include_name(line: &str) -> Option<&str> {
+    if line == "%include" {
+        Some("")
+    } else if line.starts_with("%include")
+            && line["%include".len()..].starts_with(char::is_whitespace) {
+        Some(line["%include".len()..].trim())
+    } else {
+        None
+    }
+}
+
+// Quotes each string in `items` and joins them into a list like
+// `'a', 'b' and 'c'`, where the last two are joined with `conj`.
+pub fn quote_list(items: &[String], conj: &str) -> String {
+    let quoted: Vec<String> =
+        items.iter().map(|s| format!("'{}'", s)).collect();
+    match quoted.len() {
+        0 => String::new(),
+        1 => quoted[0].clone(),
+        n => {
+            format!("{} {} {}", quoted[..n - 1].join(", "), conj, quoted[n - 1])
+        }
     }
 }
 
@@ -197,8 +197,10 @@
 //! fn main() {
 //!     let argv = || vec!["cp", "-a", "file1", "file2", "dest/"];
 //!
-//!     // Your `Args` struct has a single static method defined on it,
-//!     // `docopt`, which will return a normal `Docopt` value.
+//!     // Your `Args` struct has a static method defined on it, `docopt`,
+//!     // which will return a normal `Docopt` value. The usage string
+//!     // itself is available as `Args::USAGE`, and `Args::help()` returns
+//!     // the help message shown for `--help`.
 //!     let args: Args = Args::docopt().decode().unwrap_or_else(|e| e.exit());
 //!
 //!     // Now access your argv values.
@@ -209,6 +211,36 @@
 //!     assert_eq!(args.arg_dest, s(""));
 //! }
 //! ```
+//!
+//! An annotated field named `cmd`, or with a `OneOf` type, is added to the
+//! struct even though it doesn't correspond to a key. It holds an enum that
+//! is decoded from the commands or the group of atoms that were given, as
+//! described in `ArgvMap::decode`:
+//!
+//! ```ignore
+//! docopt!(Args, "Usage: prog (--stdin | --file=<f>)",
+//!         input: OneOf<Input>);
+//!
+//! #[derive(RustcDecodable)]
+//! enum Input { Stdin, File(String) }
+//! ```
+//!
+//! A type annotation can also give a conversion function for a field whose
+//! type doesn't implement `Decodable`. The field is decoded with its
+//! inferred type, and a reference to that value is passed to the function,
+//! which must return a `Result` whose error implements `Debug`:
+//!
+//! ```ignore
+//! docopt!(Args, "Usage: prog --level=<level>",
+//!         flag_level: Level = Level::from_str);
+//! ```
+//!
+//! To be warned at compile time about usage strings with lines that are too
+//! wide, pass a maximum width to the plugin:
+//!
+//! ```ignore
+//! #![plugin(docopt_macros(max_width = 80))]
+//! ```
 
 #![crate_name = "docopt"]
 #![doc(html_root_url = "http://burntsushi.net/rustdoc/docopt")]
@@ -221,7 +253,12 @@ extern crate regex;
 extern crate rustc_serialize;
 extern crate strsim;
 
-pub use dopt::{ArgvMap, Decoder, Docopt, Error, Value};
+pub use dopt::{
+    ArgvMap, BytesEncoding, Decoder, Docopt, DuplicateKeys, Error, HelpLayout,
+    HelpStyle, OneOf, Parsed, Value, ValueKind,
+};
+pub use man::ManMeta;
+pub use shellwords::{QuoteStyle, reconstruct_command_line, shellwords};
 
 macro_rules! werr(
     ($($arg:tt)*) => ({
@@ -238,9 +275,14 @@ fn cap_or_empty<'t>(caps: &regex::Captures<'t>, name: &str) -> &'t str {
     caps.name(name).map_or("", |m| m.as_str())
 }
 
+#[cfg(feature = "devtools")]
+pub mod devtools;
+mod color;
 mod dopt;
+mod man;
 #[doc(hidden)]
 pub mod parse;
+mod shellwords;
 mod synonym;
 #[cfg(test)]
 mod test;
@@ -0,0 +1,145 @@
+use parse::{Atom, Parser};
+
+/// Information for the header and footer of a man page.
+///
+/// This is used with `Docopt::man_page`.
+#[derive(Clone, Debug, Default)]
+pub struct ManMeta {
+    /// The program name, used in the title and the NAME section.
+    pub name: String,
+    /// The manual section, e.g., `1`.
+    pub section: String,
+    /// The program version, shown in the footer.
+    pub version: String,
+    /// The date of the last change to the man page, shown in the footer.
+    pub date: String,
+}
+
+pub fn man_page(p: &Parser, meta: &ManMeta) -> String {
+    let mut out = String::new();
+    out.push_str(&format!(".TH {} {} {} {}\n",
+                          quote(&meta.name.to_uppercase()),
+                          quote(&meta.section),
+                          quote(&meta.date),
+                          quote(format!("{} {}", meta.name, meta.version).trim())));
+
+    // The prose before `Usage:` is split into a one line summary and the
+    // paragraphs that follow it.
+    let prose = p.full_doc.find(&*p.usage_block)
+                          .map_or("", |i| &p.full_doc[..i]);
+    let mut lines = prose.lines().map(str::trim).skip_while(|l| l.is_empty());
+    let summary = lines.next().unwrap_or("");
+    let mut paragraphs: Vec<Vec<&str>> = vec![vec![]];
+    for line in lines {
+        if line.is_empty() {
+            paragraphs.push(vec![]);
+        } else {
+            paragraphs.last_mut().unwrap().push(line);
+        }
+    }
+    paragraphs.retain(|para| !para.is_empty());
+
+    out.push_str(".SH NAME\n");
+    out.push_str(&escape(&meta.name));
+    if !summary.is_empty() {
+        out.push_str(" \\- ");
+        out.push_str(&escape_text(summary));
+    }
+    out.push('\n');
+
+    out.push_str(".SH SYNOPSIS\n.nf\n");
+    for line in p.usage_block[p.usage_prog_offset..].lines() {
+        let line = line.trim();
+        let rest = if line.starts_with(&*p.program) {
+            &line[p.program.len()..]
+        } else {
+            continue
+        };
+        out.push_str(&format!("\\fB{}\\fR{}\n",
+                              escape(&p.program), escape(rest)));
+    }
+    out.push_str(".fi\n");
+
+    if !paragraphs.is_empty() {
+        out.push_str(".SH DESCRIPTION\n");
+        for (i, para) in paragraphs.iter().enumerate() {
+            if i > 0 {
+                out.push_str(".PP\n");
+            }
+            for line in para {
+                out.push_str(&escape_text(line));
+                out.push('\n');
+            }
+        }
+    }
+
+    let options = option_lines(p);
+    if !options.is_empty() {
+        out.push_str(".SH OPTIONS\n");
+        for (flags, help) in options {
+            out.push_str(&format!(".TP\n\\fB{}\\fR\n", escape(&flags)));
+            if !help.is_empty() {
+                out.push_str(&escape_text(&help));
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+// Finds the option descriptions in the order they appear in the doc, and
+// returns the flag column of each along with its full help text.
+fn option_lines(p: &Parser) -> Vec<(String, String)> {
+    let after = p.full_doc.find(&*p.usage_block).map_or("", |i| {
+        &p.full_doc[i + p.usage_block.len()..]
+    });
+    let mut found = vec![];
+    for line in after.lines() {
+        let mut line = line.trim();
+        if line.is_char_boundary(8) && line[..8].to_lowercase() == "options:" {
+            line = line[8..].trim();
+        }
+        if !line.starts_with('-') {
+            continue;
+        }
+        let flags = line.find("  ").map_or(line, |i| &line[..i]).trim();
+        let first = flags.split(|c| c == ' ' || c == ',' || c == '=')
+                         .next().unwrap();
+        if let Some(opts) = p.descs.find(&Atom::new(first)) {
+            found.push((flags.to_string(), opts.help.clone()));
+        }
+    }
+    found
+}
+
+// Quotes a macro argument.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s).replace("\"", "\\(dq"))
+}
+
+// Escapes a line of text that starts a line in the output.
+fn escape_text(s: &str) -> String {
+    let s = escape(s);
+    if s.starts_with('.') || s.starts_with('\'') {
+        format!("\\&{}", s)
+    } else {
+        s
+    }
+}
+
+// Escapes backslashes and hyphens for roff.
+//
+// A backslash starts an escape sequence in roff, so it is written as `\e`.
+// A plain `-` may be rendered as a hyphen, which can't be copied into a
+// shell, so it is written as `\-`.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\e"),
+            '-' => out.push_str("\\-"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -47,11 +47,13 @@ use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry::{Vacant, Occupied};
 use std::cmp::Ordering;
 use std::fmt;
+use std::rc::Rc;
 use regex;
 use regex::Regex;
 use strsim::levenshtein;
 
 use dopt::Value::{self, Switch, Counted, Plain, List};
+use dopt::quote_list;
 use synonym::SynonymMap;
 use cap_or_empty;
 
@@ -62,28 +64,68 @@ macro_rules! err(
 #[derive(Clone)]
 pub struct Parser {
     pub program: String,
+    /// The Docopt string exactly as it was given.
+    pub source: String,
+    /// The Docopt string without a leading byte order mark and with `\r\n`
+    /// line endings replaced by `\n`. Everything else is parsed from this.
     pub full_doc: String,
     pub usage: String,
+    /// The usage section as it appears in `full_doc`, including the
+    /// indentation preceding the `Usage:` header.
+    pub usage_block: String,
+    /// The offset in `usage_block` of the program name in the first usage
+    /// pattern.
+    pub usage_prog_offset: usize,
     pub descs: SynonymMap<Atom, Options>,
+    desc_order: Vec<Atom>, // described options in the order they're written
     usages: Vec<Pattern>,
     last_atom_added: Option<Atom>, // context for [default: ...]
+    last_desc_indent: Option<usize>, // context for continuation lines
+    simple: Option<Vec<Atom>>, // see `simple_atoms`
 }
 
 impl Parser {
-    pub fn new(doc: &str) -> Result<Parser, String> {
+    pub fn new(source: &str) -> Result<Parser, UsageError> {
+        let doc = source.trim_left_matches('\u{feff}').replace("\r\n", "\n");
         let mut d = Parser {
             program: String::new(),
-            full_doc: doc.into(),
+            source: source.into(),
+            full_doc: doc.clone(),
             usage: String::new(),
+            usage_block: String::new(),
+            usage_prog_offset: 0,
             usages: vec!(),
             descs: SynonymMap::new(),
+            desc_order: vec!(),
             last_atom_added: None,
+            last_desc_indent: None,
+            simple: None,
         };
-        try!(d.parse(doc));
+        try!(d.parse(&doc));
+        d.simple = d.simple_atoms();
         Ok(d)
     }
 
     pub fn matches(&self, argv: &Argv) -> Option<SynonymMap<String, Value>> {
+        let atoms = match self.simple {
+            None => return self.matches_general(argv),
+            Some(ref atoms) => atoms,
+        };
+        self.matches_simple(atoms, argv)
+    }
+
+    // Checks that `matches_simple`, when it's used, agrees with the general
+    // matcher.
+    #[cfg(test)]
+    pub fn check_matchers(&self, argv: &Argv) {
+        if let Some(ref atoms) = self.simple {
+            assert_eq!(self.matches_simple(atoms, argv),
+                       self.matches_general(argv));
+        }
+    }
+
+    fn matches_general(&self, argv: &Argv)
+                      -> Option<SynonymMap<String, Value>> {
         for usage in &self.usages {
             match Matcher::matches(argv, usage) {
                 None => continue,
@@ -93,19 +135,334 @@ impl Parser {
         None
     }
 
-    pub fn parse_argv(&self, argv: Vec<String>, options_first: bool)
-                         -> Result<Argv, String> {
-        Argv::new(self, argv, options_first)
+    // Matches `argv` against a usage that is a fixed sequence of distinct
+    // positional arguments and commands (see `simple_atoms`). Since there
+    // are no choices to make, each element of argv is simply paired with
+    // the atom at the same position. This skips the backtracking of the
+    // general matcher, but still builds the same map of values.
+    fn matches_simple(&self, atoms: &[Atom], argv: &Argv)
+                     -> Option<SynonymMap<String, Value>> {
+        if !argv.flags.is_empty() || argv.positional.len() != atoms.len() {
+            return None
+        }
+        let mut vals = HashMap::with_capacity(atoms.len());
+        for (atom, tok) in atoms.iter().zip(&argv.positional) {
+            let arg = match tok.atom {
+                Positional(ref arg) => arg,
+                _ => return None,
+            };
+            let val = match *atom {
+                Command(ref name) if name == arg => Switch(true),
+                Command(_) => return None,
+                _ => Plain(Some(arg.clone())),
+            };
+            vals.insert(atom.clone(), val);
+        }
+        Some(self.synonym_map(vals))
+    }
+
+    // Returns the atoms of the usage if it's a single pattern made of only
+    // positional arguments and commands, with none of them optional or
+    // repeated (like `prog add <name> <url>`), and there are no flags at
+    // all. Such a usage can be matched without the general matcher.
+    fn simple_atoms(&self) -> Option<Vec<Atom>> {
+        fn flatten(p: &Pattern, atoms: &mut Vec<Atom>) -> bool {
+            match *p {
+                Alternates(ref ps) if ps.len() == 1 => flatten(&ps[0], atoms),
+                Sequence(ref ps) => ps.iter().all(|p| flatten(p, atoms)),
+                PatAtom(ref a @ Positional(_)) => {
+                    atoms.push(a.clone());
+                    true
+                }
+                PatAtom(ref a @ Command(_)) if *a != double_dash() => {
+                    atoms.push(a.clone());
+                    true
+                }
+                _ => false,
+            }
+        }
+        if self.usages.len() != 1 {
+            return None
+        }
+        let mut atoms = vec![];
+        if !flatten(&self.usages[0], &mut atoms) {
+            return None
+        }
+        let simple = self.descs.iter().all(|(atom, opts)| {
+            !opts.repeats && match *atom {
+                Positional(_) | Command(_) => true,
+                Short(_) | Long(_) => false,
+            }
+        });
+        if simple { Some(atoms) } else { None }
+    }
+
+    pub fn parse_argv<'a>(&'a self, argv: Vec<String>,
+                          settings: &ArgvSettings<'a>)
+                         -> Result<Argv<'a>, String> {
+        Argv::new(self, argv, settings)
+    }
+
+    /// For each usage pattern with the positional argument `atom`, returns
+    /// the positional arguments and commands that come before it.
+    pub fn positionals_before(&self, atom: &Atom) -> Vec<Vec<Atom>> {
+        let mut found = vec![];
+        for usage in &self.usages {
+            let mut order = vec![];
+            usage.atoms_in_order(&mut order);
+            if let Some(i) = order.iter().position(|a| a == atom) {
+                found.push(order[..i].iter().filter(|a| match **a {
+                    Positional(_) | Command(_) => true,
+                    _ => false,
+                }).cloned().collect());
+            }
+        }
+        found
+    }
+
+    /// Returns the fewest and the most positional arguments required by any
+    /// single usage pattern.
+    pub fn required_positional_count(&self) -> (usize, usize) {
+        let counts = self.usages.iter().map(|p| p.required_positionals());
+        let min = counts.clone().map(|c| c.0).min().unwrap_or(0);
+        let max = counts.map(|c| c.1).max().unwrap_or(0);
+        (min, max)
+    }
+
+    /// Returns the required atoms that `argv` doesn't give, for the usage
+    /// pattern that it comes closest to matching.
+    ///
+    /// An atom is required if it isn't inside `[...]`. Positional arguments
+    /// and commands are matched against argv from left to right, while a
+    /// flag is given if it appears anywhere. When there are alternatives,
+    /// the one missing the fewest commands is used, and then the one missing
+    /// the fewest atoms.
+    pub fn missing_required(&self, argv: &Argv) -> Vec<Atom> {
+        let mut best: Option<Vec<Atom>> = None;
+        for usage in &self.usages {
+            let mut missing = vec![];
+            usage.missing_required(argv, 0, true, &mut missing);
+            if best.as_ref().map_or(true, |b| fewer_missing(&missing, b)) {
+                best = Some(missing);
+            }
+        }
+        best.unwrap_or(vec![])
+    }
+
+    /// Renders the usage section with at most `max` usage patterns.
+    ///
+    /// The patterns shown are the ones that best match `argv`, in their
+    /// original order. A pattern containing the closest spelling of an
+    /// unknown flag in `argv` is always shown. If any patterns are left out,
+    /// a final line says how many.
+    pub fn elided_usage(&self, argv: &[String], max: usize) -> String {
+        let n = self.usages.len();
+        if n <= max {
+            return self.usage_block.clone();
+        }
+        let scores = self.usage_scores(argv);
+        let mut keep: Vec<usize> = vec![];
+        for atom in self.closest_unknown_flags(argv) {
+            let best = (0..n).filter(|&i| self.usages[i].all_atoms().contains(&atom))
+                             .max_by_key(|&i| (scores[i], n - i));
+            if let Some(i) = best {
+                if !keep.contains(&i) && keep.len() < max {
+                    keep.push(i);
+                }
+            }
+        }
+        let mut ranked: Vec<usize> = (0..n).collect();
+        ranked.sort_by(|&a, &b| (scores[b], a).cmp(&(scores[a], b)));
+        for i in ranked {
+            if keep.len() >= max {
+                break;
+            }
+            if !keep.contains(&i) {
+                keep.push(i);
+            }
+        }
+
+        let block = &*self.usage_block;
+        let first = block[..self.usage_prog_offset].matches('\n').count();
+        let mut lines = vec![];
+        let mut indent = "";
+        for (i, line) in block.lines().enumerate() {
+            if i < first {
+                lines.push(line.to_string());
+            } else if keep.contains(&(i - first)) {
+                lines.push(line.to_string());
+                indent = &line[..indentation(line)];
+            } else if i == first {
+                // Keep the `Usage:` header even if its pattern is dropped.
+                let start = block[..self.usage_prog_offset]
+                                .rfind('\n').map_or(0, |i| i + 1);
+                let header = block[start..self.usage_prog_offset].trim_right();
+                if !header.is_empty() {
+                    lines.push(header.to_string());
+                }
+            }
+        }
+        lines.push(format!("{}... and {} more forms (run with --help to see all)",
+                           indent, n - keep.len()));
+        lines.join("\n")
+    }
+
+    // Scores each usage pattern by how many tokens in `argv` it mentions.
+    // Flags and commands that appear in a pattern count twice as much as a
+    // close misspelling of a flag in the pattern.
+    fn usage_scores(&self, argv: &[String]) -> Vec<usize> {
+        let mut exact = vec![];
+        for arg in argv.iter().take_while(|a| &***a != "--") {
+            if Atom::is_long_argv(arg) {
+                let (atom, _) = parse_long_equal_argv(arg);
+                exact.push(self.descs.resolve(&atom));
+            } else if Atom::is_short(arg) {
+                for c in arg[1..].chars() {
+                    let atom = self.descs.resolve(&Short(c));
+                    let has_arg = self.requires_arg(&atom);
+                    exact.push(atom);
+                    if has_arg {
+                        break;
+                    }
+                }
+            } else if Atom::is_cmd(arg) {
+                exact.push(Command(arg.clone()));
+            }
+        }
+        let close = self.closest_unknown_flags(argv);
+        self.usages.iter().map(|pat| {
+            let atoms = pat.all_atoms();
+            2 * exact.iter().filter(|a| atoms.contains(a)).count()
+            + close.iter().filter(|a| atoms.contains(a)).count()
+        }).collect()
+    }
+
+    // For each unknown long flag in `argv`, returns the closest known flag,
+    // if there is one that is close enough.
+    fn closest_unknown_flags(&self, argv: &[String]) -> Vec<Atom> {
+        let mut found = vec![];
+        for arg in argv.iter().take_while(|a| &***a != "--") {
+            if !Atom::is_long_argv(arg) {
+                continue;
+            }
+            let (atom, _) = parse_long_equal_argv(arg);
+            if self.descs.contains_key(&atom) {
+                continue;
+            }
+            let flag = atom.to_string();
+            let best = self.descs.keys()
+                           .filter(|a| match **a { Long(_) => true, _ => false })
+                           .map(|a| (levenshtein(&flag, &a.to_string()), a))
+                           .filter(|&(dist, _)| dist < 3)
+                           .min_by_key(|&(dist, _)| dist);
+            if let Some((_, a)) = best {
+                found.push(a.clone());
+            }
+        }
+        found
+    }
+
+    /// Returns the value of every atom as if no arguments were given, i.e.,
+    /// the `[default: ...]` value if there is one and an empty value
+    /// otherwise.
+    pub fn default_values(&self) -> SynonymMap<String, Value> {
+        let mut vals = HashMap::new();
+        self.add_default_values(&mut vals);
+        self.synonym_map(vals)
+    }
+
+    /// Returns every atom in the order it first appears in the usage
+    /// patterns, where `[options]` stands for the described options in the
+    /// order they're written. Described options that aren't in any usage
+    /// pattern come last, sorted.
+    pub fn key_order(&self) -> Vec<Atom> {
+        let mut order = vec![];
+        for usage in &self.usages {
+            usage.atoms_in_order(&mut order);
+        }
+        let mut rest: Vec<Atom> =
+            self.options_atoms().into_iter()
+                .filter(|a| !order.contains(a))
+                .collect();
+        rest.sort();
+        order.extend(rest);
+        order
+    }
+
+    /// Returns every atom in `descs` with its options, leaving out
+    /// synonyms, in the same order as `key_order`. Anything else comes
+    /// last, sorted.
+    ///
+    /// Unlike iterating over `descs`, this is the same every time, so it's
+    /// what should be used for anything generated from the atoms.
+    pub fn descs_in_order(&self) -> Vec<(&Atom, &Options)> {
+        let mut order: Vec<Atom> = vec![];
+        for atom in self.key_order() {
+            let atom = self.descs.resolve(&atom);
+            if !order.contains(&atom) {
+                order.push(atom);
+            }
+        }
+        let pos = |atom: &Atom| {
+            order.iter().position(|a| a == atom).unwrap_or(order.len())
+        };
+        let mut descs: Vec<(&Atom, &Options)> = self.descs.iter().collect();
+        descs.sort_by(|&(a, _), &(b, _)| (pos(a), a).cmp(&(pos(b), b)));
+        descs
+    }
+
+    /// Returns the atoms that must be given to match any usage pattern,
+    /// i.e., those outside of `[...]` in every pattern (and in every
+    /// alternative of a `(a | b)` group).
+    pub fn required_atoms(&self) -> HashSet<Atom> {
+        let mut usages = self.usages.iter().map(|p| p.required_atoms());
+        let first = usages.next().unwrap_or(HashSet::new());
+        usages.fold(first, |req, atoms| &req & &atoms)
+    }
+
+    /// Checks that every flag in the usage patterns has an option
+    /// description, and that every option description is used by at least
+    /// one usage pattern (possibly through `[options]`).
+    pub fn check_descs(&self) -> Result<(), String> {
+        let mut used = HashSet::new();
+        for usage in &self.usages {
+            used.extend(usage.all_atoms().into_iter());
+        }
+        let mut undescribed: Vec<Atom> =
+            used.iter()
+                .filter(|a| match **a { Short(_) | Long(_) => true, _ => false })
+                .filter(|a| !self.descs.find(a).map_or(false, |o| o.is_desc))
+                .cloned()
+                .collect();
+        let mut unused: Vec<Atom> =
+            self.options_atoms().into_iter()
+                .filter(|a| !used.contains(a))
+                .collect();
+        if undescribed.is_empty() && unused.is_empty() {
+            return Ok(());
+        }
+        undescribed.sort();
+        unused.sort();
+        fn join(atoms: &[Atom]) -> String {
+            atoms.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")
+        }
+        let mut msgs = vec![];
+        if !undescribed.is_empty() {
+            msgs.push(format!("Flags used in usage patterns but missing an \
+                               option description: {}.", join(&undescribed)));
+        }
+        if !unused.is_empty() {
+            msgs.push(format!("Option descriptions not used in any usage \
+                               pattern: {}.", join(&unused)));
+        }
+        Err(msgs.join("\n"))
     }
 }
 
 impl Parser {
+    // Returns the described options in the order they're written.
     fn options_atoms(&self) -> Vec<Atom> {
-        let mut atoms = vec!();
-        for (atom, _) in self.descs.iter().filter(|&(_, opts)| opts.is_desc) {
-            atoms.push(atom.clone());
-        }
-        atoms
+        self.desc_order.clone()
     }
 
     fn has_arg(&self, atom: &Atom) -> bool {
@@ -115,6 +472,13 @@ impl Parser {
         }
     }
 
+    fn requires_arg(&self, atom: &Atom) -> bool {
+        match self.descs.find(atom) {
+            None => false,
+            Some(opts) => opts.arg.requires_arg(),
+        }
+    }
+
     fn has_repeat(&self, atom: &Atom) -> bool {
         match self.descs.find(atom) {
             None => false,
@@ -122,18 +486,137 @@ impl Parser {
         }
     }
 
-    fn parse(&mut self, doc: &str) -> Result<(), String> {
+    // A toggle is a flag that takes no argument and isn't repeated.
+    fn is_toggle(&self, atom: &Atom) -> bool {
+        match self.descs.find(atom) {
+            None => false,
+            Some(opts) => !opts.arg.has_arg() && !opts.repeats,
+        }
+    }
+
+    // Adds the default value of every atom that isn't in `vs`. Atoms
+    // without a `[default: ...]` get an empty value. The values given for
+    // a list-valued flag are combined with its default as set with
+    // `[default-mode: ...]`.
+    fn add_default_values(&self, vs: &mut HashMap<Atom, Value>) {
+        lazy_static! {
+            static ref SPLIT_SPACE: Regex = regex!(r"\s+");
+        }
+        for (a, opts) in self.descs.iter() {
+            if let Some(v) = vs.get_mut(a) {
+                let def: Vec<String> = match (&opts.arg, opts.sep) {
+                    (&One(Some(ref def)), Some(sep)) => {
+                        def.split(sep).map(String::from).collect()
+                    }
+                    (&One(Some(ref def)), None) if opts.repeats => {
+                        SPLIT_SPACE.split(def).map(String::from).collect()
+                    }
+                    _ => continue,
+                };
+                if let List(ref mut given) = *v {
+                    match opts.default_mode {
+                        DefaultMode::Replace => {}
+                        DefaultMode::Append => {
+                            *given = def.into_iter()
+                                        .chain(given.drain(..))
+                                        .collect();
+                        }
+                        DefaultMode::Prepend => given.extend(def),
+                    }
+                }
+                continue
+            }
+            let atom = a.clone();
+            if let Argument::Optional(_) = opts.arg {
+                // The default of a flag with an optional argument is only
+                // used when the flag is given without one.
+                if opts.repeats || opts.sep.is_some() {
+                    vs.insert(atom, List(vec![]));
+                } else {
+                    vs.insert(atom, Plain(None));
+                }
+                continue
+            }
+            if let (Some(sep), &One(ref def)) = (opts.sep, &opts.arg) {
+                let words = match *def {
+                    None => vec![],
+                    Some(ref v) => v.split(sep).map(String::from).collect(),
+                };
+                vs.insert(atom, List(words));
+                continue
+            }
+            match (opts.repeats, &opts.arg) {
+                (false, &Zero) => {
+                    match *a {
+                        Positional(_) => vs.insert(atom, Plain(None)),
+                        _ => vs.insert(atom, Switch(false)),
+                    };
+                }
+                (true, &Zero) => {
+                    match *a {
+                        Positional(_) => vs.insert(atom, List(vec!())),
+                        _ => vs.insert(atom, Counted(0)),
+                    };
+                }
+                (false, &One(None)) => { vs.insert(atom, Plain(None)); }
+                (true, &One(None)) => { vs.insert(atom, List(vec!())); }
+                (false, &One(Some(ref v))) => {
+                    vs.insert(atom, Plain(Some(v.clone())));
+                }
+                (true, &One(Some(ref v))) => {
+                    let words = SPLIT_SPACE
+                                .split(v)
+                                .map(|s| s.to_owned())
+                                .collect();
+                    vs.insert(atom, List(words));
+                }
+                (_, &Argument::Optional(_)) => unreachable!(),
+            }
+        }
+    }
+
+    // Build a synonym map so that it's easier to look up values.
+    fn synonym_map(&self, vals: HashMap<Atom, Value>)
+                  -> SynonymMap<String, Value> {
+        let mut synmap: SynonymMap<String, Value> =
+            vals.into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect();
+        for (from, to) in self.descs.synonyms() {
+            let (from, to) = (from.to_string(), to.to_string());
+            if synmap.contains_key(&to) {
+                synmap.insert_synonym(from, to);
+            }
+        }
+        synmap
+    }
+
+    fn parse(&mut self, doc: &str) -> Result<(), UsageError> {
         lazy_static! {
             static ref MUSAGE: Regex = Regex::new(
-                r"(?s)(?i:usage):\s*(?P<prog>\S+)(?P<pats>.*?)(?:$|\n\s*\n)"
+                r"(?s)(?i:usage):[ \t]*(?:\r?\n[ \t]*)*(?P<prog>\S*)(?P<pats>.*?)(?:$|\n\s*\n)"
             ).unwrap();
         }
         let caps = match MUSAGE.captures(doc) {
-            None => err!("Could not find usage patterns in doc string."),
+            None => {
+                return Err(UsageError {
+                    message: "No usage patterns found: the doc string has \
+                              no 'Usage:' section.".into(),
+                    location: None,
+                })
+            }
             Some(caps) => caps,
         };
-        if cap_or_empty(&caps, "prog").is_empty() {
-            err!("Could not find program name in doc string.")
+        // The program name may follow blank lines after `Usage:`, but if
+        // the next thing is the header of another section (like
+        // `Options:`), then the usage section is empty.
+        let prog = cap_or_empty(&caps, "prog");
+        if prog.is_empty() || prog.ends_with(':') {
+            let start = caps.get(0).unwrap().start();
+            return Err(self.error_at(doc, start, "usage:".len(), format!(
+                "No usage patterns found: the 'Usage:' section is empty. \
+                 Usage patterns must come after 'Usage:', before any other \
+                 section.")))
         }
         self.program = cap_or_empty(&caps, "prog").to_string();
         self.usage = caps[0].to_string();
@@ -151,12 +634,23 @@ impl Parser {
         // includes `Options: -a ...`, which means some lines not beginning
         // with `-` can actually have options.
         let (pstart, pend) = caps.get(0).map(|m|(m.start(), m.end())).unwrap();
+        // Only reach back to the start of the line if `Usage:` is preceded
+        // by nothing but indentation.
+        let lstart = doc[..pstart].rfind('\n').map_or(0, |i| i + 1);
+        let bstart =
+            if doc[lstart..pstart].trim().is_empty() { lstart } else { pstart };
+        self.usage_block = doc[bstart..pend].trim_right().to_string();
+        self.usage_prog_offset = caps.name("prog").unwrap().start() - bstart;
         let (before, after) = (&doc[..pstart], &doc[pend..]);
         // We process every line here (instead of restricting to lines starting
         // with "-") because we need to check every line for a default value.
         // The default value always belongs to the most recently defined desc.
         for line in before.lines().chain(after.lines()) {
-            try!(self.parse_desc(line));
+            if let Err(msg) = self.parse_desc(line) {
+                let (start, len) = desc_span(line);
+                let start = offset_of(doc, line) + start;
+                return Err(self.error_at(doc, start, len, msg));
+            }
         }
 
         let mprog = format!(
@@ -165,12 +659,17 @@ impl Parser {
         let pats = Regex::new(&*mprog).unwrap();
 
         if cap_or_empty(&caps, "pats").is_empty() {
-            let pattern = try!(PatParser::new(self, "").parse());
+            let pats_start = caps.name("pats").unwrap().start();
+            let pattern = try!(self.parse_pattern(doc, pats_start, ""));
             self.usages.push(pattern);
         } else {
             for line in cap_or_empty(&caps, "pats").lines() {
-                for pat in pats.captures_iter(line.trim()) {
-                    let pattern = try!(PatParser::new(self, &pat[1]).parse());
+                let line = line.trim();
+                for pat in pats.captures_iter(line) {
+                    let m = pat.get(1).unwrap();
+                    let start = offset_of(doc, line) + m.start();
+                    let pattern =
+                        try!(self.parse_pattern(doc, start, m.as_str()));
                     self.usages.push(pattern);
                 }
             }
@@ -178,6 +677,33 @@ impl Parser {
         Ok(())
     }
 
+    // Parses a single usage pattern found at offset `start` in `doc`.
+    fn parse_pattern(&mut self, doc: &str, start: usize, pat: &str)
+                    -> Result<Pattern, UsageError> {
+        let result = {
+            let mut p = PatParser::new(self, pat);
+            p.parse().map_err(|msg| (msg, p.span()))
+        };
+        result.map_err(|(msg, (pstart, len))| {
+            self.error_at(doc, start + pstart, len, msg)
+        })
+    }
+
+    /// Locates the `len` bytes of text at offset `start` in `full_doc`.
+    pub fn locate(&self, start: usize, len: usize) -> Location {
+        Location::new(&self.source, &self.full_doc, start, len)
+    }
+
+    // Builds an error for the `len` bytes of text at offset `start` in
+    // `doc`.
+    fn error_at(&self, doc: &str, start: usize, len: usize, msg: String)
+               -> UsageError {
+        UsageError {
+            message: msg,
+            location: Some(Location::new(&self.source, doc, start, len)),
+        }
+    }
+
     fn parse_desc(&mut self, full_desc: &str) -> Result<(), String> {
         lazy_static! {
             static ref OPTIONS: Regex = regex!(r"^\s*(?i:options:)\s*");
@@ -185,27 +711,43 @@ impl Parser {
             static ref REMOVE_DESC: Regex = regex!(r"  .*$");
             static ref NORMALIZE_FLAGS: Regex = regex!(r"([^-\s]), -");
             static ref FIND_FLAGS: Regex = regex!(r"(?x)
-                (?:(?P<long>--[^\x20\t=]+)|(?P<short>-[^\x20\t=]+))
-                (?:(?:\x20|=)(?P<arg>[^.-]\S*))?
+                (?:(?P<long>--[^\x20\t=\[]+)|(?P<short>-[^\x20\t=]+))
+                (?:
+                    \[=(?P<optarg>[^\]\x20\t]+)\]
+                    |(?:\x20|=)(?P<arg>[^.-]\S*)
+                )?
                 (?P<repeated>\x20\.\.\.)?
             ");
         }
+        if full_desc.trim().starts_with("%include") {
+            // Left for `Docopt::include_resolver`.
+            self.last_desc_indent = None;
+            return Ok(())
+        }
         let desc = OPTIONS.replace(full_desc.trim(), "");
         let desc = &*desc;
         if !ISFLAG.is_match(desc) {
+            let continued = self.parse_desc_continuation(full_desc);
             try!(self.parse_default(full_desc));
-            return Ok(())
+            if continued {
+                try!(self.parse_split_default());
+            }
+            try!(self.parse_sep(full_desc));
+            try!(self.parse_default_mode(full_desc));
+            try!(self.parse_choices(full_desc));
+            return self.check_default_choices()
         }
 
         // Get rid of the description, which must be at least two spaces
         // after the flag or argument.
+        let help = desc.find("  ").map_or("", |i| desc[i..].trim());
         let desc = REMOVE_DESC.replace(desc, "");
         // Normalize `-x, --xyz` to `-x --xyz`.
         let desc = NORMALIZE_FLAGS.replace(&desc, "$1 -");
         let desc = desc.trim();
 
         let (mut short, mut long) = <(String, String)>::default();
-        let mut has_arg = false;
+        let mut arg = Zero;
         let mut last_end = 0;
         let mut repeated = false;
         for flags in FIND_FLAGS.captures_iter(desc) {
@@ -233,15 +775,25 @@ impl Parser {
                 }
                 long = l.into()
             }
-            if let Some(arg) = flags.name("arg").map(|m| m.as_str()) {
-                if !arg.is_empty() {
-                    if !Atom::is_arg(arg) {
+            if let Some(a) = flags.name("arg").map(|m| m.as_str()) {
+                if !a.is_empty() {
+                    if !Atom::is_arg(a) {
                         err!("Argument '{}' is not of the form ARG or <arg>.",
-                             arg)
+                             a)
                     }
-                    has_arg = true; // may be changed to default later
+                    arg = One(None); // may be changed to default later
                 }
             }
+            if let Some(a) = flags.name("optarg").map(|m| m.as_str()) {
+                if !Atom::is_arg(a) {
+                    err!("Argument '{}' is not of the form ARG or <arg>.", a)
+                }
+                if l.is_empty() {
+                    err!("Only long flags can have an optional argument, \
+                          but found '{}'.", flags.get(0).unwrap().as_str())
+                }
+                arg = Argument::Optional(None);
+            }
         }
         // Make sure that we consumed everything. If there are leftovers,
         // then there is some malformed description. Alert the user.
@@ -250,17 +802,74 @@ impl Parser {
             err!("Extraneous text '{}' in option description '{}'.",
                  &desc[last_end..], desc)
         }
-        try!(self.add_desc(&short, &long, has_arg, repeated));
+        self.last_desc_indent = Some(indentation(full_desc));
+        try!(self.add_desc(&short, &long, arg, repeated, help));
         // Looking for default in this line must come after adding the
         // description, otherwise `parse_default` won't know which option
         // to assign it to.
-        self.parse_default(full_desc)
+        try!(self.parse_default(full_desc));
+        try!(self.parse_sep(full_desc));
+        try!(self.parse_default_mode(full_desc));
+        try!(self.parse_choices(full_desc));
+        self.check_default_choices()
+    }
+
+    // A line indented deeper than the option description before it continues
+    // that description. Anything else ends it. Returns whether `line` was a
+    // continuation.
+    fn parse_desc_continuation(&mut self, line: &str) -> bool {
+        let start = match self.last_desc_indent {
+            None => return false,
+            Some(start) => start,
+        };
+        if line.trim().is_empty() || indentation(line) <= start {
+            self.last_desc_indent = None;
+            return false;
+        }
+        let atom = self.last_atom_added.as_ref()
+                       .expect("BUG: continuation without an option");
+        let opts = self.descs.find_mut(atom)
+                       .expect("BUG: last opt desc key is invalid.");
+        if !opts.help.is_empty() {
+            opts.help.push(' ');
+        }
+        opts.help.push_str(line.trim());
+        true
+    }
+
+    // Looks for a `[default: ...]` that was split across the lines of a
+    // multi-line option description, e.g., `[default:` at the end of one
+    // line and the value on the next.
+    fn parse_split_default(&mut self) -> Result<(), String> {
+        lazy_static! {
+            static ref SPLIT_DEFAULT: Regex = regex!(
+                r"\[(?i:default):(?:[^\[\]]|\[[^\[\]]*\])*\]"
+            );
+        }
+        let found = {
+            let atom = self.last_atom_added.as_ref()
+                           .expect("BUG: continuation without an option");
+            let opts = self.descs.find(atom)
+                           .expect("BUG: last opt desc key is invalid.");
+            match opts.arg {
+                One(None) | Argument::Optional(None) => {}
+                _ => return Ok(()),
+            }
+            match SPLIT_DEFAULT.find(&opts.help) {
+                None => return Ok(()),
+                Some(m) => m.as_str().to_string(),
+            }
+        };
+        self.parse_default(&found)
     }
 
     fn parse_default(&mut self, desc: &str) -> Result<(), String> {
+        // The value stops at the first unbalanced `]`, so that annotations
+        // after the default are not swallowed by it. One level of brackets
+        // is allowed in the value itself, e.g., `[default: [0-9]+]`.
         lazy_static! {
             static ref FIND_DEFAULT: Regex = regex!(
-                r"\[(?i:default):(?P<val>.*)\]"
+                r"\[(?i:default):(?P<val>(?:[^\[\]]|\[[^\[\]]*\])*)\]"
             );
         }
         let defval =
@@ -279,17 +888,170 @@ impl Parser {
             .find_mut(last_atom)
             .expect(&*format!("BUG: last opt desc key ('{:?}') is invalid.",
                               last_atom));
-        match opts.arg {
-            One(None) => {}, // OK
+        opts.arg = match opts.arg {
+            One(None) => One(Some(defval.into())),
+            Argument::Optional(None) =>
+                Argument::Optional(Some(defval.into())),
             Zero =>
                 err!("Cannot assign default value '{}' to flag '{}' \
                       that has no arguments.", defval, last_atom),
-            One(Some(ref curval)) =>
+            One(Some(ref curval)) | Argument::Optional(Some(ref curval)) =>
                 err!("Flag '{}' already has a default value \
                       of '{}' (second default value: '{}').",
                      last_atom, curval, defval),
+        };
+        Ok(())
+    }
+
+    fn parse_sep(&mut self, desc: &str) -> Result<(), String> {
+        lazy_static! {
+            static ref FIND_SEP: Regex = regex!(
+                r"\[(?i:sep):\s*(?P<val>[^\]]*?)\s*\]"
+            );
+        }
+        let sep =
+            match FIND_SEP.captures(desc) {
+                None => return Ok(()),
+                Some(c) => cap_or_empty(&c, "val"),
+            };
+        let last_atom =
+            match self.last_atom_added {
+                None => err!("Found separator '{}' in '{}' before first \
+                              option description.", sep, desc),
+                Some(ref atom) => atom,
+            };
+        let mut chars = sep.chars();
+        let c = match (chars.next(), chars.next()) {
+            (Some(c), None) => c,
+            _ => err!("Separator for flag '{}' must be a single character, \
+                       but found '{}'.", last_atom, sep),
+        };
+        let opts =
+            self.descs
+            .find_mut(last_atom)
+            .expect(&*format!("BUG: last opt desc key ('{:?}') is invalid.",
+                              last_atom));
+        if !opts.arg.has_arg() {
+            err!("Cannot assign separator '{}' to flag '{}' \
+                  that has no arguments.", c, last_atom)
+        }
+        if let Some(cur) = opts.sep {
+            err!("Flag '{}' already has a separator of '{}' \
+                  (second separator: '{}').", last_atom, cur, c)
+        }
+        opts.sep = Some(c);
+        Ok(())
+    }
+
+    fn parse_default_mode(&mut self, desc: &str) -> Result<(), String> {
+        lazy_static! {
+            static ref FIND_MODE: Regex = regex!(
+                r"\[(?i:default-mode):\s*(?P<val>[^\]]*?)\s*\]"
+            );
+        }
+        let mode =
+            match FIND_MODE.captures(desc) {
+                None => return Ok(()),
+                Some(c) => cap_or_empty(&c, "val"),
+            };
+        let last_atom =
+            match self.last_atom_added {
+                None => err!("Found default mode '{}' in '{}' before first \
+                              option description.", mode, desc),
+                Some(ref atom) => atom,
+            };
+        let opts =
+            self.descs
+            .find_mut(last_atom)
+            .expect(&*format!("BUG: last opt desc key ('{:?}') is invalid.",
+                              last_atom));
+        if !opts.arg.has_arg() {
+            err!("Cannot assign default mode '{}' to flag '{}' \
+                  that has no arguments.", mode, last_atom)
+        }
+        if !opts.repeats && opts.sep.is_none() {
+            err!("Cannot assign default mode '{}' to flag '{}' \
+                  that has a single value. Mark it as repeated with '...' \
+                  or give it a '[sep: X]'.", mode, last_atom)
+        }
+        opts.default_mode = match &*mode.to_lowercase() {
+            "replace" => DefaultMode::Replace,
+            "append" => DefaultMode::Append,
+            "prepend" => DefaultMode::Prepend,
+            _ => err!("Unknown default mode '{}' for flag '{}'. Expected \
+                       'replace', 'append' or 'prepend'.", mode, last_atom),
+        };
+        Ok(())
+    }
+
+    fn parse_choices(&mut self, desc: &str) -> Result<(), String> {
+        lazy_static! {
+            static ref FIND_CHOICES: Regex = regex!(
+                r"\[(?i:choices):(?P<val>[^\]]*)\]"
+            );
+        }
+        let choices: Vec<String> =
+            match FIND_CHOICES.captures(desc) {
+                None => return Ok(()),
+                Some(c) => {
+                    cap_or_empty(&c, "val").split_whitespace()
+                                           .map(String::from).collect()
+                }
+            };
+        let last_atom =
+            match self.last_atom_added {
+                None => err!("Found choices in '{}' before first option \
+                              description.", desc),
+                Some(ref atom) => atom,
+            };
+        if choices.is_empty() {
+            err!("No choices given for flag '{}'.", last_atom)
+        }
+        let opts =
+            self.descs
+            .find_mut(last_atom)
+            .expect(&*format!("BUG: last opt desc key ('{:?}') is invalid.",
+                              last_atom));
+        if !opts.arg.has_arg() {
+            err!("Cannot assign choices to flag '{}' that has no arguments.",
+                 last_atom)
+        }
+        if !opts.choices.is_empty() {
+            err!("Flag '{}' already has choices of {} (second choices: {}).",
+                 last_atom, quote_list(&opts.choices, "and"),
+                 quote_list(&choices, "and"))
+        }
+        opts.choices = choices;
+        Ok(())
+    }
+
+    // Checks that each value in the default of the most recently defined
+    // desc is one of its choices, if it has both.
+    fn check_default_choices(&self) -> Result<(), String> {
+        let atom = match self.last_atom_added {
+            None => return Ok(()),
+            Some(ref atom) => atom,
+        };
+        let opts = self.descs.find(atom)
+                       .expect("BUG: last opt desc key is invalid.");
+        let def = match opts.arg {
+            One(Some(ref def)) | Argument::Optional(Some(ref def)) => def,
+            _ => return Ok(()),
+        };
+        if opts.choices.is_empty() {
+            return Ok(())
+        }
+        let values: Vec<&str> = match opts.sep {
+            Some(sep) => def.split(sep).collect(),
+            None if opts.repeats => def.split_whitespace().collect(),
+            None => vec![&**def],
+        };
+        for value in values {
+            if !opts.choices.iter().any(|c| c == value) {
+                err!("Default value '{}' for '{}' must be one of {}.",
+                     value, atom, quote_list(&opts.choices, "or"))
+            }
         }
-        opts.arg = One(Some(defval.into()));
         Ok(())
     }
 
@@ -297,18 +1059,24 @@ impl Parser {
         &mut self,
         short: &str,
         long: &str,
-        has_arg: bool,
+        arg: Argument,
         repeated: bool,
+        help: &str,
     ) -> Result<(), String> {
         assert!(!short.is_empty() || !long.is_empty());
         if !short.is_empty() && short.chars().count() != 2 {
             // It looks like the reference implementation just ignores
             // these lines.
+            self.last_desc_indent = None;
             return Ok(());
         }
-        let mut opts = Options::new(
-            repeated, if has_arg { One(None) } else { Zero });
+        let mut opts = Options::new(repeated, arg);
         opts.is_desc = true;
+        opts.help = help.into();
+        let atom = Atom::new(if long.is_empty() { short } else { long });
+        if !self.desc_order.contains(&atom) {
+            self.desc_order.push(atom);
+        }
 
         if !short.is_empty() && !long.is_empty() {
             let (short, long) = (Atom::new(short), Atom::new(long));
@@ -358,18 +1126,128 @@ impl fmt::Debug for Parser {
     }
 }
 
+/// An error in a Docopt string.
+///
+/// When the error can be traced back to a particular piece of the Docopt
+/// string, its location is included. Displaying the error then shows the
+/// offending line with the piece underlined, like this:
+///
+/// ```text
+/// Unclosed group. Expected ']'.
+///  --> line 4, column 27
+///   |
+/// 4 |     prog [--verbose <file>
+///   |                           ^
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsageError {
+    /// A description of the error.
+    pub message: String,
+    /// Where the error was found, if known.
+    pub location: Option<Location>,
+}
+
+/// The location of an error in a Docopt string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// The byte offset of the offending text in the Docopt string exactly as
+    /// it was given (see `Parser::source`).
+    pub offset: usize,
+    /// The length of the offending text in bytes. This is zero when the
+    /// error is about something missing, in which case `offset` is where
+    /// it was expected.
+    pub len: usize,
+    /// The name of the included text that the error is in, or `None` if
+    /// it's in the Docopt string itself. (See `Docopt::include_resolver`.)
+    pub file: Option<String>,
+    /// The line number, starting at `1`.
+    pub line: usize,
+    /// The column in characters (not bytes), starting at `1`.
+    pub column: usize,
+    /// The text of the line, without its line terminator.
+    pub text: String,
+}
+
+impl Location {
+    // Locates the `len` bytes at offset `start` in `doc`, which is the
+    // normalized form of `source`.
+    fn new(source: &str, doc: &str, start: usize, len: usize) -> Location {
+        let lstart = doc[..start].rfind('\n').map_or(0, |i| i + 1);
+        let lend = doc[start..].find('\n').map_or(doc.len(), |i| start + i);
+        let offset = source_offset(source, start);
+        Location {
+            offset: offset,
+            len: source_offset(source, start + len) - offset,
+            file: None,
+            line: doc[..start].matches('\n').count() + 1,
+            column: doc[lstart..start].chars().count() + 1,
+            text: doc[lstart..lend].to_string(),
+        }
+    }
+}
+
+// Maps an offset in the normalized doc back to the source it came from,
+// which may have a byte order mark and `\r\n` line endings.
+fn source_offset(source: &str, offset: usize) -> usize {
+    let bytes = source.as_bytes();
+    let mut i = source.len() - source.trim_left_matches('\u{feff}').len();
+    for _ in 0..offset {
+        if bytes[i] == b'\r' && bytes.get(i + 1) == Some(&b'\n') {
+            i += 1;
+        }
+        i += 1;
+    }
+    i
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.message));
+        let loc = match self.location {
+            None => return Ok(()),
+            Some(ref loc) => loc,
+        };
+        let line = loc.line.to_string();
+        let pad: String = line.chars().map(|_| ' ').collect();
+        // Tabs are kept so that the carets line up with the text above.
+        let indent: String =
+            loc.text.chars().take(loc.column - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+        let (mut carets, mut len) = (String::from("^"), 0);
+        for c in loc.text.chars().skip(loc.column - 1) {
+            len += c.len_utf8();
+            if len >= loc.len {
+                break
+            }
+            carets.push('^');
+        }
+        let file = loc.file.as_ref().map_or(String::new(), |f| {
+            format!("{}, ", f)
+        });
+        write!(f, "\n{}--> {}line {}, column {}\n{} |\n{} | {}\n{} | {}{}",
+               pad, file, loc.line, loc.column, pad, line, loc.text,
+               pad, indent, carets)
+    }
+}
+
 struct PatParser<'a> {
     dopt: &'a mut Parser,
     tokens: Vec<String>, // used while parsing a single usage pattern
+    spans: Vec<(usize, usize)>, // ^^ offset and length of each token
+    end: usize, // ^^ length of the pattern without trailing whitespace
     curi: usize, // ^^ index into pattern chars
     expecting: Vec<char>, // stack of expected ']' or ')'
 }
 
 impl<'a> PatParser<'a> {
     fn new(dopt: &'a mut Parser, pat: &str) -> PatParser<'a> {
+        let tokens = pattern_tokens(pat);
         PatParser {
             dopt: dopt,
-            tokens: pattern_tokens(pat),
+            spans: token_spans(pat, &tokens),
+            end: pat.trim_right().len(),
+            tokens: tokens,
             curi: 0,
             expecting: vec!(),
         }
@@ -490,7 +1368,7 @@ impl<'a> PatParser<'a> {
 
             // The only way for a short option to have an argument is if
             // it's specified in an option description.
-            if !self.dopt.has_arg(&atom) {
+            if !self.dopt.requires_arg(&atom) {
                 self.add_atom_ifnotexists(Zero, &atom);
             } else {
                 // At this point, the flag MUST have an argument. Therefore,
@@ -532,7 +1410,13 @@ impl<'a> PatParser<'a> {
                 // Found `=` in usage, but previous usage of this flag
                 // didn't specify an argument.
                 err!("Flag '{}' does not take any arguments.", atom)
-            } else if !arg.has_arg() && has_arg {
+            } else if !arg.requires_arg() && arg.has_arg()
+                    && self.dopt.requires_arg(&atom) {
+                // Found `[=ARG]` in usage, but previous usage of this flag
+                // requires an argument.
+                err!("Flag '{}' requires an argument, so it cannot be \
+                      given as '{}'.", atom, self.cur())
+            } else if !arg.has_arg() && self.dopt.requires_arg(&atom) {
                 // Didn't find any `=` in usage for this flag, but previous
                 // usage of this flag specifies an argument.
                 // So look for `--flag ARG`
@@ -574,12 +1458,34 @@ impl<'a> PatParser<'a> {
     }
 
     fn positional(&mut self) -> Result<Pattern, String> {
-        let atom = Atom::new(self.cur());
+        let atom = self.same_positional(Atom::new(self.cur()));
         self.add_atom_ifnotexists(Zero, &atom);
         self.next();
         Ok(self.maybe_repeat(PatAtom(atom)))
     }
 
+    // `FILE` and `<file>` are two spellings of the same positional argument.
+    // Whichever is used first is the one that's kept, so that both refer
+    // to a single atom.
+    fn same_positional(&self, atom: Atom) -> Atom {
+        fn upper(s: &str) -> bool {
+            s.chars().all(char::is_uppercase)
+        }
+        let found = match atom {
+            Positional(ref name) => {
+                self.dopt.descs.keys().find(|a| match **a {
+                    Positional(ref other) => {
+                        upper(name) != upper(other)
+                        && name.to_uppercase() == other.to_uppercase()
+                    }
+                    _ => false,
+                }).cloned()
+            }
+            _ => None,
+        };
+        found.unwrap_or(atom)
+    }
+
     fn add_atom_ifnotexists(&mut self, arg: Argument, atom: &Atom) {
         if !self.dopt.descs.contains_key(atom) {
             let opts = Options::new(false, arg);
@@ -622,6 +1528,15 @@ impl<'a> PatParser<'a> {
     fn cur(&self) -> &str {
         &*self.tokens[self.curi]
     }
+    // The offset and length in the pattern of the current token, or an
+    // empty span at the end of the pattern if all tokens were consumed.
+    fn span(&self) -> (usize, usize) {
+        if self.is_eof() {
+            (self.end, 0)
+        } else {
+            self.spans[self.curi]
+        }
+    }
     fn atis(&self, offset: usize, is: &str) -> bool {
         let i = self.curi + offset;
         i < self.tokens.len() && self.tokens[i] == is
@@ -661,12 +1576,45 @@ pub struct Options {
 
     /// Whether it shows up in the "options description" second.
     pub is_desc: bool,
+
+    /// The help text from the option description, with any continuation
+    /// lines joined by a single space. Empty if there is none.
+    pub help: String,
+
+    /// A separator set with `[sep: X]` in the option description. When
+    /// present, the flag's argument is split on it into a list.
+    pub sep: Option<char>,
+
+    /// The values allowed for the flag's argument, set with
+    /// `[choices: a b c]` in the option description. Any value is allowed
+    /// when this is empty.
+    pub choices: Vec<String>,
+
+    /// How the values given for a list-valued flag are combined with its
+    /// default, set with `[default-mode: ...]` in the option description.
+    pub default_mode: DefaultMode,
+}
+
+/// How the values given for a flag that takes a list are combined with the
+/// list from its `[default: ...]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefaultMode {
+    /// The given values replace the default (`[default-mode: replace]`).
+    Replace,
+    /// The given values come after the default (`[default-mode: append]`).
+    Append,
+    /// The given values come before the default
+    /// (`[default-mode: prepend]`).
+    Prepend,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Argument {
     Zero,
     One(Option<String>), // optional default value
+    /// An argument that may be left off, as in `--color[=WHEN]`. The value,
+    /// if any, is used when the flag is given without an argument.
+    Optional(Option<String>),
 }
 
 impl Pattern {
@@ -699,6 +1647,126 @@ impl Pattern {
         add(self, &all_atoms, par);
     }
 
+    // The fewest and the most positional arguments needed to match this
+    // pattern, which differ when it has alternatives.
+    fn required_positionals(&self) -> (usize, usize) {
+        match *self {
+            Alternates(ref ps) => {
+                let counts: Vec<(usize, usize)> =
+                    ps.iter().map(|p| p.required_positionals()).collect();
+                (counts.iter().map(|c| c.0).min().unwrap_or(0),
+                 counts.iter().map(|c| c.1).max().unwrap_or(0))
+            }
+            Sequence(ref ps) => {
+                ps.iter().map(|p| p.required_positionals())
+                         .fold((0, 0), |(min, max), c| (min + c.0, max + c.1))
+            }
+            Optional(_) => (0, 0),
+            Repeat(ref p) => p.required_positionals(),
+            PatAtom(Positional(_)) => (1, 1),
+            PatAtom(_) => (0, 0),
+        }
+    }
+
+    // Walks this pattern against the positional arguments of `argv`,
+    // starting at `i`, and pushes the required atoms that aren't given onto
+    // `missing`. Returns the index of the next positional argument.
+    fn missing_required(&self, argv: &Argv, mut i: usize, required: bool,
+                        missing: &mut Vec<Atom>) -> usize {
+        match *self {
+            Alternates(ref ps) => {
+                let mut best: Option<(Vec<Atom>, usize)> = None;
+                for p in ps.iter() {
+                    let mut m = vec![];
+                    let next = p.missing_required(argv, i, required, &mut m);
+                    if best.as_ref().map_or(true, |b| fewer_missing(&m, &b.0)) {
+                        best = Some((m, next));
+                    }
+                }
+                if let Some((m, next)) = best {
+                    missing.extend(m);
+                    i = next;
+                }
+                i
+            }
+            Sequence(ref ps) => {
+                for p in ps.iter() {
+                    i = p.missing_required(argv, i, required, missing);
+                }
+                i
+            }
+            Optional(ref ps) => {
+                for p in ps.iter() {
+                    i = p.missing_required(argv, i, false, missing);
+                }
+                i
+            }
+            Repeat(ref p) => {
+                i = p.missing_required(argv, i, required, missing);
+                loop {
+                    let next = p.missing_required(argv, i, false, missing);
+                    if next == i {
+                        return i;
+                    }
+                    i = next;
+                }
+            }
+            PatAtom(ref a @ Short(_)) | PatAtom(ref a @ Long(_)) => {
+                if required && !argv.counts.contains_key(a) {
+                    missing.push(a.clone());
+                }
+                i
+            }
+            PatAtom(ref a) => {
+                let dd = double_dash();
+                let skip = *a != dd && argv.positional.get(i)
+                                           .map_or(false, |t| t.atom == dd);
+                let j = if skip { i + 1 } else { i };
+                let given = match (a, argv.positional.get(j)) {
+                    (_, None) => false,
+                    (&Positional(_), Some(tok)) => tok.atom != dd,
+                    (&Command(ref name), Some(tok)) => match tok.atom {
+                        Positional(ref s) | Command(ref s) => s == name,
+                        _ => false,
+                    },
+                    _ => false,
+                };
+                if given {
+                    j + 1
+                } else {
+                    if required {
+                        missing.push(a.clone());
+                    }
+                    i
+                }
+            }
+        }
+    }
+
+    fn required_atoms(&self) -> HashSet<Atom> {
+        match *self {
+            Alternates(ref ps) => {
+                let mut alts = ps.iter().map(|p| p.required_atoms());
+                let first = alts.next().unwrap_or(HashSet::new());
+                alts.fold(first, |req, atoms| &req & &atoms)
+            }
+            Sequence(ref ps) => {
+                let mut req = HashSet::new();
+                for p in ps.iter() {
+                    req.extend(p.required_atoms());
+                }
+                req
+            }
+            Optional(_) => HashSet::new(),
+            Repeat(ref p) => p.required_atoms(),
+            PatAtom(ref a) => {
+                let mut req = HashSet::new();
+                req.insert(a.clone());
+                req
+            }
+        }
+    }
+
     fn all_atoms(&self) -> HashSet<Atom> {
         fn all_atoms(pat: &Pattern, set: &mut HashSet<Atom>) {
             match *pat {
@@ -714,6 +1782,21 @@ impl Pattern {
         set
     }
 
+    // Pushes each atom not already in `order` onto it, from left to right.
+    fn atoms_in_order(&self, order: &mut Vec<Atom>) {
+        match *self {
+            Alternates(ref ps) | Sequence(ref ps) | Optional(ref ps) => {
+                for p in ps.iter() { p.atoms_in_order(order) }
+            }
+            Repeat(ref p) => p.atoms_in_order(order),
+            PatAtom(ref a) => {
+                if !order.contains(a) {
+                    order.push(a.clone());
+                }
+            }
+        }
+    }
+
     fn tag_repeats(&self, map: &mut SynonymMap<Atom, Options>) {
         fn dotag(p: &Pattern,
                  rep: bool,
@@ -864,19 +1947,50 @@ impl fmt::Display for Atom {
 
 impl Options {
     fn new(rep: bool, arg: Argument) -> Options {
-        Options { repeats: rep, arg: arg, is_desc: false, }
+        Options {
+            repeats: rep,
+            arg: arg,
+            is_desc: false,
+            help: String::new(),
+            sep: None,
+            choices: vec![],
+            default_mode: DefaultMode::Replace,
+        }
     }
 }
 
 impl Argument {
-    fn has_arg(&self) -> bool {
+    pub fn has_arg(&self) -> bool {
         match *self {
             Zero => false,
+            One(_) | Argument::Optional(_) => true,
+        }
+    }
+
+    /// Returns true if a flag with this argument must always be given a
+    /// value.
+    pub fn requires_arg(&self) -> bool {
+        match *self {
             One(_) => true,
+            Zero | Argument::Optional(_) => false,
         }
     }
 }
 
+/// How `Parser::parse_argv` matches argv against the Docopt string. See
+/// the fields of `Argv` for what each setting does.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug)]
+pub struct ArgvSettings<'a> {
+    pub options_first: bool,
+    pub plus_minus_toggles: bool,
+    pub strict_short_stacks: bool,
+    pub ignore_case: &'a [Atom],
+    pub aliases: &'a [(Atom, Atom)],
+    pub collect_unknown: bool,
+    pub trailing: &'a [Vec<Atom>],
+}
+
 #[doc(hidden)]
 pub struct Argv<'a> {
     /// A representation of an argv string as an ordered list of tokens.
@@ -885,14 +1999,44 @@ pub struct Argv<'a> {
     /// Same as positional, but contains short and long flags.
     /// Each flag may have an argument string.
     flags: Vec<ArgvToken>,
+    /// Every flag given, in order. Unlike `flags`, a toggle is included
+    /// each time it's given.
+    flag_order: Vec<Atom>,
     /// Counts the number of times each flag appears.
     counts: HashMap<Atom, usize>,
+    /// The final state of each toggle given as `+x` or `-x`, when
+    /// `plus_minus_toggles` is enabled.
+    toggles: HashMap<Atom, bool>,
+    /// Whether `--` was given and appears in a usage pattern.
+    double_dash: bool,
+    /// The index in argv of the first positional argument, if any.
+    first_positional_at: Option<usize>,
 
     // State for parser.
     dopt: &'a Parser,
     argv: Vec<String>,
     curi: usize,
     options_first: bool,
+    plus_minus_toggles: bool,
+    /// Whether a short flag that takes an argument can't be followed by
+    /// other short flags in the same stack, as in `-oab`.
+    strict_short_stacks: bool,
+    /// Flags that match case insensitively.
+    ignore_case: &'a [Atom],
+    /// Old flags that are given in place of a flag in the Docopt string,
+    /// as `(old, new)` pairs.
+    aliases: &'a [(Atom, Atom)],
+    /// Every time an alias was used, as an index into `aliases`.
+    aliases_used: Vec<usize>,
+    /// Whether unknown flags are kept in `unknown` instead of being an
+    /// error.
+    collect_unknown: bool,
+    /// The unknown flags given, in order, when `collect_unknown` is set.
+    unknown: Vec<String>,
+    /// The positional arguments and commands that come before a trailing
+    /// variable argument. Once the positional arguments given match one of
+    /// these, every argument after them is a positional argument.
+    trailing: &'a [Vec<Atom>],
 }
 
 #[derive(Clone, Debug)]
@@ -902,16 +2046,28 @@ struct ArgvToken {
 }
 
 impl<'a> Argv<'a> {
-    fn new(dopt: &'a Parser, argv: Vec<String>, options_first: bool)
+    fn new(dopt: &'a Parser, argv: Vec<String>, settings: &ArgvSettings<'a>)
           -> Result<Argv<'a>, String> {
         let mut a = Argv {
             positional: vec!(),
             flags: vec!(),
+            flag_order: vec!(),
             counts: HashMap::new(),
+            toggles: HashMap::new(),
+            double_dash: false,
+            first_positional_at: None,
             dopt: dopt,
             argv: argv.iter().cloned().collect(),
             curi: 0,
-            options_first: options_first,
+            options_first: settings.options_first,
+            plus_minus_toggles: settings.plus_minus_toggles,
+            strict_short_stacks: settings.strict_short_stacks,
+            ignore_case: settings.ignore_case,
+            aliases: settings.aliases,
+            aliases_used: vec!(),
+            collect_unknown: settings.collect_unknown,
+            unknown: vec!(),
+            trailing: settings.trailing,
         };
         try!(a.parse());
         for flag in &a.flags {
@@ -920,30 +2076,81 @@ impl<'a> Argv<'a> {
                 Occupied(mut v) => { *v.get_mut() += 1; }
             }
         }
+        try!(a.check_aliases());
         Ok(a)
     }
 
+    // Checks that a flag wasn't given under both its old and new names.
+    fn check_aliases(&self) -> Result<(), String> {
+        for &i in &self.aliases_used {
+            let (ref old, ref new) = self.aliases[i];
+            let via_alias = self.aliases_used.iter().filter(|&&j| {
+                self.aliases[j].1 == *new
+            }).count();
+            if self.counts.get(new).map_or(0, |&n| n) > via_alias {
+                err!("Flags '{}' and '{}' cannot both be given, since '{}' \
+                      is an old name for '{}'.", old, new, old, new)
+            }
+        }
+        Ok(())
+    }
+
     fn parse(&mut self) -> Result<(), String> {
         let mut seen_double_dash = false;
         while self.curi < self.argv.len() {
+            if self.in_trailing() {
+                seen_double_dash = true;
+            }
             let do_flags =
                 !seen_double_dash
                 && (!self.options_first || self.positional.is_empty());
 
-            if do_flags && Atom::is_short(self.cur()) {
+            if do_flags && self.plus_minus_toggles
+                    && self.cur().starts_with("+") && self.cur().len() > 1 {
+                let stacked: String = self.cur()[1..].into();
+                for c in stacked.chars() {
+                    let atom = self.resolve_flag(&Short(c));
+                    if !self.dopt.descs.contains_key(&atom) {
+                        err!("Unknown flag: '+{}'", c);
+                    }
+                    if !self.dopt.is_toggle(&atom) {
+                        err!("Flag '{}' cannot be toggled with '+{}'.",
+                             &atom, c);
+                    }
+                    self.push_toggle(atom, true);
+                }
+            } else if do_flags && Atom::is_short(self.cur()) {
                 let stacked: String = self.cur()[1..].into();
                 for (i, c) in stacked.chars().enumerate() {
                     let mut tok = ArgvToken {
-                        atom: self.dopt.descs.resolve(&Short(c)),
+                        atom: self.resolve_flag(&Short(c)),
                         arg: None,
                     };
                     if !self.dopt.descs.contains_key(&tok.atom) {
-                        err!("Unknown flag: '{}'", &tok.atom);
+                        if !self.collect_unknown {
+                            err!("Unknown flag: '{}'", &tok.atom);
+                        }
+                        // Known flags before this one in the stack have
+                        // already been taken, so keep only the rest.
+                        let rest: String = stacked.chars().skip(i).collect();
+                        self.unknown.push(format!("-{}", rest));
+                        break
                     }
-                    if !self.dopt.has_arg(&tok.atom) {
-                        self.flags.push(tok);
+                    if self.plus_minus_toggles
+                            && self.dopt.is_toggle(&tok.atom) {
+                        self.push_toggle(tok.atom, false);
+                    } else if !self.dopt.requires_arg(&tok.atom) {
+                        try!(self.push_flag(tok));
                     } else {
                         let rest = &stacked[i+1..];
+                        if self.strict_short_stacks && !rest.is_empty()
+                                && rest.chars().all(|c| {
+                                    self.dopt.descs.contains_key(&Short(c))
+                                }) {
+                            err!("Flag '{}' requires an argument, but is \
+                                  followed by other flags in '-{}'.",
+                                 &tok.atom, stacked)
+                        }
                         tok.arg = Some(
                             if rest.is_empty() {
                                 let arg = try!(self.next_arg(&tok.atom));
@@ -952,7 +2159,7 @@ impl<'a> Argv<'a> {
                                 rest.into()
                             }
                         );
-                        self.flags.push(tok);
+                        try!(self.push_flag(tok));
                         // We've either produced an error or gobbled up the
                         // rest of these stacked short flags, so stop.
                         break
@@ -960,22 +2167,38 @@ impl<'a> Argv<'a> {
                 }
             } else if do_flags && Atom::is_long_argv(self.cur()) {
                 let (atom, mut arg) = parse_long_equal_argv(self.cur());
-                let atom = self.dopt.descs.resolve(&atom);
+                let atom = self.resolve_flag(&atom);
                 if !self.dopt.descs.contains_key(&atom) {
-                    return self.err_unknown_flag(&atom)
+                    if !self.collect_unknown {
+                        return self.err_unknown_flag(&atom)
+                    }
+                    let unknown = self.cur().to_string();
+                    self.unknown.push(unknown);
+                    self.next();
+                    continue
                 }
                 if arg.is_some() && !self.dopt.has_arg(&atom) {
                     err!("Flag '{}' cannot have an argument, but found '{}'.",
                          &atom, arg.as_ref().unwrap())
-                } else if arg.is_none() && self.dopt.has_arg(&atom) {
+                } else if arg.is_none() && self.dopt.requires_arg(&atom) {
                     try!(self.next_noeof(&*format!("argument for flag '{}'",
                                                    &atom)));
                     arg = Some(self.cur().into());
                 }
-                self.flags.push(ArgvToken { atom: atom, arg: arg });
+                try!(self.push_flag(ArgvToken { atom: atom, arg: arg }));
             } else {
                 if !seen_double_dash && self.cur() == "--" {
                     seen_double_dash = true;
+                    // If `--` appears in a usage pattern, then it is kept
+                    // as a command so that it can be matched. It is never
+                    // the value of a positional argument.
+                    if self.dopt.descs.contains_key(&double_dash()) {
+                        self.double_dash = true;
+                        self.positional.push(ArgvToken {
+                            atom: double_dash(),
+                            arg: None,
+                        });
+                    }
                 } else {
                     // Yup, we *always* insert a positional argument, which
                     // means we completely neglect `Command` here.
@@ -985,6 +2208,9 @@ impl<'a> Argv<'a> {
                         atom: Positional(self.cur().into()),
                         arg: None,
                     };
+                    if self.positional.is_empty() {
+                        self.first_positional_at = Some(self.curi);
+                    }
                     self.positional.push(tok);
                 }
             }
@@ -993,6 +2219,110 @@ impl<'a> Argv<'a> {
         Ok(())
     }
 
+    // Resolves a flag from argv to its canonical atom. If the flag isn't
+    // known, it may still be an alias or match one of the `ignore_case`
+    // flags.
+    fn resolve_flag(&mut self, atom: &Atom) -> Atom {
+        let resolved = self.dopt.descs.resolve(atom);
+        if self.dopt.descs.contains_key(&resolved) {
+            return resolved;
+        }
+        if let Some(i) = self.aliases.iter().position(|a| a.0 == *atom) {
+            self.aliases_used.push(i);
+            return self.aliases[i].1.clone();
+        }
+        let folded = atom.to_string().to_lowercase();
+        let found = self.ignore_case.iter().find(|a| {
+            a.to_string().to_lowercase() == folded
+        });
+        match found {
+            Some(a) => self.dopt.descs.resolve(a),
+            None => resolved,
+        }
+    }
+
+    // Records the state of a toggle. The flag itself is only added once, so
+    // that giving both `+x` and `-x` doesn't count as repeating `x`.
+    fn push_toggle(&mut self, atom: Atom, on: bool) {
+        self.flag_order.push(atom.clone());
+        if self.toggles.insert(atom.clone(), on).is_none() {
+            self.flags.push(ArgvToken { atom: atom, arg: None });
+        }
+    }
+
+    fn push_flag(&mut self, tok: ArgvToken) -> Result<(), String> {
+        if let Some(ref arg) = tok.arg {
+            try!(self.check_choices(&tok.atom, arg));
+        }
+        self.flag_order.push(tok.atom.clone());
+        self.flags.push(tok);
+        Ok(())
+    }
+
+    // Checks that each value in `arg` is one of the choices for `atom`,
+    // if it has any.
+    fn check_choices(&self, atom: &Atom, arg: &str) -> Result<(), String> {
+        let opts = match self.dopt.descs.find(atom) {
+            Some(opts) if !opts.choices.is_empty() => opts,
+            _ => return Ok(()),
+        };
+        let values: Vec<&str> = match opts.sep {
+            Some(sep) => arg.split(sep).collect(),
+            None => vec![arg],
+        };
+        for value in values {
+            if !opts.choices.iter().any(|c| c == value) {
+                err!("Invalid value '{}' for '{}'. It must be one of {}.",
+                     value, atom, quote_list(&opts.choices, "or"))
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the first positional argument given, if any, along with
+    /// every argument that comes after it in argv.
+    pub fn first_positional(&self) -> Option<(&str, &[String])> {
+        self.first_positional_at.map(|i| {
+            (&*self.argv[i], &self.argv[i + 1..])
+        })
+    }
+
+    // Whether the positional arguments given so far are the ones before a
+    // trailing variable argument. This only looks at the positional
+    // arguments, not at the patterns they're in, so once it's true, the
+    // rest of argv is positional even if no pattern ends up matching.
+    fn in_trailing(&self) -> bool {
+        self.trailing.iter().any(|before| {
+            before.len() == self.positional.len()
+            && before.iter().zip(&self.positional).all(|(a, tok)| {
+                match (a, &tok.atom) {
+                    (&Command(ref c), &Positional(ref s)) => c == s,
+                    _ => true,
+                }
+            })
+        })
+    }
+
+    /// Returns the positional arguments and commands given, in the order
+    /// they appear.
+    pub fn positional_order(&self) -> Vec<String> {
+        self.positional.iter().filter_map(|tok| match tok.atom {
+            Positional(ref s) => Some(s.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    /// Returns every flag given, in the order they appear.
+    pub fn flag_order(&self) -> &[Atom] {
+        &self.flag_order
+    }
+
+    /// Returns the unknown flags given, in the order they appear, when
+    /// they are collected instead of being an error.
+    pub fn unknown(&self) -> &[String] {
+        &self.unknown
+    }
+
     fn err_unknown_flag(&self, atom: &Atom) -> Result<(), String> {
         use std::usize::MAX;
         let mut best = String::new();
@@ -1070,7 +2400,119 @@ struct MState {
     argvi: usize, // index into Argv.positional
     counts: HashMap<Atom, usize>, // flags remaining for pattern consumption
     max_counts: HashMap<Atom, usize>, // optional flag appearances
-    vals: HashMap<Atom, Value>,
+    vals: HashMap<Atom, MValue>,
+}
+
+// A value collected while matching.
+//
+// The matcher keeps every intermediate state of a repeated pattern, so the
+// values of a repeated argument are kept in a `ValueList` that all of those
+// states share. Otherwise, matching `<file>...` would copy every file seen
+// so far for each new one.
+#[derive(Clone, Debug, PartialEq)]
+enum MValue {
+    Single(Value),
+    Many(ValueList),
+}
+
+impl MValue {
+    fn into_value(self) -> Value {
+        match self {
+            MValue::Single(v) => v,
+            MValue::Many(vs) => List(vs.to_vec()),
+        }
+    }
+}
+
+// A persistent list of values, to which values can only be appended.
+//
+// Cloning a list is cheap, and a value appended to a clone isn't seen by
+// the original.
+#[derive(Clone)]
+struct ValueList {
+    len: usize,
+    last: Option<Rc<ValueNode>>,
+}
+
+struct ValueNode {
+    value: String,
+    prev: Option<Rc<ValueNode>>,
+}
+
+impl ValueList {
+    fn new() -> ValueList {
+        ValueList { len: 0, last: None }
+    }
+
+    fn push(&mut self, value: String) {
+        let prev = self.last.take();
+        self.last = Some(Rc::new(ValueNode { value: value, prev: prev }));
+        self.len += 1;
+    }
+
+    // Iterates over the values from last to first.
+    fn iter_rev(&self) -> ValueListIter {
+        ValueListIter { node: self.last.as_ref().map(|n| &**n) }
+    }
+
+    fn to_vec(&self) -> Vec<String> {
+        let mut vs: Vec<String> =
+            self.iter_rev().map(|v| v.to_string()).collect();
+        vs.reverse();
+        vs
+    }
+}
+
+impl PartialEq for ValueList {
+    fn eq(&self, other: &ValueList) -> bool {
+        if self.len != other.len {
+            return false;
+        }
+        let same = match (&self.last, &other.last) {
+            (&Some(ref a), &Some(ref b)) => {
+                &**a as *const ValueNode == &**b as *const ValueNode
+            }
+            (&None, &None) => true,
+            _ => false,
+        };
+        same || self.iter_rev().zip(other.iter_rev()).all(|(a, b)| a == b)
+    }
+}
+
+impl fmt::Debug for ValueList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.to_vec().fmt(f)
+    }
+}
+
+impl Drop for ValueList {
+    // The default drop is recursive, which overflows the stack for long
+    // lists. Instead, unlink the nodes that no other list shares one at
+    // a time.
+    fn drop(&mut self) {
+        let mut next = self.last.take();
+        while let Some(node) = next {
+            next = match Rc::try_unwrap(node) {
+                Ok(mut node) => node.prev.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+struct ValueListIter<'a> {
+    node: Option<&'a ValueNode>,
+}
+
+impl<'a> Iterator for ValueListIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.node.map(|node| {
+            self.node = node.prev.as_ref().map(|n| &**n);
+            &*node.value
+        })
+    }
 }
 
 impl MState {
@@ -1078,17 +2520,17 @@ impl MState {
                  -> bool {
         match (arg, rep) {
             (None, false) => {
-                self.vals.insert(key, Switch(true));
+                self.vals.insert(key, MValue::Single(Switch(true)));
             }
             (Some(arg), false) => {
-                self.vals.insert(key, Plain(Some(arg)));
+                self.vals.insert(key, MValue::Single(Plain(Some(arg))));
             }
             (None, true) => {
                 match self.vals.entry(key) {
-                    Vacant(v) => { v.insert(Counted(1)); }
+                    Vacant(v) => { v.insert(MValue::Single(Counted(1))); }
                     Occupied(mut v) => {
                         match *v.get_mut() {
-                            Counted(ref mut c) => { *c += 1; }
+                            MValue::Single(Counted(ref mut c)) => { *c += 1; }
                             _ => return false,
                         }
                     }
@@ -1096,10 +2538,14 @@ impl MState {
             }
             (Some(arg), true) => {
                 match self.vals.entry(key) {
-                    Vacant(v) => { v.insert(List(vec!(arg))); }
+                    Vacant(v) => {
+                        let mut vs = ValueList::new();
+                        vs.push(arg);
+                        v.insert(MValue::Many(vs));
+                    }
                     Occupied(mut v) => {
                         match *v.get_mut() {
-                            List(ref mut vs) => vs.push(arg),
+                            MValue::Many(ref mut vs) => vs.push(arg),
                             _ => return false,
                         }
                     }
@@ -1111,9 +2557,46 @@ impl MState {
 
     fn add_value(&mut self, opts: &Options,
                  spec: &Atom, atom: &Atom, arg: &Option<String>) -> bool {
-        assert!(opts.arg.has_arg() == arg.is_some(),
+        assert!(!opts.arg.requires_arg() || arg.is_some(),
                 "'{:?}' should have an argument but doesn't", atom);
+        assert!(opts.arg.has_arg() || arg.is_none(),
+                "'{:?}' should not have an argument but does", atom);
+        // A flag given without its optional argument takes the default,
+        // as if it had been given with it.
+        let def;
+        let arg = match (&opts.arg, arg) {
+            (&Argument::Optional(Some(ref v)), &None) => {
+                def = Some(v.clone());
+                &def
+            }
+            (&Argument::Optional(None), &None) => {
+                if !opts.repeats && opts.sep.is_none() {
+                    self.vals.insert(spec.clone(),
+                                     MValue::Single(Plain(None)));
+                } else {
+                    self.vals.entry(spec.clone())
+                        .or_insert(MValue::Many(ValueList::new()));
+                }
+                return true
+            }
+            _ => arg,
+        };
         match *atom {
+            Short(_) | Long(_) if opts.sep.is_some() && arg.is_some() => {
+                let sep = opts.sep.unwrap();
+                let parts = arg.as_ref().unwrap().split(sep).map(String::from);
+                let vs = self.vals.entry(spec.clone())
+                             .or_insert(MValue::Many(ValueList::new()));
+                match *vs {
+                    MValue::Many(ref mut vs) => {
+                        for part in parts {
+                            vs.push(part);
+                        }
+                    }
+                    _ => return false,
+                }
+                true
+            }
             Short(_) | Long(_) => {
                 self.fill_value(spec.clone(), opts.repeats, arg.clone())
             }
@@ -1157,11 +2640,12 @@ impl MState {
     fn match_cmd_or_posarg(&mut self, spec: &Atom, argv: &ArgvToken)
                           -> Option<ArgvToken> {
         match (spec, &argv.atom) {
-            (_, &Command(_)) => {
-                // This is impossible because the argv parser doesn't know
-                // how to produce `Command` values.
-                unreachable!()
+            (&Command(ref n1), &Command(ref n2)) if n1 == n2 => {
+                // The argv parser only produces a `Command` for `--`.
+                self.argvi += 1;
+                Some(argv.clone())
             }
+            (_, &Command(_)) => None,
             (&Command(ref n1), &Positional(ref n2)) if n1 == n2 => {
                 // Coerce a positional to a command because the pattern
                 // demands it and the positional argument matches it.
@@ -1197,20 +2681,20 @@ impl<'a, 'b> Matcher<'a, 'b> {
          .next()
          .map(|mut s| {
              m.add_flag_values(&mut s);
-             m.add_default_values(&mut s);
-
-             // Build a synonym map so that it's easier to look up values.
-             let mut synmap: SynonymMap<String, Value> =
-                 s.vals.into_iter()
-                       .map(|(k, v)| (k.to_string(), v))
-                       .collect();
-             for (from, to) in argv.dopt.descs.synonyms() {
-                 let (from, to) = (from.to_string(), to.to_string());
-                 if synmap.contains_key(&to) {
-                     synmap.insert_synonym(from, to);
+             let mut vals: HashMap<Atom, Value> =
+                 s.vals.into_iter().map(|(k, v)| (k, v.into_value())).collect();
+             argv.dopt.add_default_values(&mut vals);
+             // `--` is recorded even when it was skipped while matching.
+             if argv.double_dash {
+                 vals.insert(double_dash(), Switch(true));
+             }
+             for (atom, &on) in &argv.toggles {
+                 if let Some(v) = vals.get_mut(atom) {
+                     *v = Switch(on);
                  }
              }
-             synmap
+
+             argv.dopt.synonym_map(vals)
          })
     }
 
@@ -1231,47 +2715,9 @@ impl<'a, 'b> Matcher<'a, 'b> {
         }
     }
 
-    fn add_default_values(&self, state: &mut MState) {
-        lazy_static! {
-            static ref SPLIT_SPACE: Regex = regex!(r"\s+");
-        }
-        let vs = &mut state.vals;
-        for (a, opts) in self.argv.dopt.descs.iter() {
-            if vs.contains_key(a) {
-                continue
-            }
-            let atom = a.clone();
-            match (opts.repeats, &opts.arg) {
-                (false, &Zero) => {
-                    match *a {
-                        Positional(_) => vs.insert(atom, Plain(None)),
-                        _ => vs.insert(atom, Switch(false)),
-                    };
-                }
-                (true, &Zero) => {
-                    match *a {
-                        Positional(_) => vs.insert(atom, List(vec!())),
-                        _ => vs.insert(atom, Counted(0)),
-                    };
-                }
-                (false, &One(None)) => { vs.insert(atom, Plain(None)); }
-                (true, &One(None)) => { vs.insert(atom, List(vec!())); }
-                (false, &One(Some(ref v))) => {
-                    vs.insert(atom, Plain(Some(v.clone())));
-                }
-                (true, &One(Some(ref v))) => {
-                    let words = SPLIT_SPACE
-                                .split(v)
-                                .map(|s| s.to_owned())
-                                .collect();
-                    vs.insert(atom, List(words));
-                }
-            }
-        }
-    }
-
     fn state_consumed_all_argv(&self, state: &MState) -> bool {
-        self.argv.positional.len() == state.argvi
+        let rest = &self.argv.positional[state.argvi..];
+        rest.iter().all(|tok| tok.atom == double_dash())
     }
 
     fn state_has_valid_flags(&self, state: &MState) -> bool {
@@ -1399,11 +2845,21 @@ impl<'a, 'b> Matcher<'a, 'b> {
                         }
                     }
                     Command(_) | Positional(_) => {
-                        let tok =
+                        let mut tok =
                             match self.token_from(init) {
                                 None => return vec!(),
                                 Some(tok) => tok,
                             };
+                        // A `--` that isn't matched here is skipped, since
+                        // it may appear anywhere after the command.
+                        let dd = double_dash();
+                        if tok.atom == dd && *atom != dd {
+                            state.argvi += 1;
+                            tok = match self.token_from(&state) {
+                                None => return vec!(),
+                                Some(tok) => tok,
+                            };
+                        }
                         let tok =
                             match state.match_cmd_or_posarg(atom, tok) {
                                 None => return vec!(),
@@ -1436,12 +2892,49 @@ impl<'a, 'b> Matcher<'a, 'b> {
     }
 }
 
+// Whether the atoms in `a` are fewer than the atoms in `b` when looking for
+// missing atoms. A missing command counts for more than any other atom,
+// since it means that argv is for a different pattern.
+fn fewer_missing(a: &[Atom], b: &[Atom]) -> bool {
+    fn key(atoms: &[Atom]) -> (usize, usize) {
+        let cmds = atoms.iter().filter(|a| match **a {
+            Command(_) => true,
+            _ => false,
+        }).count();
+        (cmds, atoms.len())
+    }
+    key(a) < key(b)
+}
+
+// The `--` command, which ends flag parsing in argv.
+fn double_dash() -> Atom {
+    Command("--".into())
+}
+
+// The number of bytes of leading whitespace in `line`.
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_left().len()
+}
+
 // Tries to parse a long flag of the form '--flag[=arg]' and returns a tuple
 // with the flag atom and whether there is an argument or not.
+// A literal '--flag[=arg]' means the argument may be left off.
 // If '=arg' exists and 'arg' isn't a valid argument, an error is returned.
 fn parse_long_equal(flag: &str) -> Result<(Atom, Argument), String> {
     lazy_static! {
         static ref LONG_EQUAL: Regex = regex!("^(?P<name>[^=]+)=(?P<arg>.+)$");
+        static ref LONG_OPTIONAL: Regex = regex!(
+            r"^(?P<name>[^=\[]+)\[=(?P<arg>[^\]]+)\]$"
+        );
+    }
+    if let Some(cap) = LONG_OPTIONAL.captures(flag) {
+        let arg = cap_or_empty(&cap, "arg");
+        if !Atom::is_arg(arg) {
+            err!("Argument '{}' for flag '{}' is not in the \
+                  form ARG or <arg>.", arg, flag)
+        }
+        let atom = Atom::new(cap_or_empty(&cap, "name"));
+        return Ok((atom, Argument::Optional(None)))
     }
     match LONG_EQUAL.captures(flag) {
         None => Ok((Atom::new(flag), Zero)),
@@ -1449,7 +2942,7 @@ fn parse_long_equal(flag: &str) -> Result<(Atom, Argument), String> {
             let arg = cap_or_empty(&cap, "arg");
             if !Atom::is_arg(arg) {
                 err!("Argument '{}' for flag '{}' is not in the \
-                      form ARG or <arg>.", flag, arg)
+                      form ARG or <arg>.", arg, flag)
             }
             Ok((Atom::new(cap_or_empty(&cap, "name")), One(None)))
         }
@@ -1474,11 +2967,15 @@ fn parse_long_equal_argv(flag: &str) -> (Atom, Option<String>) {
 // It first normalizes `[xyz]` -> `[ xyz ]` so that delimiters are tokens.
 // Similarly for `...`, `(`, `)` and `|`.
 // One hitch: `--flag=<arg spaces>` is allowed, so we use a regex to pick out
-// words.
+// words. Another: `--flag[=ARG]` is a single token, so it is matched first.
 fn pattern_tokens(pat: &str) -> Vec<String> {
     lazy_static! {
-        static ref NORMALIZE: Regex = regex!(r"\.\.\.|\[|\]|\(|\)|\|");
-        static ref WORDS: Regex = regex!(r"--\S+?=<[^>]+>|<[^>]+>|\S+");
+        static ref NORMALIZE: Regex = regex!(
+            r"--[^\s\[\]()|=]+\[=[^\]]+\]|\.\.\.|\[|\]|\(|\)|\|"
+        );
+        static ref WORDS: Regex = regex!(
+            r"--[^\s\[]+\[=[^\]]+\]|--\S+?=<[^>]+>|<[^>]+>|\S+"
+        );
     }
 
     let pat = NORMALIZE.replace_all(pat.trim(), " $0 ");
@@ -1488,3 +2985,40 @@ fn pattern_tokens(pat: &str) -> Vec<String> {
     }
     words
 }
+
+// Finds the offset and length of each token in the pattern it came from.
+// Tokens appear in the pattern in order, except for the rare `<...>` token
+// that had spaces inserted while normalizing. Such a token is given an
+// empty span where it should have been.
+fn token_spans(pat: &str, tokens: &[String]) -> Vec<(usize, usize)> {
+    let mut pos = 0;
+    tokens.iter().map(|tok| {
+        match pat[pos..].find(&**tok) {
+            Some(i) => {
+                pos += i + tok.len();
+                (pos - tok.len(), tok.len())
+            }
+            None => (pos, 0),
+        }
+    }).collect()
+}
+
+// Returns the offset of `sub` in `s`. `sub` must be a slice of `s`.
+fn offset_of(s: &str, sub: &str) -> usize {
+    sub.as_ptr() as usize - s.as_ptr() as usize
+}
+
+// Returns the offset and length of the flags of an option description
+// line, i.e., everything after the indentation (and an `Options:` header)
+// up to the two spaces that start the help text.
+fn desc_span(line: &str) -> (usize, usize) {
+    lazy_static! {
+        static ref OPTIONS: Regex = regex!(r"^\s*(?i:options:)\s*");
+    }
+    let start = match OPTIONS.find(line) {
+        Some(m) => m.end(),
+        None => line.len() - line.trim_left().len(),
+    };
+    let rest = line[start..].trim_right();
+    (start, rest.find("  ").unwrap_or(rest.len()))
+}
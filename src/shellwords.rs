@@ -0,0 +1,247 @@
+use dopt::Error::{self, Split};
+
+/// Splits a string into arguments using POSIX shell quoting rules.
+///
+/// Words are separated by unquoted whitespace. Inside single quotes, every
+/// character is taken literally. Inside double quotes, a backslash only
+/// escapes `$`, `` ` ``, `"`, `\` and a newline. Outside of quotes, a
+/// backslash escapes any character. A backslash followed by a newline is
+/// removed entirely (outside single quotes).
+///
+/// Quotes don't end a word, so `a"b c"d` is the single argument `ab cd`.
+/// An empty pair of quotes produces an empty argument.
+///
+/// No other shell expansion (variables, globs, etc.) is performed.
+///
+/// If a quote is never closed or the string ends with an unescaped
+/// backslash, then a `Split` error is returned.
+///
+/// # Example
+///
+/// ```rust
+/// use docopt::shellwords;
+///
+/// let args = shellwords(r#"cp -r "my dir" '' a\ b"#).unwrap();
+/// assert_eq!(args, vec!["cp", "-r", "my dir", "", "a b"]);
+/// ```
+pub fn shellwords(s: &str) -> Result<Vec<String>, Error> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Quote { No, Single, Double }
+
+    let mut words = vec![];
+    let mut word = String::new();
+    // Whether a word has been started, which may be true even when `word`
+    // is empty (e.g., after `''`).
+    let mut in_word = false;
+    let mut quote = Quote::No;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Quote::Single, '\'') => quote = Quote::No,
+            (Quote::Single, c) => word.push(c),
+            (Quote::Double, '"') => quote = Quote::No,
+            (Quote::Double, '\\') => {
+                match chars.next() {
+                    None => break,
+                    Some('\n') => {}
+                    Some(c @ '$') | Some(c @ '`') | Some(c @ '"')
+                    | Some(c @ '\\') => word.push(c),
+                    Some(c) => { word.push('\\'); word.push(c); }
+                }
+            }
+            (Quote::Double, c) => word.push(c),
+            (Quote::No, '\\') => {
+                match chars.next() {
+                    None => {
+                        return Err(Split(format!(
+                            "Unexpected backslash at end of '{}'.", s)));
+                    }
+                    Some('\n') => {}
+                    Some(c) => { word.push(c); in_word = true; }
+                }
+            }
+            (Quote::No, '\'') => { quote = Quote::Single; in_word = true; }
+            (Quote::No, '"') => { quote = Quote::Double; in_word = true; }
+            (Quote::No, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(::std::mem::replace(&mut word, String::new()));
+                    in_word = false;
+                }
+            }
+            (Quote::No, c) => { word.push(c); in_word = true; }
+        }
+    }
+    match quote {
+        Quote::No => {}
+        Quote::Single => {
+            return Err(Split(format!("Unterminated single quote in '{}'.", s)))
+        }
+        Quote::Double => {
+            return Err(Split(format!("Unterminated double quote in '{}'.", s)))
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// The quoting rules used by `reconstruct_command_line`.
+///
+/// Each style quotes only the arguments that need it. The table below
+/// shows how each kind of character is written in an argument that needs
+/// quoting:
+///
+/// | Argument             | `Posix`     | `PowerShell` | `CmdExe`     |
+/// |----------------------|-------------|--------------|--------------|
+/// | empty                | `''`        | `''`         | `^"^"`       |
+/// | space                | `'a b'`     | `'a b'`      | `^"a b^"`    |
+/// | `'`                  | `'it'\''s'` | `'it''s'`    | `it's`       |
+/// | `"`                  | `'"'`       | `'"'`        | `^"\^"^"`    |
+/// | `$`                  | `'$x'`      | `'$x'`       | `$x`         |
+/// | `%`                  | `%x`        | `'%x'`       | `^%x`        |
+/// | `!`                  | `'a!'`      | `'a!'`       | `a^!`        |
+/// | newline              | `'...'`     | `'...'`      | not possible |
+/// | non-ASCII, e.g., `é` | `'é'`       | `'é'`        | `é`          |
+///
+/// `Debug` always writes each argument as a Rust string literal, which is
+/// unambiguous but is not meant to be run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuoteStyle {
+    /// Quoting for POSIX shells like `sh` and `bash`.
+    ///
+    /// Arguments are put in single quotes, in which every character is
+    /// literal (including `!`, which bash would otherwise expand from its
+    /// history). A single quote is written as `'\''`. A first argument
+    /// containing `=` is quoted too, so that it isn't taken as a variable
+    /// assignment. The result can be split again with `shellwords`.
+    Posix,
+    /// Quoting for PowerShell.
+    ///
+    /// Arguments are put in single quotes, in which every character is
+    /// literal. A single quote (including the typographic single quotes
+    /// that PowerShell also accepts) is doubled.
+    PowerShell,
+    /// Quoting for `cmd.exe` running a program that splits its command line
+    /// like the Microsoft C runtime.
+    ///
+    /// Arguments are first quoted for the C runtime: they are put in double
+    /// quotes, a double quote is written as `\"` and backslashes before a
+    /// double quote are doubled. Then each character that `cmd.exe` treats
+    /// specially (`(`, `)`, `%`, `!`, `^`, `"`, `<`, `>`, `&` and `|`) is
+    /// escaped with `^`. A newline can't be passed through `cmd.exe` at
+    /// all, so it is written as is.
+    CmdExe,
+    /// Each argument as a Rust string literal.
+    Debug,
+}
+
+impl Default for QuoteStyle {
+    fn default() -> QuoteStyle {
+        QuoteStyle::Posix
+    }
+}
+
+/// Joins `argv` into a command line that reproduces it when run in the
+/// shell chosen by `style`.
+///
+/// Arguments are separated by a single space. The first argument is
+/// expected to be the program name.
+///
+/// # Example
+///
+/// ```rust
+/// use docopt::{QuoteStyle, reconstruct_command_line, shellwords};
+///
+/// let argv = vec!["cp", "-r", "my dir", "", "it's"];
+/// let line = reconstruct_command_line(&argv, QuoteStyle::Posix);
+/// assert_eq!(line, r#"cp -r 'my dir' '' 'it'\''s'"#);
+/// assert_eq!(shellwords(&line).unwrap(), argv);
+/// ```
+pub fn reconstruct_command_line<S>(argv: &[S], style: QuoteStyle) -> String
+        where S: AsRef<str> {
+    let words: Vec<String> = argv.iter().enumerate().map(|(i, arg)| {
+        let arg = arg.as_ref();
+        match style {
+            QuoteStyle::Posix => quote_posix(arg, i == 0),
+            QuoteStyle::PowerShell => quote_powershell(arg),
+            QuoteStyle::CmdExe => quote_cmd(arg),
+            QuoteStyle::Debug => format!("{:?}", arg),
+        }
+    }).collect();
+    words.join(" ")
+}
+
+fn quote_posix(arg: &str, first: bool) -> String {
+    fn safe(c: char) -> bool {
+        c < '\u{80}' && (c.is_alphanumeric() || "_@%+=:,./-".contains(c))
+    }
+    let assignment = first && arg.contains('=');
+    if !arg.is_empty() && arg.chars().all(safe) && !assignment {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace("'", r"'\''"))
+}
+
+fn quote_powershell(arg: &str) -> String {
+    fn safe(c: char) -> bool {
+        c < '\u{80}' && (c.is_alphanumeric() || "_+=:./-".contains(c))
+    }
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    let mut out = String::from("'");
+    for c in arg.chars() {
+        match c {
+            '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' => {
+                out.push(c);
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+fn quote_cmd(arg: &str) -> String {
+    // First, quote for the C runtime's command line splitting.
+    let crt =
+        if !arg.is_empty() && !arg.contains(|c| " \t\n\x0b\"".contains(c)) {
+            arg.to_string()
+        } else {
+            let mut out = String::from("\"");
+            let mut backslashes = 0;
+            for c in arg.chars() {
+                match c {
+                    '\\' => backslashes += 1,
+                    '"' => {
+                        out.push_str(&repeat('\\', 2 * backslashes + 1));
+                        out.push('"');
+                        backslashes = 0;
+                    }
+                    c => {
+                        out.push_str(&repeat('\\', backslashes));
+                        out.push(c);
+                        backslashes = 0;
+                    }
+                }
+            }
+            out.push_str(&repeat('\\', 2 * backslashes));
+            out.push('"');
+            out
+        };
+    // Then, escape everything that `cmd.exe` would interpret.
+    let mut out = String::with_capacity(crt.len());
+    for c in crt.chars() {
+        if "()%!^\"<>&|".contains(c) {
+            out.push('^');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn repeat(c: char, n: usize) -> String {
+    ::std::iter::repeat(c).take(n).collect()
+}
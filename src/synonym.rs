@@ -40,6 +40,15 @@ impl<K: Eq + Hash, V> SynonymMap<K, V> {
         self.with_key(k, |k| self.vals.get(k))
     }
 
+    /// Returns the key that `k` is stored under, following a synonym if
+    /// there is one.
+    pub fn canonical(&self, k: &K) -> Option<&K> {
+        match self.syns.get(k) {
+            Some(to) => Some(to),
+            None => self.vals.keys().find(|key| *key == k),
+        }
+    }
+
     pub fn contains_key(&self, k: &K) -> bool {
         self.with_key(k, |k| self.vals.contains_key(k))
     }
@@ -99,6 +108,12 @@ impl<K: Eq + Hash + Clone, V> FromIterator<(K, V)> for SynonymMap<K, V> {
     }
 }
 
+impl<K: Eq + Hash, V: PartialEq> PartialEq for SynonymMap<K, V> {
+    fn eq(&self, other: &SynonymMap<K, V>) -> bool {
+        self.vals == other.vals && self.syns == other.syns
+    }
+}
+
 impl<K: Eq + Hash + Debug, V: Debug> Debug for SynonymMap<K, V> {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
         try!(self.vals.fmt(f));
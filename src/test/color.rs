@@ -0,0 +1,43 @@
+use std::ffi::OsString;
+
+use color::{colorize, enabled};
+
+const USAGE: &'static str = "Naval Fate.
+
+Usage:
+  naval_fate ship <name> [--speed=<kn>]
+  naval_fate -h | --help
+
+Options:
+  -h --help     Show this screen.
+  --speed=<kn>  Speed in knots.";
+
+#[test]
+fn colorize_doc() {
+    assert_eq!(colorize(USAGE), "Naval Fate.
+
+\x1b[1;4mUsage:\x1b[0m
+  naval_fate ship <name> [--speed=<kn>]
+  naval_fate -h | --help
+
+\x1b[1;4mOptions:\x1b[0m
+  \x1b[32m-h --help\x1b[0m     Show this screen.
+  \x1b[32m--speed=<kn>\x1b[0m  Speed in knots.");
+}
+
+#[test]
+fn colorize_inline() {
+    assert_eq!(colorize("Usage: prog [-v]\nGlobal options: -v  Verbose."),
+               "\x1b[1;4mUsage:\x1b[0m prog [-v]\n\
+                \x1b[1;4mGlobal options:\x1b[0m \x1b[32m-v\x1b[0m  Verbose.");
+    assert_eq!(colorize("Read these options: carefully."),
+               "Read these options: carefully.");
+}
+
+#[test]
+fn color_enabled() {
+    assert!(enabled(true, None));
+    assert!(enabled(true, Some(OsString::new())));
+    assert!(!enabled(true, Some(OsString::from("1"))));
+    assert!(!enabled(false, None));
+}
@@ -0,0 +1,463 @@
+use Docopt;
+use devtools::{ArgSpec, CliSpec, from_clap_like};
+use devtools::{Change, ChangeKind, diff_grammars};
+use devtools::{Attachment, Placeholders, Rule, RuleSet, lint};
+
+fn s(x: &str) -> String { x.to_string() }
+
+fn flag(short: Option<char>, long: &str, help: &str) -> ArgSpec {
+    ArgSpec {
+        name: s(long),
+        short: short,
+        long: Some(s(long)),
+        help: s(help),
+        ..ArgSpec::default()
+    }
+}
+
+fn positional(name: &str, multiple: bool) -> ArgSpec {
+    ArgSpec {
+        name: s(name),
+        multiple: multiple,
+        required: true,
+        ..ArgSpec::default()
+    }
+}
+
+fn spec() -> CliSpec {
+    CliSpec {
+        name: s("pkg"),
+        about: Some(s("Manage packages.")),
+        args: vec![flag(Some('v'), "verbose", "Show extra output.")],
+        subcommands: vec![
+            CliSpec {
+                name: s("install"),
+                args: vec![
+                    ArgSpec {
+                        name: s("DIR"),
+                        long: Some(s("root")),
+                        takes_value: true,
+                        default: Some(s("/usr/local")),
+                        help: s("Install prefix."),
+                        ..ArgSpec::default()
+                    },
+                    positional("pkg", true),
+                ],
+                ..CliSpec::default()
+            },
+            CliSpec {
+                name: s("search"),
+                args: vec![
+                    ArgSpec {
+                        name: s("fmt"),
+                        short: Some('f'),
+                        long: Some(s("format")),
+                        takes_value: true,
+                        required: true,
+                        choices: vec![s("json"), s("text")],
+                        help: s("Output format."),
+                        ..ArgSpec::default()
+                    },
+                    ArgSpec {
+                        name: s("tag"),
+                        short: Some('t'),
+                        takes_value: true,
+                        multiple: true,
+                        help: s("Filter by tag."),
+                        ..ArgSpec::default()
+                    },
+                    positional("query", false),
+                ],
+                ..CliSpec::default()
+            },
+        ],
+    }
+}
+
+#[test]
+fn from_clap_like_multi_subcommand() {
+    let usage = from_clap_like(spec()).unwrap();
+    assert_eq!(usage, "\
+Manage packages.
+
+Usage:
+  pkg install [options] <pkg>...
+  pkg search [options] --format=<fmt> <query>
+  pkg --help
+
+Options:
+  -v, --verbose       Show extra output.
+  --root=<dir>        Install prefix. [default: /usr/local]
+  -f, --format=<fmt>  Output format. [choices: json text]
+  -t <tag> ...        Filter by tag.
+  -h, --help          Show this screen.
+");
+
+    let args = Docopt::new(&*usage).unwrap()
+        .argv(&["pkg", "search", "-vf", "json", "-t", "a", "-t", "b", "x"])
+        .parse().unwrap();
+    assert!(args.get_bool("search") && args.get_bool("--verbose"));
+    assert_eq!(args.get_str("--format"), "json");
+    assert_eq!(args.get_vec("-t"), vec!["a", "b"]);
+    assert_eq!(args.get_str("<query>"), "x");
+    assert_eq!(args.get_str("--root"), "/usr/local");
+}
+
+#[test]
+fn from_clap_like_keeps_user_short_h() {
+    let spec = CliSpec {
+        name: s("net"),
+        args: vec![ArgSpec {
+            name: s("host"),
+            short: Some('h'),
+            long: Some(s("host")),
+            takes_value: true,
+            ..ArgSpec::default()
+        }],
+        ..CliSpec::default()
+    };
+    let usage = from_clap_like(spec).unwrap();
+    let dopt = Docopt::new(&*usage).unwrap();
+    let args = dopt.clone().argv(&["net", "-h", "example.com"]).parse().unwrap();
+    assert_eq!(args.get_str("--host"), "example.com");
+    assert!(dopt.argv(&["net", "--help"]).parse().is_err());
+}
+
+#[test]
+fn from_clap_like_rejects_unparseable_output() {
+    let spec = CliSpec {
+        name: s("bad"),
+        args: vec![ArgSpec {
+            name: s("x"),
+            short: Some('x'),
+            long: Some(s("with space")),
+            ..ArgSpec::default()
+        }],
+        ..CliSpec::default()
+    };
+    assert!(from_clap_like(spec).is_err());
+}
+
+const OLD: &'static str = "
+Usage:
+  pkg install [options] <pkg>...
+  pkg remove <pkg>
+  pkg --help
+
+Options:
+  -q, --quiet          Say less.
+  -v, --verbose        Say more.
+  --force              Overwrite files.
+  --root=<dir>         Install prefix. [default: /usr/local]
+  --jobs=<n>           Parallel jobs.
+  --color              Use color.
+  --format=<fmt>       Output format. [choices: json text]
+  --tag=<tag>          Filter by tag.
+  --old                Going away.
+  -h, --help           Show this screen.
+";
+
+const NEW: &'static str = "
+Usage:
+  pkg install [options] <pkg>...
+  pkg search <query>
+  pkg --help
+
+Options:
+  -q, --silent         Say less.
+  --verbose            Say more.
+  -f, --force          Overwrite files.
+  --root=<dir>         Install prefix. [default: /opt]
+  --jobs               Parallel jobs.
+  --color              Use colors in output.
+  --format=<fmt>       Output format. [choices: json yaml]
+  --tag=<tag> ...      Filter by tag.
+  --new                Brand new.
+  -h, --help           Show this screen.
+";
+
+fn change(kind: ChangeKind, breaking: bool) -> Change {
+    Change { kind: kind, breaking: breaking }
+}
+
+#[test]
+fn diff_grammars_every_category() {
+    let diff = diff_grammars(OLD, NEW).unwrap();
+    assert_eq!(diff.changes, vec![
+        change(ChangeKind::DescriptionChanged {
+            name: s("--color"),
+            old: s("Use color."),
+            new: s("Use colors in output."),
+        }, false),
+        change(ChangeKind::AliasAdded { name: s("--force"), alias: s("-f") },
+               false),
+        change(ChangeKind::ChoicesChanged {
+            name: s("--format"),
+            added: vec![s("yaml")],
+            removed: vec![s("text")],
+        }, true),
+        change(ChangeKind::ArityChanged {
+            name: s("--jobs"),
+            old: s("one argument"),
+            new: s("a switch"),
+        }, true),
+        change(ChangeKind::OptionAdded { name: s("--new") }, false),
+        change(ChangeKind::OptionRemoved { name: s("--old") }, true),
+        change(ChangeKind::DefaultChanged {
+            name: s("--root"),
+            old: Some(s("/usr/local")),
+            new: Some(s("/opt")),
+        }, true),
+        change(ChangeKind::OptionRenamed {
+            old: s("--quiet"),
+            new: s("--silent"),
+        }, true),
+        change(ChangeKind::ArityChanged {
+            name: s("--tag"),
+            old: s("one argument"),
+            new: s("repeated arguments"),
+        }, false),
+        change(ChangeKind::AliasRemoved {
+            name: s("--verbose"),
+            alias: s("-v"),
+        }, true),
+        change(ChangeKind::PatternRemoved { pattern: s("remove <pkg>") },
+               true),
+        change(ChangeKind::PatternAdded { pattern: s("search <query>") },
+               false),
+    ]);
+    assert!(diff.is_breaking());
+}
+
+#[test]
+fn diff_grammars_optional_default() {
+    let old = "\
+Usage: prog [--color[=<when>]]
+
+Options:
+  --color[=<when>]  Color. [default: auto]
+";
+    let new = "\
+Usage: prog [--color[=<when>]]
+
+Options:
+  --color[=<when>]  Color. [default: always]
+";
+    let diff = diff_grammars(old, new).unwrap();
+    assert_eq!(diff.changes, vec![
+        change(ChangeKind::DefaultChanged {
+            name: s("--color"),
+            old: Some(s("auto")),
+            new: Some(s("always")),
+        }, true),
+    ]);
+}
+
+#[test]
+fn diff_grammars_identical() {
+    let diff = diff_grammars(OLD, OLD).unwrap();
+    assert!(diff.changes.is_empty());
+    assert!(!diff.is_breaking());
+    assert_eq!(diff.to_string(), "");
+    assert_eq!(diff.to_json(), "{\"breaking\":false,\"changes\":[]}");
+}
+
+#[test]
+fn diff_grammars_non_breaking() {
+    let old = "Usage: prog [--color]\n\nOptions:\n  --color  Color.\n";
+    let new = "\
+Usage: prog [--color]
+       prog [--color] -n
+
+Options:
+  -c, --color  Color.
+  -n           Dry run.
+";
+    let diff = diff_grammars(old, new).unwrap();
+    assert!(!diff.is_breaking());
+    assert_eq!(diff.to_string(), "\
+- Added `-c` as a synonym for `--color`.
+- Added option `-n`.
+- Added usage `[--color] -n`.
+");
+    let json = diff.to_json();
+    assert!(json.starts_with("{\"breaking\":false,\"changes\":[\
+                              {\"alias\":\"-c\",\"breaking\":false,\
+                              \"kind\":\"alias_added\",\"name\":\"--color\",\
+                              \"summary\":\"Added `-c` as a synonym for \
+                              `--color`.\"},"), "{}", json);
+}
+
+// A rule set with every rule off, to check them one at a time.
+fn no_rules() -> RuleSet {
+    RuleSet {
+        value_attachment: None,
+        placeholders: None,
+        defaults_last: false,
+        two_space_separator: false,
+        aligned_descriptions: false,
+    }
+}
+
+// Lints `doc`, giving the rule, line, column and suggestion of each lint.
+fn lints(doc: &str, rules: &RuleSet) -> Vec<(Rule, usize, usize, String)> {
+    lint(doc, rules).unwrap().into_iter().map(|l| {
+        (l.rule, l.location.line, l.location.column, l.suggestion)
+    }).collect()
+}
+
+#[test]
+fn lint_value_attachment() {
+    let doc = "\
+Usage: prog [options]
+
+Options:
+  -o FILE, --out=FILE  Output.
+  --log LEVEL          Log level.
+  --color[=WHEN]       Color.
+";
+    let rules = RuleSet {
+        value_attachment: Some(Attachment::Consistent),
+        ..no_rules()
+    };
+    assert_eq!(lints(doc, &rules), vec![
+        (Rule::ValueAttachment, 5, 8,
+         s("  --log=LEVEL          Log level.")),
+    ]);
+    let rules = RuleSet {
+        value_attachment: Some(Attachment::Space),
+        ..no_rules()
+    };
+    assert_eq!(lints(doc, &rules), vec![
+        (Rule::ValueAttachment, 4, 17,
+         s("  -o FILE, --out FILE  Output.")),
+    ]);
+    let fixed = doc.replace("--log LEVEL", "--log=LEVEL");
+    let rules = RuleSet {
+        value_attachment: Some(Attachment::Equals),
+        ..no_rules()
+    };
+    assert_eq!(lints(&fixed, &rules), vec![]);
+}
+
+#[test]
+fn lint_placeholders() {
+    let doc = "\
+Usage: prog [options]
+
+Options:
+  --out=<file>     Output.
+  --log=LEVEL      Log level.
+  --dir=<Out-Dir>  Directory.
+";
+    let rules = RuleSet {
+        placeholders: Some(Placeholders::Consistent),
+        ..no_rules()
+    };
+    assert_eq!(lints(doc, &rules), vec![
+        (Rule::Placeholders, 5, 9, s("  --log=<level>      Log level.")),
+        (Rule::Placeholders, 6, 9, s("  --dir=<out-dir>  Directory.")),
+    ]);
+    let rules = RuleSet {
+        placeholders: Some(Placeholders::Caps),
+        ..no_rules()
+    };
+    assert_eq!(lints(doc, &rules), vec![
+        (Rule::Placeholders, 4, 9, s("  --out=FILE     Output.")),
+        (Rule::Placeholders, 6, 9, s("  --dir=OUTDIR  Directory.")),
+    ]);
+    let fixed = "Usage: prog [options]\n\n  -o FILE, --out=FILE  Output.\n";
+    assert_eq!(lints(fixed, &rules), vec![]);
+}
+
+#[test]
+fn lint_defaults_last() {
+    let doc = "\
+Usage: prog [options]
+
+Options:
+  --jobs=N  [default: 4] Parallel jobs.
+  --log=L   Log level [default: warn] or off.
+  --fmt=F   Format. [default: json] [choices: json text]
+  --tag=T ...  Tags. [default: a b] [default-mode: append]
+";
+    let rules = RuleSet { defaults_last: true, ..no_rules() };
+    assert_eq!(lints(doc, &rules), vec![
+        (Rule::DefaultsLast, 4, 13,
+         s("  --jobs=N  Parallel jobs. [default: 4]")),
+        (Rule::DefaultsLast, 5, 23,
+         s("  --log=L   Log level or off. [default: warn]")),
+    ]);
+}
+
+#[test]
+fn lint_separator_and_alignment() {
+    let doc = "\
+Usage: prog [options]
+
+Options:
+  -v, --verbose     Say more.
+  -q, --quiet      Say less.
+  --out=FILE       Output.
+    Continued here.
+  --color          Color.
+
+Other options:
+  --jobs=N  Jobs.
+  -n  Dry run.
+";
+    let rules = RuleSet { two_space_separator: true, ..no_rules() };
+    assert_eq!(lints(doc, &rules), vec![
+        (Rule::Separator, 4, 16, s("  -v, --verbose  Say more.")),
+    ]);
+
+    let rules = RuleSet { aligned_descriptions: true, ..no_rules() };
+    assert_eq!(lints(doc, &rules), vec![
+        (Rule::Alignment, 4, 21, s("  -v, --verbose    Say more.")),
+        (Rule::Alignment, 12, 7, s("  -n        Dry run.")),
+    ]);
+
+    // With both rules, the column is two spaces after the widest flags.
+    let rules = RuleSet {
+        two_space_separator: true,
+        aligned_descriptions: true,
+        ..no_rules()
+    };
+    assert_eq!(lints(doc, &rules), vec![
+        (Rule::Separator, 4, 16, s("  -v, --verbose  Say more.")),
+        (Rule::Alignment, 5, 20, s("  -q, --quiet    Say less.")),
+        (Rule::Alignment, 6, 20, s("  --out=FILE     Output.")),
+        (Rule::Alignment, 8, 20, s("  --color        Color.")),
+        (Rule::Alignment, 12, 7, s("  -n        Dry run.")),
+    ]);
+}
+
+#[test]
+fn lint_conforming() {
+    let doc = "\
+Usage: prog [options] <file>
+
+Options:
+  -o <file>, --out=<file>  Output.
+  --jobs=<n>               Parallel jobs. [default: 4]
+  -v, --verbose            Say more.
+";
+    assert_eq!(lints(doc, &RuleSet::default()), vec![]);
+}
+
+#[test]
+fn lint_display() {
+    let doc = "Usage: prog [options]\n\nOptions:\n  --out FILE  Output.\n";
+    let rules = RuleSet {
+        value_attachment: Some(Attachment::Equals),
+        ..no_rules()
+    };
+    let found = lint(doc, &rules).unwrap();
+    assert_eq!(found[0].to_string(), "\
+'--out' is joined to its argument with a space instead of '='.
+ --> line 4, column 8
+  |
+4 |   --out FILE  Output.
+  |        ^
+  = suggestion: --out=FILE  Output.");
+}
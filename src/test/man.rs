@@ -0,0 +1,83 @@
+use {Docopt, ManMeta};
+use man::escape;
+
+fn meta() -> ManMeta {
+    ManMeta {
+        name: "naval-fate".into(),
+        section: "1".into(),
+        version: "2.0".into(),
+        date: "2017-02-11".into(),
+    }
+}
+
+#[test]
+fn escape_roff() {
+    assert_eq!(escape("--speed"), r"\-\-speed");
+    assert_eq!(escape(r"C:\dir"), r"C:\edir");
+    assert_eq!(escape(r"a\-b"), r"a\e\-b");
+    assert_eq!(escape("plain"), "plain");
+}
+
+#[test]
+fn man_page() {
+    let dopt = Docopt::new("
+Naval Fate.
+
+Moves ships around. Use with care.
+
+See also: mine-field.
+
+Usage:
+  naval-fate ship <name> move <x> <y> [--speed=<kn>]
+  naval-fate mine (set|remove) <x> <y> [--moored | --drifting]
+  naval-fate -h | --help
+
+Options:
+  -h --help     Show this screen.
+  --speed=<kn>  Speed in knots
+                (use \\ to escape) [default: 10].
+  --moored      Moored (anchored) mine.
+  --drifting    .drifting mine.
+").unwrap();
+    assert_eq!(dopt.man_page(&meta()), r#".TH "NAVAL\-FATE" "1" "2017\-02\-11" "naval\-fate 2.0"
+.SH NAME
+naval\-fate \- Naval Fate.
+.SH SYNOPSIS
+.nf
+\fBnaval\-fate\fR ship <name> move <x> <y> [\-\-speed=<kn>]
+\fBnaval\-fate\fR mine (set|remove) <x> <y> [\-\-moored | \-\-drifting]
+\fBnaval\-fate\fR \-h | \-\-help
+.fi
+.SH DESCRIPTION
+Moves ships around. Use with care.
+.PP
+See also: mine\-field.
+.SH OPTIONS
+.TP
+\fB\-h \-\-help\fR
+Show this screen.
+.TP
+\fB\-\-speed=<kn>\fR
+Speed in knots (use \e to escape) [default: 10].
+.TP
+\fB\-\-moored\fR
+Moored (anchored) mine.
+.TP
+\fB\-\-drifting\fR
+\&.drifting mine.
+"#);
+}
+
+#[test]
+fn man_page_minimal() {
+    let dopt = Docopt::new("Usage: prog <x>").unwrap();
+    let meta = ManMeta { name: "prog".into(), ..ManMeta::default() };
+    assert_eq!(dopt.man_page(&meta), r#".TH "PROG" "" "" "prog"
+.SH NAME
+prog
+.SH SYNOPSIS
+.nf
+\fBprog\fR <x>
+.fi
+"#);
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
-use {Docopt, ArgvMap};
-use Value::{self, Switch, Plain};
+use {Docopt, ArgvMap, BytesEncoding, HelpStyle, OneOf};
+use Value::{self, Switch, Counted, Plain, List};
+use parse::{ArgvSettings, Atom};
 
 fn get_args(doc: &str, argv: &[&'static str]) -> ArgvMap {
     let dopt = match Docopt::new(doc) {
@@ -13,6 +14,29 @@ fn get_args(doc: &str, argv: &[&'static str]) -> ArgvMap {
     }
 }
 
+// Checks that the general matcher agrees with the simpler one used for
+// some usages (see `Parser::matches`).
+fn check_matchers(doc: &str, argv: &[&str]) {
+    let dopt = match Docopt::new(doc) {
+        Err(_) => return,
+        Ok(dopt) => dopt,
+    };
+    let p = dopt.parser();
+    let settings = ArgvSettings {
+        options_first: false,
+        plus_minus_toggles: false,
+        strict_short_stacks: false,
+        ignore_case: &[],
+        aliases: &[],
+        collect_unknown: false,
+        trailing: &[],
+    };
+    let argv = argv.iter().map(|s| s.to_string()).collect();
+    if let Ok(parsed) = p.parse_argv(argv, &settings) {
+        p.check_matchers(&parsed);
+    }
+}
+
 fn map_from_alist(alist: Vec<(&'static str, Value)>)
                  -> HashMap<String, Value> {
     alist.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
@@ -43,6 +67,7 @@ macro_rules! test_expect(
     ($name:ident, $doc:expr, $args:expr, $expected:expr) => (
         #[test]
         fn $name() {
+            ::test::check_matchers($doc, $args);
             let vals = get_args($doc, $args);
             let expected = map_from_alist($expected);
             same_args(&expected, &vals);
@@ -53,8 +78,15 @@ macro_rules! test_expect(
 macro_rules! test_user_error(
     ($name:ident, $doc:expr, $args:expr) => (
         #[test]
-        #[should_panic]
-        fn $name() { get_args($doc, $args); }
+        fn $name() {
+            // Not `should_panic`, so that the matchers disagreeing isn't
+            // taken for the expected error.
+            ::test::check_matchers($doc, $args);
+            if let Ok(dopt) = ::Docopt::new($doc) {
+                let argv = vec!["cmd"].into_iter().chain($args.iter().cloned());
+                assert!(dopt.argv(argv).parse().is_err());
+            }
+        }
     );
 );
 
@@ -106,6 +138,2655 @@ fn regression_issue_195() {
     dopt.parse().unwrap();
 }
 
+const HELP_STYLE_USAGE: &'static str = "
+Frobnicate things.
+
+    Usage:
+        frob [options] <thing>
+        frob --help
+
+    Options:
+        -v, --verbose  Be loud.
+        -h, --help     Show this message.
+";
+
+fn help_style_err(style: HelpStyle, argv: &[&'static str]) -> String {
+    Docopt::new(HELP_STYLE_USAGE).unwrap()
+        .help_style(style)
+        .argv(vec!["frob"].iter().chain(argv.iter()))
+        .parse()
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn test_help_style_usage_only() {
+    assert_eq!(help_style_err(HelpStyle::UsageOnly, &["-x"]), "\
+Unknown flag: '-x'
+
+    Usage:
+        frob [options] <thing>
+        frob --help
+
+Try 'frob --help' for more information.");
+    assert_eq!(help_style_err(HelpStyle::UsageOnly, &["--help"]),
+               HELP_STYLE_USAGE.trim());
+}
+
+#[test]
+fn test_help_style_full() {
+    assert_eq!(help_style_err(HelpStyle::Full, &["-x"]),
+               format!("Unknown flag: '-x'\n\n{}", HELP_STYLE_USAGE.trim()));
+    assert_eq!(help_style_err(HelpStyle::Full, &["--help"]),
+               HELP_STYLE_USAGE.trim());
+}
+
+const ELIDED_USAGE: &'static str = "
+Usage: vcs init [--bare]
+       vcs clone <url> [--depth=<n>]
+       vcs commit [--message=<msg>] [--amend]
+       vcs push [--force] [<remote>]
+       vcs pull [--rebase] [<remote>]
+       vcs log [--oneline]
+
+Options:
+    --bare           Create a bare repository.
+    --depth=<n>      Limit history.
+    --message=<msg>  Commit message.
+    --amend          Amend the last commit.
+    --force          Overwrite the remote branch.
+    --rebase         Rebase instead of merging.
+    --oneline        One line per commit.
+";
+
+fn elided_err(max: usize, argv: &[&'static str]) -> String {
+    Docopt::new(ELIDED_USAGE).unwrap()
+        .help_style(HelpStyle::Elided)
+        .max_usage_alternatives(max)
+        .argv(vec!["vcs"].iter().chain(argv.iter()))
+        .parse()
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn test_help_style_elided() {
+    assert_eq!(elided_err(2, &["commit", "--ammend"]), "\
+Unknown flag: '--ammend'. Did you mean '--amend'?
+
+Usage: vcs init [--bare]
+       vcs commit [--message=<msg>] [--amend]
+       ... and 4 more forms (run with --help to see all)
+
+Try 'vcs --help' for more information.");
+}
+
+#[test]
+fn test_help_style_elided_drops_first_pattern() {
+    assert_eq!(elided_err(1, &["push", "--forse"]), "\
+Unknown flag: '--forse'. Did you mean '--force'?
+
+Usage:
+       vcs push [--force] [<remote>]
+       ... and 5 more forms (run with --help to see all)
+
+Try 'vcs --help' for more information.");
+}
+
+#[test]
+fn test_help_style_elided_few_patterns() {
+    assert_eq!(elided_err(6, &["log", "--x"]), format!("\
+Unknown flag: '--x'
+
+{}
+
+Try 'vcs --help' for more information.",
+        ELIDED_USAGE.trim().split("\n\n").next().unwrap()));
+}
+
+const TAR_USAGE: &'static str = "
+Usage: tar [options] [<file>...]
+
+Options:
+    -x          Extract.
+    -v          Verbose.
+    -f ARCHIVE  Archive file.
+    -o FILE     Output file.
+";
+
+test_expect!(test_short_cluster_last_takes_value, TAR_USAGE,
+             &["-xvf", "archive.tar"],
+             vec![("-x", Switch(true)), ("-v", Switch(true)),
+                  ("-f", Plain(Some("archive.tar".to_string()))),
+                  ("<file>", List(vec![]))]);
+
+test_expect!(test_short_attached_value, TAR_USAGE, &["-ofile.txt"],
+             vec![("-o", Plain(Some("file.txt".to_string())))]);
+
+test_expect!(test_short_cluster_attached_value, TAR_USAGE,
+             &["-xvfarchive.tar", "a"],
+             vec![("-x", Switch(true)), ("-v", Switch(true)),
+                  ("-f", Plain(Some("archive.tar".to_string()))),
+                  ("<file>", List(vec!["a".to_string()]))]);
+
+// The rest of the cluster is the value, even if it spells valid flags.
+test_expect!(test_short_cluster_value_wins, TAR_USAGE, &["-fxv"],
+             vec![("-x", Switch(false)), ("-v", Switch(false)),
+                  ("-f", Plain(Some("xv".to_string())))]);
+
+test_user_error!(test_short_cluster_missing_value, TAR_USAGE, &["-xvf"]);
+
+#[test]
+fn test_multiline_option_descriptions() {
+    let dopt = Docopt::new("
+Usage: prog [options]
+
+Options:
+    -o, --output=<dir>  Where generated files
+                        are written. [default: out]
+    --jobs=<n>          Number of jobs to run
+                        in parallel, which should not
+                        exceed the core count. [default: 4]
+    -q                  Be quiet.
+").unwrap();
+    let help = |flag: &str| {
+        dopt.parser().descs.find(&Atom::new(flag)).unwrap().help.clone()
+    };
+    assert_eq!(help("--output"),
+               "Where generated files are written. [default: out]");
+    assert_eq!(help("--jobs"),
+               "Number of jobs to run in parallel, which should not \
+                exceed the core count. [default: 4]");
+    assert_eq!(help("-q"), "Be quiet.");
+
+    let vals = dopt.argv(&["prog"]).parse().unwrap();
+    assert_eq!(vals.get_str("--output"), "out");
+    assert_eq!(vals.get_str("--jobs"), "4");
+}
+
+#[test]
+fn test_multiline_option_description_defaults() {
+    let dopt = Docopt::new("
+Usage: prog [options]
+
+Options:
+  --output=<dir>   Where generated files are written.
+                   Defaults to the current directory. [default: .]
+  --cache=<dir>    Where to cache downloads. [default:
+                   ~/.cache]
+  --mode=<m>       How to run.
+
+                   This is not part of the description.
+").unwrap();
+    let help = |flag: &str| {
+        dopt.parser().descs.find(&Atom::new(flag)).unwrap().help.clone()
+    };
+    assert_eq!(help("--output"),
+               "Where generated files are written. Defaults to the current \
+                directory. [default: .]");
+    assert_eq!(help("--cache"),
+               "Where to cache downloads. [default: ~/.cache]");
+    assert_eq!(help("--mode"), "How to run.");
+
+    let vals = dopt.argv(&["prog"]).parse().unwrap();
+    assert_eq!(vals.get_str("--output"), ".");
+    assert_eq!(vals.get_str("--cache"), "~/.cache");
+    assert_eq!(vals.find("--mode"), Some(&Plain(None)));
+}
+
+const SCOPED_REQUIRED_USAGE: &'static str = "
+Usage: prog build --output=<file> [-v]
+       prog test [--output=<file>] [-v]
+
+Options:
+    -o, --output=<file>  Where to write results.
+    -v                   Verbose.
+";
+
+test_expect!(test_scoped_required_given, SCOPED_REQUIRED_USAGE,
+             &["build", "-o", "a.out"],
+             vec![("build", Switch(true)), ("test", Switch(false)),
+                  ("--output", Plain(Some("a.out".to_string())))]);
+
+test_user_error!(test_scoped_required_missing, SCOPED_REQUIRED_USAGE,
+                 &["build", "-v"]);
+
+test_expect!(test_scoped_optional_absent, SCOPED_REQUIRED_USAGE,
+             &["test", "-v"],
+             vec![("build", Switch(false)), ("test", Switch(true)),
+                  ("--output", Plain(None)), ("-v", Switch(true))]);
+
+test_expect!(test_scoped_optional_given, SCOPED_REQUIRED_USAGE,
+             &["test", "--output=r.txt"],
+             vec![("test", Switch(true)),
+                  ("--output", Plain(Some("r.txt".to_string())))]);
+
+#[test]
+fn test_new_strict_consistent() {
+    Docopt::new_strict("
+Usage: prog [options] <file>
+       prog --output=<dir> <file>
+
+Options:
+    -v, --verbose       Be loud.
+    -o, --output=<dir>  Output.
+").unwrap();
+}
+
+#[test]
+fn test_new_strict_usage_only() {
+    let err = Docopt::new_strict("
+Usage: prog [-v] --output=<dir> <file>
+
+Options:
+    -v, --verbose  Be loud.
+").unwrap_err();
+    assert_eq!(err.to_string(),
+               "Flags used in usage patterns but missing an option \
+                description: --output.");
+}
+
+#[test]
+fn test_new_strict_description_only() {
+    let err = Docopt::new_strict("
+Usage: prog --output=<dir> <file>
+
+Options:
+    --output=<dir>  Output.
+    --ouput=<dir>   Output, misspelled.
+    -q              Quiet.
+").unwrap_err();
+    assert_eq!(err.to_string(),
+               "Option descriptions not used in any usage pattern: -q, --ouput.");
+}
+
+#[test]
+fn test_sanitize_help() {
+    const USAGE: &'static str = "
+Usage: prog [options]
+
+Options:
+    -h, --help  Show \x1b[31mthis\x1b[0m message.\x1b]0;pwned\x07\x08
+";
+    let help = |yes: bool| {
+        Docopt::new(USAGE).unwrap()
+            .sanitize_help(yes)
+            .argv(&["prog", "--help"])
+            .parse()
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(help(true), "\
+Usage: prog [options]
+
+Options:
+    -h, --help  Show this message.");
+    assert_eq!(help(false), USAGE.trim());
+}
+
+const EMPTY_VALUE_USAGE: &'static str = "
+Usage: prog --prefix=<p> [--flag=<f>]
+
+Options:
+    --prefix=<p>  Install prefix.
+    --flag=<f>    Anything.
+";
+
+test_expect!(test_empty_value_equals, EMPTY_VALUE_USAGE, &["--prefix="],
+             vec![("--prefix", Plain(Some("".to_string()))),
+                  ("--flag", Plain(None))]);
+
+test_expect!(test_empty_value_separate, EMPTY_VALUE_USAGE, &["--prefix", ""],
+             vec![("--prefix", Plain(Some("".to_string())))]);
+
+test_expect!(test_value_keeps_everything_after_equals, EMPTY_VALUE_USAGE,
+             &["--prefix=a", "--flag=--weird=x=y"],
+             vec![("--prefix", Plain(Some("a".to_string()))),
+                  ("--flag", Plain(Some("--weird=x=y".to_string())))]);
+
+#[test]
+fn test_empty_value_decode() {
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        flag_prefix: Option<String>,
+        flag_flag: Option<String>,
+    }
+
+    let argvs: &[&[&str]] = &[&["prog", "--prefix="], &["prog", "--prefix", ""]];
+    for argv in argvs {
+        let vals = Docopt::new(EMPTY_VALUE_USAGE).unwrap()
+                          .argv(argv.iter()).parse().unwrap();
+        assert_eq!(vals.find("--prefix"), Some(&Plain(Some("".to_string()))));
+        assert_eq!(vals.get_str("--prefix"), "");
+        let args: Args = vals.decode().unwrap();
+        assert_eq!(args.flag_prefix, Some("".to_string()));
+        assert_eq!(args.flag_flag, None);
+    }
+}
+
+#[test]
+fn test_struct_field_mapping() {
+    let cases = &[
+        ("--dry-run", "flag_dry_run"),
+        ("-v", "flag_v"),
+        ("<input-file>", "arg_input_file"),
+        ("<input.file>", "arg_input_file"),
+        ("FILE", "arg_FILE"),
+        ("self.update", "cmd_self_update"),
+        ("self-update", "cmd_self_update"),
+    ];
+    for &(key, field) in cases {
+        assert_eq!(ArgvMap::key_to_struct_field(key), field);
+    }
+    assert_eq!(ArgvMap::struct_field_to_key("flag_dry_run"), "--dry-run");
+    assert_eq!(ArgvMap::struct_field_to_key("arg_input_file"), "<input-file>");
+    assert_eq!(ArgvMap::struct_field_to_key("arg_FILE"), "FILE");
+    assert_eq!(ArgvMap::struct_field_to_key("cmd_self_update"), "self-update");
+}
+
+#[test]
+fn test_struct_field_round_trip() {
+    // Build every key the parser produces from a mix of name pieces and
+    // separators, and check that fields survive a trip through a key.
+    let pieces = &["a", "dry", "Run", "x2", "FILE"];
+    let seps = &["", "-", ".", "_"];
+    let mut names = vec![];
+    for a in pieces {
+        for sep in seps {
+            for b in pieces {
+                names.push(format!("{}{}{}", a, sep, b));
+            }
+        }
+    }
+    let mut keys = vec![];
+    for name in &names {
+        keys.push(format!("--{}", name));
+        keys.push(format!("<{}>", name));
+        keys.push(name.clone());
+        keys.push(name.to_uppercase());
+    }
+    for c in "abzAZ019".chars() {
+        keys.push(format!("-{}", c));
+    }
+    for key in &keys {
+        let key = Atom::new(key).to_string();
+        let field = ArgvMap::key_to_struct_field(&key);
+        let back = ArgvMap::struct_field_to_key(&field);
+        assert_eq!(ArgvMap::key_to_struct_field(&back), field,
+                   "key '{}' went to field '{}' and back to '{}'",
+                   key, field, back);
+        if !key.contains('.') && !key.contains('_') {
+            assert_eq!(back, key);
+        }
+    }
+}
+
+#[test]
+fn test_struct_field_decode_dots() {
+    #[allow(non_snake_case)]
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        cmd_self_update: bool,
+        flag_dry_run: bool,
+        flag_x: bool,
+        arg_input_file: String,
+        arg_FILE: Vec<String>,
+    }
+
+    let args: Args = Docopt::new("
+Usage: prog self.update [--dry-run] [--x] <input.file> FILE...
+").unwrap().argv(&["prog", "self.update", "--dry-run", "--x", "in", "a", "b"])
+           .decode().unwrap();
+    assert!(args.cmd_self_update);
+    assert!(args.flag_dry_run);
+    assert!(args.flag_x);
+    assert_eq!(args.arg_input_file, "in");
+    assert_eq!(args.arg_FILE, vec!["a", "b"]);
+}
+
+const COMMAND_ENUM_USAGE: &'static str = "
+Usage: cargo (build | test | self.update) [--release]
+       cargo [build | test]
+";
+
+#[derive(RustcDecodable, Debug, PartialEq)]
+enum Command { Build, Test, SelfUpdate }
+
+#[derive(RustcDecodable, Debug)]
+struct CommandArgs {
+    cmd: Command,
+    cmd_build: bool,
+    flag_release: bool,
+}
+
+fn decode_command(argv: &[&str]) -> Result<CommandArgs, ::Error> {
+    Docopt::new(COMMAND_ENUM_USAGE).unwrap()
+           .argv(vec!["cargo"].iter().chain(argv.iter()))
+           .decode()
+}
+
+#[test]
+fn test_decode_command_enum() {
+    let args = decode_command(&["build"]).unwrap();
+    assert_eq!(args.cmd, Command::Build);
+    assert!(args.cmd_build);
+    let args = decode_command(&["test", "--release"]).unwrap();
+    assert_eq!(args.cmd, Command::Test);
+    assert!(!args.cmd_build && args.flag_release);
+    assert_eq!(decode_command(&["self.update"]).unwrap().cmd,
+               Command::SelfUpdate);
+}
+
+#[test]
+fn test_decode_command_enum_missing() {
+    match decode_command(&[]) {
+        Err(::Error::Decode(msg)) => {
+            assert!(msg.starts_with("None of the commands"), "{}", msg)
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
+
+#[derive(RustcDecodable, Debug, PartialEq)]
+enum Input { Stdin, File(String), Url(String) }
+
+#[derive(RustcDecodable, Debug)]
+struct InputArgs {
+    input: OneOf<Input>,
+    flag_verbose: bool,
+}
+
+#[derive(RustcDecodable, Debug)]
+struct MaybeInputArgs {
+    input: Option<OneOf<Input>>,
+    arg_extra: Option<String>,
+}
+
+fn decode_input<T: ::rustc_serialize::Decodable>(doc: &str, argv: &[&str])
+                                                -> Result<T, ::Error> {
+    let mut full = vec!["prog"];
+    full.extend(argv);
+    Docopt::new(doc).unwrap().argv(&full).decode()
+}
+
+#[test]
+fn test_decode_one_of_enum() {
+    let doc = "
+Usage: prog [--verbose] (--stdin | --file=<f> | <url>)
+
+Options:
+    --verbose   Be loud.
+    --stdin     Read stdin.
+    --file=<f>  Read a file.
+";
+    let args: InputArgs = decode_input(doc, &["--stdin"]).unwrap();
+    assert_eq!(*args.input, Input::Stdin);
+    let args: InputArgs = decode_input(doc, &["--file", "a.txt"]).unwrap();
+    assert_eq!(*args.input, Input::File("a.txt".to_string()));
+    let args: InputArgs = decode_input(doc, &["-", "--verbose"]).unwrap();
+    assert_eq!(*args.input, Input::Url("-".to_string()));
+    assert!(args.flag_verbose);
+}
+
+#[test]
+fn test_decode_one_of_enum_conflict() {
+    let doc = "
+Usage: prog [--verbose] [--stdin] [--file=<f>] [<url>]
+
+Options:
+    --verbose   Be loud.
+    --stdin     Read stdin.
+    --file=<f>  Read a file.
+";
+    match decode_input::<InputArgs>(doc, &["--stdin", "--file=a"]) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Only one of '--stdin' and '--file' may be \
+                             given (for struct field 'input').");
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+    match decode_input::<InputArgs>(doc, &["--stdin", "--file=a", "u"]) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Only one of '--stdin', '--file' and '<url>' \
+                             may be given (for struct field 'input').");
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+    match decode_input::<InputArgs>(doc, &["--verbose"]) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "One of '--stdin', '--file' or '<url>' must be \
+                             given (for struct field 'input').");
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+
+    let args: MaybeInputArgs = decode_input(doc, &[]).unwrap();
+    assert_eq!(args.input, None);
+    assert_eq!(args.arg_extra, None);
+    let args: MaybeInputArgs = decode_input(doc, &["--file=a"]).unwrap();
+    assert_eq!(args.input, Some(OneOf(Input::File("a".to_string()))));
+    assert!(decode_input::<MaybeInputArgs>(doc, &["--stdin", "x"]).is_err());
+
+    assert!(Docopt::new(doc).unwrap().check_defaults::<InputArgs>().is_ok());
+}
+
+#[test]
+fn test_decode_one_of_enum_unknown_variant() {
+    match decode_input::<InputArgs>("Usage: prog [--verbose] --stdin",
+                                    &["--stdin"]) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not find an argument for variant 'File' \
+                             (for struct field 'input').");
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
+
+const TOGGLE_USAGE: &'static str = "
+Usage: ed [options] [-v...] [<file>]
+
+Options:
+    -a           Autoprint.
+    -s           Silent.
+    -v           Verbose.
+    -p PROMPT    Prompt.
+";
+
+fn toggle_args(argv: &[&'static str]) -> Result<ArgvMap, ::Error> {
+    Docopt::new(TOGGLE_USAGE).unwrap()
+           .plus_minus_toggles(true)
+           .argv(vec!["ed"].iter().chain(argv.iter()))
+           .parse()
+}
+
+#[test]
+fn test_plus_minus_toggles() {
+    let vals = toggle_args(&["+a"]).unwrap();
+    assert!(vals.get_bool("-a"));
+    assert!(!vals.get_bool("-s"));
+
+    let vals = toggle_args(&["-a"]).unwrap();
+    assert_eq!(vals.find("-a"), Some(&Switch(false)));
+}
+
+#[test]
+fn test_plus_minus_toggles_together() {
+    let vals = toggle_args(&["+a", "-s", "file"]).unwrap();
+    assert!(vals.get_bool("-a"));
+    assert!(!vals.get_bool("-s"));
+    assert_eq!(vals.get_str("<file>"), "file");
+
+    let vals = toggle_args(&["+as", "-vv", "-p", ">"]).unwrap();
+    assert!(vals.get_bool("-a") && vals.get_bool("-s"));
+    assert_eq!(vals.get_count("-v"), 2);
+    assert_eq!(vals.get_str("-p"), ">");
+
+    // The last toggle wins.
+    assert!(!toggle_args(&["+a", "-a"]).unwrap().get_bool("-a"));
+    assert!(toggle_args(&["-a", "+a"]).unwrap().get_bool("-a"));
+}
+
+#[test]
+fn test_plus_minus_toggles_errors() {
+    assert!(toggle_args(&["+p"]).is_err());
+    assert!(toggle_args(&["+v"]).is_err());
+    assert!(toggle_args(&["+x"]).is_err());
+}
+
+#[test]
+fn test_plus_minus_toggles_disabled() {
+    let vals = get_args(TOGGLE_USAGE, &["+a"]);
+    assert_eq!(vals.get_str("<file>"), "+a");
+    assert!(!vals.get_bool("-a"));
+}
+
+#[test]
+fn test_strict_short_stacks() {
+    let doc = "Usage: prog [-a] [-b] [-o <file>] [<x>]
+
+Options:
+  -a
+  -b
+  -o <file>";
+    let dopt = Docopt::new(doc).unwrap().strict_short_stacks(true);
+    let parse = |argv: &[&str]| {
+        dopt.argv(vec!["prog"].iter().chain(argv.iter())).parse()
+    };
+    match parse(&["-oab"]) {
+        Err(::Error::WithProgramUsage(ref err, _)) => match **err {
+            ::Error::Argv(ref msg) => {
+                assert_eq!(msg, "Flag '-o' requires an argument, but is \
+                                 followed by other flags in '-oab'.")
+            }
+            ref err => panic!("expected argv error, got {:?}", err),
+        },
+        r => panic!("expected argv error, got {:?}", r),
+    }
+    assert!(parse(&["-aob"]).is_err());
+    assert_eq!(parse(&["-ovalue"]).unwrap().get_str("-o"), "value");
+    assert_eq!(parse(&["-abovalue"]).unwrap().get_str("-o"), "value");
+    let vals = parse(&["-abo", "value"]).unwrap();
+    assert!(vals.get_bool("-a") && vals.get_bool("-b"));
+    assert_eq!(vals.get_str("-o"), "value");
+
+    // Off by default.
+    let vals = get_args(doc, &["-oab"]);
+    assert_eq!(vals.get_str("-o"), "ab");
+    assert!(!vals.get_bool("-a"));
+}
+
+#[test]
+fn test_required_positional_count() {
+    let dopt = Docopt::new("
+Usage: prog add <src> [<dst>]
+       prog copy <src>... <dst> [--force]
+       prog (merge <a> <b> | split <c>) [<rest>...]
+       prog --help
+").unwrap();
+    assert_eq!(dopt.required_positional_count(), (0, 2));
+
+    let dopt = Docopt::new("Usage: prog <a> [<b>...]").unwrap();
+    assert_eq!(dopt.required_positional_count(), (1, 1));
+
+    let dopt = Docopt::new("Usage: prog (<a> | <b> <c>)").unwrap();
+    assert_eq!(dopt.required_positional_count(), (1, 2));
+}
+
+const SYNONYM_USAGE: &'static str = "
+Usage: prog [options] [-v...]
+
+Options:
+    -q, --quiet       Be quiet.
+    -n                Dry run.
+    --name=<name>     A name.
+    -v, --verbose     Be loud.
+    -o, --out <file>  Output file.
+";
+
+#[test]
+fn test_synonym_lookup() {
+    let vals = get_args(SYNONYM_USAGE,
+                        &["-q", "-n", "--name", "x", "-vv", "--out", "f"]);
+    assert!(vals.get_bool("-q") && vals.get_bool("--quiet"));
+    assert!(vals.get_bool("-n"));
+    assert_eq!(vals.get_str("--name"), "x");
+    assert_eq!(vals.get_count("-v"), 2);
+    assert_eq!(vals.get_count("--verbose"), 2);
+    assert_eq!(vals.get_str("-o"), "f");
+    assert_eq!(vals.get_vec("--out"), vec!["f"]);
+    assert_eq!(vals.find("-o"), vals.find("--out"));
+}
+
+#[test]
+fn test_synonym_canonical() {
+    let vals = get_args(SYNONYM_USAGE, &[]);
+    assert_eq!(vals.canonical("-q"), Some("--quiet"));
+    assert_eq!(vals.canonical("--quiet"), Some("--quiet"));
+    assert_eq!(vals.canonical("-n"), Some("-n"));
+    assert_eq!(vals.canonical("--name"), Some("--name"));
+    assert_eq!(vals.canonical("-x"), None);
+}
+
+const SEP_USAGE: &'static str = "
+Usage: prog [options]
+
+Options:
+    --path=<p>     Search paths. [sep: :]
+    --tag=<t> ...  Tags. [sep: ,]
+    --mode=<m>     Modes. [sep: +] [default: a+b]
+    --name=<n>     A name.
+";
+
+#[test]
+fn test_sep_split() {
+    let vals = get_args(SEP_USAGE, &["--path", "a:b:c"]);
+    assert_eq!(vals.find("--path"), Some(&List(vec![
+        "a".into(), "b".into(), "c".into(),
+    ])));
+    assert_eq!(vals.get_vec("--mode"), vec!["a", "b"]);
+    assert_eq!(vals.find("--tag"), Some(&List(vec![])));
+}
+
+#[test]
+fn test_sep_single_value() {
+    let vals = get_args(SEP_USAGE, &["--path=a", "--name=x:y"]);
+    assert_eq!(vals.find("--path"), Some(&List(vec!["a".into()])));
+    assert_eq!(vals.find("--name"), Some(&Plain(Some("x:y".into()))));
+}
+
+#[test]
+fn test_sep_repeated() {
+    let vals = get_args(SEP_USAGE, &["--tag", "a,b", "--tag", "c"]);
+    assert_eq!(vals.get_vec("--tag"), vec!["a", "b", "c"]);
+}
+
+#[test]
+fn test_sep_errors() {
+    assert!(Docopt::new("
+Usage: prog [options]
+
+Options:
+    -v  Verbose. [sep: :]
+").is_err());
+    assert!(Docopt::new("
+Usage: prog [options]
+
+Options:
+    --path=<p>  Paths. [sep: ::]
+").is_err());
+}
+
+const DEFAULT_MODE_USAGE: &'static str = "
+Usage: prog [options]
+
+Options:
+    --tag=<t> ...     Tags. [default: latest stable]
+    --add=<t> ...     Tags. [default-mode: append] [default: latest stable]
+    --pre=<t> ...     Tags. [default: latest stable] [default-mode: prepend]
+    --path=<p>        Paths. [default: a:b] [default-mode: append] [sep: :]
+";
+
+#[test]
+fn test_default_mode() {
+    let vals = get_args(DEFAULT_MODE_USAGE, &[
+        "--tag=x", "--add=x", "--add=y", "--pre=x", "--path=c",
+    ]);
+    assert_eq!(vals.get_vec("--tag"), vec!["x"]);
+    assert_eq!(vals.get_vec("--add"), vec!["latest", "stable", "x", "y"]);
+    assert_eq!(vals.get_vec("--pre"), vec!["x", "latest", "stable"]);
+    assert_eq!(vals.get_vec("--path"), vec!["a", "b", "c"]);
+
+    // Without any values given, every mode gives the default.
+    let vals = get_args(DEFAULT_MODE_USAGE, &[]);
+    for key in &["--tag", "--add", "--pre"] {
+        assert_eq!(vals.get_vec(key), vec!["latest", "stable"]);
+    }
+    assert_eq!(vals.get_vec("--path"), vec!["a", "b"]);
+}
+
+#[test]
+fn test_default_mode_errors() {
+    assert!(Docopt::new("
+Usage: prog [options]
+
+Options:
+    -v  Verbose. [default-mode: append]
+").is_err());
+    assert!(Docopt::new("
+Usage: prog [options]
+
+Options:
+    --tag=<t> ...  Tags. [default-mode: merge]
+").is_err());
+    match Docopt::new("
+Usage: prog [options]
+
+Options:
+    --tag=<t>  Tag. [default: stable] [default-mode: append]
+") {
+        Err(::Error::Usage(msg)) => {
+            assert!(msg.starts_with("Cannot assign default mode 'append' to \
+                                     flag '--tag' that has a single value."),
+                    "{}", msg)
+        }
+        r => panic!("expected usage error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_count() {
+    let vals = get_args("
+Usage: prog [-q] [-v...] [--out=<file>] [--name=<name>] <file>...
+", &["-vvv", "--out", "o", "a", "b"]);
+    assert_eq!(vals.count("<file>"), 2);
+    assert_eq!(vals.count("--out"), 1);
+    assert_eq!(vals.count("--name"), 0);
+    assert_eq!(vals.count("-v"), 3);
+    assert_eq!(vals.count("-q"), 0);
+    assert_eq!(vals.count("--missing"), 0);
+
+    let vals = get_args("Usage: prog [-q] [<file>...]", &["-q"]);
+    assert_eq!(vals.count("-q"), 1);
+    assert_eq!(vals.count("<file>"), 0);
+}
+
+#[test]
+fn test_usage() {
+    let dopt = Docopt::new("
+Naval Fate.
+
+Usage:
+  naval_fate ship new <name>...
+  naval_fate ship <name> move <x> <y> [--speed=<kn>]
+  naval_fate --help
+
+Options:
+  -h --help     Show this screen.
+  --speed=<kn>  Speed in knots [default: 10].
+").unwrap();
+    assert_eq!(dopt.usage(), "\
+Usage:
+  naval_fate ship new <name>...
+  naval_fate ship <name> move <x> <y> [--speed=<kn>]
+  naval_fate --help");
+}
+
+#[test]
+fn test_rest_as_string() {
+    let dopt = Docopt::new("
+Usage: prog [-v] run <script>...
+       prog [-v] list [<script>...]
+").unwrap().rest_as_string("<script>").options_first(true);
+    let vals = dopt.clone()
+                   .argv(&["prog", "-v", "run", "echo", "a b", "-n"])
+                   .parse().unwrap();
+    assert_eq!(vals.find("<script>"), Some(&Plain(Some("echo a b -n".into()))));
+    assert!(vals.get_bool("-v"));
+
+    let vals = dopt.argv(&["prog", "list"]).parse().unwrap();
+    assert_eq!(vals.find("<script>"), Some(&Plain(None)));
+}
+
+const DOUBLE_DASH_USAGE: &'static str = "
+Usage: prog [options] [--] <file>...
+
+Options:
+    -v, --verbose  Be loud.
+";
+
+#[test]
+fn test_double_dash_in_pattern() {
+    let vals = get_args(DOUBLE_DASH_USAGE,
+                        &["-v", "--", "--not-a-flag", "-v"]);
+    assert!(vals.get_bool("--"));
+    assert!(vals.get_bool("--verbose"));
+    assert_eq!(vals.get_vec("<file>"), vec!["--not-a-flag", "-v"]);
+
+    let vals = get_args(DOUBLE_DASH_USAGE, &["a", "-v", "b"]);
+    assert!(!vals.get_bool("--"));
+    assert!(vals.get_bool("--verbose"));
+    assert_eq!(vals.get_vec("<file>"), vec!["a", "b"]);
+
+    let vals = get_args(DOUBLE_DASH_USAGE, &["a", "--", "-b", "--", "c"]);
+    assert!(vals.get_bool("--"));
+    assert!(!vals.get_bool("--verbose"));
+    assert_eq!(vals.get_vec("<file>"), vec!["a", "-b", "--", "c"]);
+}
+
+#[test]
+fn test_double_dash_decode() {
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        cmd_double_dash: bool,
+        flag_verbose: bool,
+        arg_file: Vec<String>,
+    }
+
+    let args: Args = Docopt::new(DOUBLE_DASH_USAGE).unwrap()
+                            .argv(&["prog", "--", "-x"])
+                            .decode().unwrap();
+    assert!(args.cmd_double_dash);
+    assert!(!args.flag_verbose);
+    assert_eq!(args.arg_file, vec!["-x"]);
+    assert_eq!(ArgvMap::struct_field_to_key("cmd_double_dash"), "--");
+}
+
+#[test]
+fn test_flags_and_positionals() {
+    let dopt = Docopt::new("
+Usage: prog add [options] <src> <dst>
+       prog rm [-f] FILE...
+
+Options:
+    -v, --verbose   Be loud.
+    --dry-run       Don't do anything.
+    -f              Force.
+").unwrap();
+    assert_eq!(dopt.flags(), vec!["--dry-run", "--verbose", "-f", "-v"]);
+    assert_eq!(dopt.positionals(), vec!["<dst>", "<src>", "FILE"]);
+}
+
+const DEFAULT_ARGV_USAGE: &'static str = "
+Usage: prog status [-s]
+       prog commit [-m <msg>]
+";
+
+fn default_argv_args(argv: &[&str]) -> ArgvMap {
+    Docopt::new(DEFAULT_ARGV_USAGE).unwrap()
+           .argv(vec!["prog"].iter().chain(argv.iter()))
+           .default_argv(vec!["status".into(), "-s".into()])
+           .parse().unwrap()
+}
+
+#[test]
+fn test_default_argv_empty() {
+    let vals = default_argv_args(&[]);
+    assert!(vals.get_bool("status") && vals.get_bool("-s"));
+    assert!(!vals.get_bool("commit"));
+}
+
+#[test]
+fn test_default_argv_bypassed() {
+    let vals = default_argv_args(&["commit"]);
+    assert!(vals.get_bool("commit"));
+    assert!(!vals.get_bool("status") && !vals.get_bool("-s"));
+
+    let vals = default_argv_args(&["status"]);
+    assert!(vals.get_bool("status") && !vals.get_bool("-s"));
+}
+
+#[derive(RustcDecodable, Debug)]
+struct KeyArgs {
+    flag_key: Vec<u8>,
+    flag_iv: Option<Vec<u8>>,
+    flag_tag: Vec<String>,
+}
+
+fn decode_key(enc: BytesEncoding, argv: &[&str])
+             -> Result<KeyArgs, ::Error> {
+    Docopt::new("Usage: prog --key=<key> [--iv=<iv>] [--tag=<tag>]").unwrap()
+           .bytes_encoding("--key", enc)
+           .bytes_encoding("--iv", enc)
+           .argv(vec!["prog"].iter().chain(argv.iter()))
+           .decode()
+}
+
+#[test]
+fn test_decode_vec_elements() {
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        arg_n: Vec<u32>,
+    }
+
+    let decode = |argv: &[&str]| -> Result<Args, ::Error> {
+        Docopt::new("Usage: prog <n>...").unwrap()
+               .argv(vec!["prog"].iter().chain(argv.iter()))
+               .decode()
+    };
+    assert_eq!(decode(&["1", "20", "300"]).unwrap().arg_n, vec![1, 20, 300]);
+    match decode(&["1", "x", "3"]) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode 'x' to u32 for '<n>' \
+                             (element 2 of 3).")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_decode_key_value_pairs() {
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        flag_D: Vec<(String, i64)>,
+    }
+
+    let decode = |argv: &[&str]| -> Result<Args, ::Error> {
+        Docopt::new("
+Usage: build [-D <define>]...
+
+Options:
+    -D <define>  Define a value as key=value.
+").unwrap().argv(vec!["build"].iter().chain(argv.iter())).decode()
+    };
+    let args = decode(&["-D", "count=5", "-Dlimit=-2"]).unwrap();
+    assert_eq!(args.flag_D, vec![("count".to_string(), 5),
+                                 ("limit".to_string(), -2)]);
+    assert_eq!(decode(&[]).unwrap().flag_D, vec![]);
+
+    match decode(&["-D", "count=five"]) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode 'five' to i64 for '-D' \
+                             (element 1 of 1, key 'count').")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+    // Only the first `=` separates the key from the value.
+    match decode(&["-D", "count=5", "-D", "x=1=2"]) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode '1=2' to i64 for '-D' \
+                             (element 2 of 2, key 'x').")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+    match decode(&["-D", "count"]) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode 'count' into a key=value pair \
+                             for '-D' (element 1 of 1).")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_decode_map() {
+    use std::collections::BTreeMap;
+    use DuplicateKeys;
+
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        flag_define: HashMap<String, String>,
+        flag_limit: BTreeMap<String, u32>,
+    }
+
+    let decode = |argv: &[&str], dups: DuplicateKeys| -> Result<Args, ::Error> {
+        Docopt::new("
+Usage: build [--define=<kv>...] [--limit=<kv>...]
+
+Options:
+    --define=<kv>  Define a parameter as key=value.
+    --limit=<kv>   Set a limit as key=value.
+").unwrap().duplicate_keys(dups)
+           .argv(vec!["build"].iter().chain(argv.iter())).decode()
+    };
+    let args = decode(&["--define=a=1", "--define=url=x?a=b", "--define=FOO",
+                        "--limit=n=3"], DuplicateKeys::Error).unwrap();
+    let mut expected = HashMap::new();
+    expected.insert("a".to_string(), "1".to_string());
+    expected.insert("url".to_string(), "x?a=b".to_string());
+    expected.insert("FOO".to_string(), "".to_string());
+    assert_eq!(args.flag_define, expected);
+    assert_eq!(args.flag_limit.into_iter().collect::<Vec<_>>(),
+               vec![("n".to_string(), 3)]);
+    assert!(decode(&[], DuplicateKeys::Error).unwrap().flag_define.is_empty());
+
+    let argv = &["--define=a=1", "--define=b=2", "--define=a=3"];
+    match decode(argv, DuplicateKeys::Error) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Duplicate key 'a' in 'a=3' for '--define' \
+                             (element 3 of 3).")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+    let args = decode(argv, DuplicateKeys::LastWins).unwrap();
+    assert_eq!(args.flag_define["a"], "3");
+    assert_eq!(args.flag_define.len(), 2);
+
+    match decode(&["--limit=n=3", "--limit==4"], DuplicateKeys::Error) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode '=4' into a key=value pair for \
+                             '--limit' (element 2 of 2): the key is empty.")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+    match decode(&["--limit=n=x"], DuplicateKeys::Error) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode 'x' to u32 for '--limit' \
+                             (element 1 of 1, key 'n').")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_decode_digit_separators() {
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        flag_count: u64,
+        flag_offset: i32,
+    }
+
+    let doc = "Usage: prog [--count=<n>] [--offset=<n>]";
+    let decode = |argv: &[&str], sep: Option<char>| -> Result<Args, ::Error> {
+        let dopt = Docopt::new(doc).unwrap();
+        let dopt = match sep {
+            None => dopt,
+            Some(sep) => dopt.thousands_separator(sep).unwrap(),
+        };
+        dopt.argv(vec!["prog"].iter().chain(argv.iter())).decode()
+    };
+    let args = decode(&["--count=1_000_000", "--offset=-12_5"], None).unwrap();
+    assert_eq!(args.flag_count, 1_000_000);
+    assert_eq!(args.flag_offset, -125);
+    let args = decode(&["--count=1,234,567", "--offset=-1,000"], Some(','))
+                     .unwrap();
+    assert_eq!(args.flag_count, 1_234_567);
+    assert_eq!(args.flag_offset, -1_000);
+    assert_eq!(decode(&["--count=12_345"], Some(',')).unwrap().flag_count,
+               12_345);
+
+    match decode(&["--count=1,000_000"], Some(',')) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode '1,000_000' to u64 for \
+                             '--count'.")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+    for bad in &["--count=1,0000", "--count=,100", "--count=1,00"] {
+        assert!(decode(&[bad], Some(',')).is_err(), "{}", bad);
+    }
+    for bad in &["--count=_1", "--count=1_", "--count=1__0", "--count=1,000"] {
+        assert!(decode(&[bad], None).is_err(), "{}", bad);
+    }
+    for &sep in &['_', '-', '+', '0'] {
+        match Docopt::new(doc).unwrap().thousands_separator(sep) {
+            Err(::Error::Usage(msg)) => {
+                assert_eq!(msg, format!("Cannot use '{}' as a thousands \
+                                         separator.", sep))
+            }
+            r => panic!("expected usage error, got {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn test_bytes_hex() {
+    let args = decode_key(BytesEncoding::Hex, &["--key", "00ffDEad"]).unwrap();
+    assert_eq!(args.flag_key, vec![0x00, 0xff, 0xde, 0xad]);
+    assert_eq!(args.flag_iv, None);
+}
+
+#[test]
+fn test_bytes_base64() {
+    let args = decode_key(BytesEncoding::Base64,
+                          &["--key", "3q2+7w==", "--iv", "AQI="]).unwrap();
+    assert_eq!(args.flag_key, vec![0xde, 0xad, 0xbe, 0xef]);
+    assert_eq!(args.flag_iv, Some(vec![1, 2]));
+}
+
+#[test]
+fn test_bytes_other_keys() {
+    let args = decode_key(BytesEncoding::Hex,
+                          &["--key", "00ff", "--tag", "beef"]).unwrap();
+    assert_eq!(args.flag_key, vec![0x00, 0xff]);
+    assert_eq!(args.flag_tag, vec!["beef"]);
+}
+
+#[test]
+fn test_bytes_invalid() {
+    for &(enc, key) in &[(BytesEncoding::Hex, "xyz"),
+                         (BytesEncoding::Hex, "abc"),
+                         (BytesEncoding::Base64, "!!!")] {
+        match decode_key(enc, &["--key", key]) {
+            Err(::Error::Decode(msg)) => {
+                assert!(msg.starts_with(&format!("Could not decode '{}'", key)),
+                        "{}", msg)
+            }
+            r => panic!("expected decode error for '{}', got {:?}", key, r),
+        }
+    }
+}
+
+#[test]
+fn test_check_defaults() {
+    #[derive(RustcDecodable, Debug)]
+    enum Mode { Fast, Slow }
+
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        cmd_run: bool,
+        arg_input: String,
+        flag_jobs: u32,
+        flag_ratio: f64,
+        flag_mode: Mode,
+        flag_sep: char,
+        flag_limit: Option<u8>,
+    }
+
+    let doc = |jobs: &str| format!("
+Usage: prog run <input> [options]
+
+Options:
+    --jobs=<n>     Jobs [default: {}].
+    --ratio=<r>    Ratio.
+    --mode=<m>     Mode.
+    --sep=<c>      Separator.
+    --limit=<n>    Limit [default: 10].
+", jobs);
+    let check = |jobs: &str| {
+        Docopt::new(doc(jobs)).unwrap().check_defaults::<Args>()
+    };
+
+    check("4").unwrap();
+    match check("many") {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode 'many' to u32 for '--jobs'.")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_check_defaults_parsed() {
+    use Parsed;
+
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        flag_jobs: Parsed<u32>,
+        flag_port: Parsed<u32>,
+    }
+
+    let doc = |jobs: &str| format!("
+Usage: prog --port=<p> [--jobs=<n>]
+
+Options:
+    --port=<p>  Port.
+    --jobs=<n>  Jobs [default: {}].
+", jobs);
+    let check = |jobs: &str| {
+        Docopt::new(doc(jobs)).unwrap().check_defaults::<Args>()
+    };
+    check("4").unwrap();
+    match check("many") {
+        Err(::Error::Decode(msg)) => {
+            assert!(msg.starts_with("Could not decode 'many': "), "{}", msg)
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_no_usage_section() {
+    for doc in &["", "Just prose.", "Options:\n    -a  All.\n"] {
+        match Docopt::new(*doc) {
+            Err(::Error::Usage(msg)) => {
+                assert!(msg.starts_with("No usage patterns found: the doc \
+                                         string has no 'Usage:' section."),
+                        "{}", msg)
+            }
+            r => panic!("expected usage error for {:?}, got {:?}", doc, r),
+        }
+    }
+}
+
+#[test]
+fn test_empty_usage_section() {
+    let docs = &[
+        "Usage:",
+        "Usage:   \n",
+        "usage:\n\nOptions:\n    -a  All.\n",
+    ];
+    for doc in docs {
+        match Docopt::new(*doc) {
+            Err(::Error::Usage(msg)) => {
+                assert!(msg.starts_with("No usage patterns found: the \
+                                         'Usage:' section is empty."),
+                        "{}", msg)
+            }
+            r => panic!("expected usage error for {:?}, got {:?}", doc, r),
+        }
+    }
+    // The first pattern may still start on a line after `Usage:`, even
+    // after a blank line.
+    Docopt::new("Usage:\n    prog run\n").unwrap();
+    let dopt = Docopt::new("Usage:\n\n    prog run\n").unwrap();
+    let vals = dopt.argv(&["prog", "run"]).parse().unwrap();
+    assert!(vals.get_bool("run"));
+}
+
+#[test]
+fn test_help_and_version_strings() {
+    let doc = "
+Usage: prog [--verbose]
+       prog --version
+
+Options:
+    --verbose  Be loud.
+";
+    let dopt = Docopt::new(doc).unwrap();
+    assert_eq!(dopt.help_string(), doc.trim());
+    assert_eq!(dopt.version_string(), None);
+
+    let dopt = dopt.version(Some("prog 1.2.3".into()));
+    assert_eq!(dopt.help_string(), doc.trim());
+    assert_eq!(dopt.version_string(), Some("prog 1.2.3"));
+}
+
+#[test]
+fn test_help_flags() {
+    let doc = "
+Usage: prog [options]
+
+Options:
+    -h, --host=<host>     Server host.
+    --help                Show help.
+    --version=<version>   Protocol version.
+";
+    let parse = |dopt: Docopt, argv: &[&str]| {
+        dopt.version(Some("prog 1.0".into()))
+            .argv(vec!["prog"].iter().chain(argv.iter()))
+            .parse()
+    };
+    let is_help = |r: Result<ArgvMap, ::Error>| match r {
+        Err(::Error::WithProgramUsage(err, _)) => match *err {
+            ::Error::Help => true,
+            _ => false,
+        },
+        _ => false,
+    };
+    let dopt = Docopt::new(doc).unwrap();
+    let flags = dopt.clone().help_flags(&["-h", "--help"]);
+
+    let vals = parse(flags.clone(), &["-h", "example.com", "--version=2"])
+                   .unwrap();
+    assert_eq!(vals.get_str("--host"), "example.com");
+    assert_eq!(vals.get_str("--version"), "2");
+    assert!(is_help(parse(flags.clone(), &["--help"])));
+    assert!(is_help(parse(dopt.clone(), &["-h", "x", "--help"])));
+    assert!(!is_help(parse(dopt.clone().help(false), &["--help"])));
+
+    let dopt = Docopt::new("Usage: prog [-?] [-h]").unwrap()
+                      .help_flags(&["-?"]);
+    assert!(is_help(parse(dopt.clone(), &["-?"])));
+    assert!(!is_help(parse(dopt, &["-h"])));
+}
+
+#[test]
+fn test_help_layout_required_first() {
+    use HelpLayout;
+
+    let doc = "
+Usage: deploy [options] --target=<host> <app>
+       deploy [options] --target=<host> --all
+
+Options:
+  -v, --verbose        Say more.
+  --dry-run            Don't change anything.
+  -t, --target=<host>  Where to deploy,
+                       e.g., prod.
+  --all                Deploy every app.
+  --help               Show this.
+
+Other text stays.
+";
+    let dopt = Docopt::new(doc).unwrap();
+    assert_eq!(dopt.help_string(), doc.trim());
+    let dopt = dopt.help_layout(HelpLayout::RequiredFirst);
+    assert_eq!(dopt.help_string(), "\
+Usage: deploy [options] --target=<host> <app>
+       deploy [options] --target=<host> --all
+
+Options:
+  -t, --target=<host>  Where to deploy,
+                       e.g., prod.
+  -v, --verbose        Say more.
+  --dry-run            Don't change anything.
+  --all                Deploy every app.
+  --help               Show this.
+
+Other text stays.");
+    match dopt.argv(&["deploy", "--help", "-tx", "a"]).parse() {
+        Err(::Error::WithProgramUsage(err, text)) => {
+            assert!(match *err { ::Error::Help => true, _ => false });
+            assert!(text.find("--target").unwrap()
+                    < text.find("--verbose").unwrap(), "{}", text)
+        }
+        r => panic!("expected help, got {:?}", r),
+    }
+    let dopt = dopt.help_layout(HelpLayout::AsWritten);
+    assert_eq!(dopt.help_string(), doc.trim());
+}
+
+#[test]
+fn test_assert_shape() {
+    use ValueKind::{Switch, Counted, Plain, List};
+
+    let vals = get_args("
+Usage: prog [-v...] [-q] [--out=<f>] <in>...
+
+Options:
+    -q, --quiet  Be quiet.
+", &["a", "b"]);
+    assert_eq!(vals.assert_shape(&[
+        ("-v", Counted), ("--quiet", Switch), ("-q", Switch),
+        ("--out", Plain), ("<in>", List),
+    ]), Ok(()));
+    assert_eq!(vals.assert_shape(&[]), Ok(()));
+    assert_eq!(vals.assert_shape(&[
+        ("-v", Switch), ("--quiet", Switch), ("--out", List), ("<file>", List),
+    ]), Err("3 of 4 keys have the wrong shape:
+  '-v' is Counted (expected Switch)
+  '--out' is Plain (expected List)
+  '<file>' is missing (expected List)".to_string()));
+}
+
+#[test]
+fn test_index() {
+    let vals = get_args("
+Usage: prog [-q] <file>...
+
+Options:
+    -q, --quiet  Be quiet.
+", &["-q", "a", "b"]);
+    assert_eq!(vals["--quiet"], Switch(true));
+    assert_eq!(vals["-q"], Switch(true));
+    assert_eq!(vals["<file>"], List(vec!["a".into(), "b".into()]));
+}
+
+#[test]
+#[should_panic(expected = "Unknown ArgvMap key: '--loud'")]
+fn test_index_unknown_key() {
+    let vals = get_args("Usage: prog [-q]", &[]);
+    let _ = &vals["--loud"];
+}
+
+#[test]
+fn test_ignore_case_for() {
+    let doc = "
+Usage: prog [options]
+
+Options:
+    -v, --verbose     Be loud.
+    -V, --version     Show version.
+    --dry-run         Do nothing.
+    --out=<file>      Output.
+";
+    let parse = |argv: &[&str]| {
+        Docopt::new(doc).unwrap()
+               .help(false)
+               .ignore_case_for(&["--verbose", "--out"])
+               .argv(vec!["prog"].iter().chain(argv.iter()))
+               .parse()
+    };
+
+    let vals = parse(&["--VERBOSE", "--Out=a"]).unwrap();
+    assert!(vals.get_bool("--verbose"));
+    assert_eq!(vals.get_str("--out"), "a");
+    let vals = parse(&["--Out", "b"]).unwrap();
+    assert_eq!(vals.get_str("--out"), "b");
+
+    // An exact match wins over a case insensitive one.
+    let vals = parse(&["-V"]).unwrap();
+    assert!(vals.get_bool("--version"));
+    assert!(!vals.get_bool("--verbose"));
+
+    // Other flags stay strict.
+    assert!(parse(&["--Dry-Run"]).is_err());
+    assert!(parse(&["--VERSION"]).is_err());
+}
+
+#[test]
+fn test_validate() {
+    let doc = "
+Usage: serve [--port=<n>] [-I <dir>]...
+
+Options:
+    --port=<n>  Port [default: 8080].
+    -I <dir>    Include a directory.
+";
+    fn port(v: &str) -> Result<(), String> {
+        match v.parse::<u16>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err("must be a number from 1 to 65535".into()),
+        }
+    }
+    fn dir(v: &str) -> Result<(), String> {
+        if v.starts_with('/') {
+            Ok(())
+        } else {
+            Err("must be an absolute path".into())
+        }
+    }
+    let parse = |argv: &[&str]| {
+        Docopt::new(doc).unwrap()
+               .validate("--port", port).unwrap()
+               .validate("-I", dir).unwrap()
+               .argv(vec!["serve"].iter().chain(argv.iter()))
+               .parse()
+    };
+
+    let vals = parse(&["-I", "/a", "-I", "/b"]).unwrap();
+    assert_eq!(vals.get_str("--port"), "8080");
+    assert_eq!(vals.get_vec("-I"), vec!["/a", "/b"]);
+
+    match parse(&["-I", "/a", "-I", "b"]) {
+        Err(::Error::WithProgramUsage(err, usage)) => {
+            match *err {
+                ::Error::Argv(ref msg) => {
+                    assert_eq!(msg, "Invalid value 'b' for '-I': \
+                                     must be an absolute path")
+                }
+                ref err => panic!("expected argv error, got {:?}", err),
+            }
+            assert!(usage.starts_with("Usage: serve"));
+        }
+        r => panic!("expected argv error, got {:?}", r),
+    }
+    match parse(&["--port", "0"]) {
+        Err(::Error::WithProgramUsage(err, _)) => {
+            assert_eq!(err.to_string(), "Invalid value '0' for '--port': \
+                                         must be a number from 1 to 65535")
+        }
+        r => panic!("expected argv error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_validate_unknown_key() {
+    match Docopt::new("Usage: serve [--port=<n>]").unwrap()
+                 .validate("--prot", |_| Ok(())) {
+        Err(::Error::Usage(msg)) => {
+            assert_eq!(msg, "Cannot add a validator for unknown key '--prot'.")
+        }
+        r => panic!("expected usage error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_many_repeated_args() {
+    let doc = "Usage: prog [--include=<dir>]... <file>...";
+    let files: Vec<String> = (0..20000).map(|i| format!("f{}", i)).collect();
+    let mut argv = vec!["prog".to_string()];
+    for file in &files {
+        argv.push(file.clone());
+        argv.push(format!("--include={}", file));
+    }
+    let vals = Docopt::new(doc).unwrap()
+                               .argv(argv.iter().map(|s| &**s))
+                               .parse().unwrap();
+    assert_eq!(vals.get_vec("<file>"), files);
+    assert_eq!(vals.get_vec("--include"), files);
+}
+
+#[test]
+fn test_from_pairs_decode() {
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        cmd_build: bool,
+        flag_verbose: u64,
+        flag_out: Option<String>,
+        flag_jobs: Option<u32>,
+        arg_file: Vec<String>,
+    }
+
+    let vals = ArgvMap::from_pairs(vec![
+        ("build", Switch(true)),
+        ("--verbose", Counted(2)),
+        ("--out", Plain(None)),
+        ("--jobs", Plain(Some("4".to_string()))),
+        ("<file>", List(vec!["a".to_string(), "b".to_string()])),
+    ]);
+    assert_eq!(vals.len(), 5);
+    let json = concat!(r#"{"build":true,"--verbose":2,"--out":null,"#,
+                       r#""--jobs":"4","<file>":["a","b"]}"#);
+    assert_eq!(vals.to_json(), json);
+    let args: Args = vals.decode().unwrap();
+    assert!(args.cmd_build);
+    assert_eq!(args.flag_verbose, 2);
+    assert_eq!(args.flag_out, None);
+    assert_eq!(args.flag_jobs, Some(4));
+    assert_eq!(args.arg_file, vec!["a", "b"]);
+}
+
+#[test]
+fn test_missing_required() {
+    let missing = |doc: &str, argv: &[&str]| -> Vec<String> {
+        let mut full = vec!["prog"];
+        full.extend(argv);
+        Docopt::new(doc).unwrap()
+                        .missing_required(&full)
+                        .into_iter().map(|a| a.to_string()).collect()
+    };
+
+    let doc = "Usage: prog [-v] <file>";
+    assert_eq!(missing(doc, &[]), vec!["<file>"]);
+    assert_eq!(missing(doc, &["-v"]), vec!["<file>"]);
+    assert!(missing(doc, &["a"]).is_empty());
+
+    let doc = "
+Usage: prog add --name=<name> <file>...
+       prog rm [--force] <file>
+
+Options:
+    --name=<name>  The name.
+    --force        Remove it anyway.
+";
+    assert_eq!(missing(doc, &["add"]), vec!["--name", "<file>"]);
+    assert_eq!(missing(doc, &["add", "--name=x"]), vec!["<file>"]);
+    assert_eq!(missing(doc, &["rm"]), vec!["<file>"]);
+    assert_eq!(missing(doc, &["--force"]), vec!["rm", "<file>"]);
+    assert!(missing(doc, &["add", "a", "b", "--name", "x"]).is_empty());
+    assert_eq!(missing(doc, &["--unknown"]), vec!["rm", "<file>"]);
+}
+
+#[test]
+fn test_shared_across_threads() {
+    use std::sync::Arc;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Docopt>();
+
+    let dopt = Arc::new(Docopt::new("
+Usage: prog [-v...] <name>
+
+Options:
+    -v  Be verbose.
+").unwrap().validate("<name>", |s| {
+        if s.starts_with("n") { Ok(()) } else { Err("bad".to_string()) }
+    }).unwrap());
+    let handles: Vec<_> = (0..8).map(|i| {
+        let dopt = dopt.clone();
+        thread::spawn(move || {
+            for j in 0..50 {
+                let name = format!("n{}-{}", i, j);
+                let verbose = vec!["-v"; j % 4];
+                let mut argv = vec!["prog", &*name];
+                argv.extend(verbose);
+                let vals = dopt.argv(&argv).parse().unwrap();
+                assert_eq!(vals.get_str("<name>"), name);
+                assert_eq!(vals.get_count("-v"), (j % 4) as u64);
+                assert!(dopt.argv(&["prog", "x"]).parse().is_err());
+            }
+        })
+    }).collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn test_flag_order() {
+    let doc = "
+Usage: prog [-v...] [-q] [-o <out>] [<file>]
+
+Options:
+    -v, --verbose      Be loud.
+    -q, --quiet        Be quiet.
+    -o <out>           The output.
+";
+    let order = |argv: &[&'static str]| -> Vec<String> {
+        get_args(doc, argv).order().iter().map(|a| a.to_string()).collect()
+    };
+    assert_eq!(order(&["-v", "--quiet", "-v"]),
+               vec!["--verbose", "--quiet", "--verbose"]);
+    assert_eq!(order(&["a", "-vqo", "x", "--verbose"]),
+               vec!["--verbose", "--quiet", "-o", "--verbose"]);
+    assert!(order(&["a"]).is_empty());
+
+    let vals = Docopt::new(doc).unwrap()
+                               .plus_minus_toggles(true)
+                               .argv(&["prog", "-q", "+q", "-v"])
+                               .parse().unwrap();
+    let order: Vec<String> = vals.order().iter().map(|a| a.to_string())
+                                 .collect();
+    assert_eq!(order, vec!["--quiet", "--quiet", "--verbose"]);
+}
+
+#[test]
+fn test_complete_values() {
+    let doc = "
+Usage: prog [--profile=<name>] [--region=<r>] <file>
+
+Options:
+    -p, --profile=<name>  The profile to use.
+    --region=<r>          The region.
+";
+    let dopt = Docopt::new(doc).and_then(|d| d.complete_values("-p", || {
+        vec!["dev".to_string(), "prod".to_string(), "stage".to_string()]
+    })).unwrap();
+    let complete = |argv: &[&str]| -> String {
+        let mut full = vec!["prog"];
+        full.extend(argv);
+        match dopt.argv(&full).parse() {
+            Err(err @ ::Error::Complete(_)) => {
+                assert!(!err.fatal());
+                err.to_string()
+            }
+            r => panic!("Expected completions, got {:?}", r),
+        }
+    };
+    assert_eq!(complete(&["--complete", "--profile"]), "dev\nprod\nstage");
+    assert_eq!(complete(&["--complete", "-p", "pr"]), "prod");
+    assert_eq!(complete(&["--complete", "--profile", "x"]), "");
+    assert_eq!(complete(&["--complete", "--region"]), "");
+    assert_eq!(complete(&["--complete"]), "");
+
+    // The flag is hidden, so it's only special at the start of argv and
+    // only when there are completions.
+    assert!(dopt.argv(&["prog", "a", "--complete"]).parse().is_err());
+    match Docopt::new(doc).unwrap().argv(&["prog", "--complete"]).parse() {
+        Err(::Error::WithProgramUsage(..)) => {}
+        r => panic!("Expected an argv error, got {:?}", r),
+    }
+
+    match Docopt::new(doc).unwrap().complete_values("--nope", || vec![]) {
+        Err(::Error::Usage(msg)) => {
+            assert_eq!(msg, "Cannot add completions for unknown key '--nope'.");
+        }
+        r => panic!("Expected a usage error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_get_typed_vec() {
+    let doc = "Usage: prog [--port=<n>...] [<size>...]";
+    let vals = get_args(doc, &["--port=80", "--port=443", "1.5", "2"]);
+    assert_eq!(vals.get_typed_vec::<u16>("--port").unwrap(), vec![80, 443]);
+    assert_eq!(vals.get_typed_vec::<f64>("<size>").unwrap(), vec![1.5, 2.0]);
+    assert!(get_args(doc, &[]).get_typed_vec::<u16>("--port")
+                              .unwrap().is_empty());
+
+    let vals = get_args(doc, &["--port=80", "--port=http", "--port=8080"]);
+    match vals.get_typed_vec::<u16>("--port") {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode 'http' for '--port' \
+                             (element 2 of 3): invalid digit found in string");
+        }
+        r => panic!("Expected a decode error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_source_text() {
+    let docs = [
+        "Usage: prog [-v] <file>  \n\nOptions:\n    -v  Verbose.   \n",
+        "Usage:\tprog [-v] <file>\n\nOptions:\n\t-v  Verbose.\t\n",
+        "Usage: prog [-v] <file>\r\n\r\nOptions:\r\n    -v  Verbose.\r\n",
+        "\u{feff}Usage: prog [-v] <file>\n\nOptions:\n    -v  Verbose.\n",
+    ];
+    for doc in &docs {
+        let dopt = Docopt::new(*doc).unwrap();
+        assert_eq!(dopt.source_text().as_bytes(), doc.as_bytes());
+
+        let vals = dopt.argv(&["prog", "-v", "a"]).parse().unwrap();
+        assert!(vals.get_bool("-v"));
+        assert_eq!(vals.get_str("<file>"), "a");
+
+        let help = dopt.help_string();
+        assert!(help.starts_with("Usage:"), "{:?}", help);
+        assert!(help.ends_with("Verbose."), "{:?}", help);
+        assert!(!help.contains('\r'), "{:?}", help);
+    }
+}
+
+#[test]
+fn test_usage_error_location() {
+    use parse::Parser;
+
+    let doc = "Frobnicate.\n\nUsage:\n    prog [--verbose <file>\n";
+    let err = Parser::new(doc).unwrap_err();
+    assert_eq!(err.to_string(), "\
+Unclosed group. Expected ']'.
+ --> line 4, column 27
+  |
+4 |     prog [--verbose <file>
+  |                           ^");
+    let loc = err.location.unwrap();
+    assert_eq!((loc.offset, loc.len), (doc.len() - 1, 0));
+
+    let doc = "Usage: prog (a | b]\n";
+    assert_eq!(Parser::new(doc).unwrap_err().to_string(), "\
+Expected ')' but got ']'.
+ --> line 1, column 19
+  |
+1 | Usage: prog (a | b]
+  |                   ^");
+
+    // Columns count characters, and offsets count bytes in the doc string
+    // as it was given.
+    let doc = "\u{feff}Usage:\r\n  prög <ñame> --x=<ü> ) <a>\r\n";
+    let err = Parser::new(doc).unwrap_err();
+    assert_eq!(err.to_string(), "\
+Unexpected ')'. No open bracket found.
+ --> line 2, column 23
+  |
+2 |   prög <ñame> --x=<ü> ) <a>
+  |                       ^");
+    let loc = err.location.unwrap();
+    assert_eq!(&doc[loc.offset..loc.offset + loc.len], ")");
+
+    let doc = "Usage: prog [options]\r\n\r\nOptions:\r\n\
+               \u{2003}-ä, -b  Two short flags.\r\n";
+    let err = Parser::new(doc).unwrap_err();
+    assert_eq!(err.to_string(), "\
+Only one short flag is allowed in an option description, but found '-ä' \
+and '-b'.
+ --> line 4, column 2
+  |
+4 | \u{2003}-ä, -b  Two short flags.
+  |  ^^^^^^");
+    let loc = err.location.unwrap();
+    assert_eq!(&doc[loc.offset..loc.offset + loc.len], "-ä, -b");
+
+    // Errors without a location are shown as before.
+    assert_eq!(Parser::new("Just prose.").unwrap_err().location, None);
+    match Docopt::new("Usage: prog <a>...... x") {
+        Err(::Error::Usage(msg)) => {
+            assert!(msg.starts_with("'...' must appear directly after"), "{}",
+                    msg);
+            assert!(msg.contains("--> line 1, column 19"), "{}", msg);
+        }
+        r => panic!("expected usage error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_choices() {
+    let doc = "
+Usage: prog [--format=<fmt>] [--tags=<t>]
+
+Options:
+  -f, --format=<fmt>  Output format [choices: json yaml toml]
+                      [default: json].
+  --tags=<t>          Tags [sep: ,] [choices: a b c].
+";
+    let vals = get_args(doc, &["-f", "yaml", "--tags=c,a"]);
+    assert_eq!(vals.get_str("--format"), "yaml");
+    assert_eq!(vals.get_vec("--tags"), vec!["c", "a"]);
+    assert_eq!(get_args(doc, &[]).get_str("--format"), "json");
+
+    let parse = |argv: &[&str]| {
+        Docopt::new(doc).unwrap()
+                        .argv(vec!["prog"].iter().chain(argv.iter()))
+                        .parse()
+    };
+    match parse(&["--format=xml"]) {
+        Err(::Error::WithProgramUsage(err, _)) => match *err {
+            ::Error::Argv(msg) => {
+                assert_eq!(msg, "Invalid value 'xml' for '--format'. It \
+                                 must be one of 'json', 'yaml' or 'toml'.")
+            }
+            err => panic!("expected argv error, got {:?}", err),
+        },
+        r => panic!("expected argv error, got {:?}", r),
+    }
+    match parse(&["-fJSON"]) {
+        Err(::Error::WithProgramUsage(err, _)) => {
+            assert!(err.to_string().starts_with("Invalid value 'JSON' for \
+                                                 '--format'."), "{}", err)
+        }
+        r => panic!("expected argv error, got {:?}", r),
+    }
+    match parse(&["--tags", "a,d"]) {
+        Err(::Error::WithProgramUsage(err, _)) => {
+            assert!(err.to_string().starts_with("Invalid value 'd' for \
+                                                 '--tags'."), "{}", err)
+        }
+        r => panic!("expected argv error, got {:?}", r),
+    }
+
+    let bad = &[
+        "Usage: prog [-v]\n\nOptions:\n  -v  Verbose [choices: a b].",
+        "Usage: prog [--f=<x>]\n\nOptions:\n  --f=<x>  F [choices: ].",
+        "Usage: prog [--f=<x>]\n\nOptions:\n  --f=<x>  F [choices: a]\n\
+         \x20          [choices: b].",
+    ];
+    for doc in bad {
+        assert!(Docopt::new(*doc).is_err(), "{}", doc);
+    }
+}
+
+#[test]
+fn test_choices_default_same_line() {
+    let doc = "
+Usage: prog [--format=<fmt>]
+
+Options:
+  --format=<fmt>  Output format [default: json] [choices: json yaml].
+";
+    let vals = get_args(doc, &[]);
+    assert_eq!(vals.get_str("--format"), "json");
+    assert_eq!(get_args(doc, &["--format=yaml"]).get_str("--format"), "yaml");
+
+    let doc = "
+Usage: prog [--format=<fmt>]
+
+Options:
+  --format=<fmt>  Output format [choices: json yaml] [default: xml].
+";
+    match Docopt::new(doc) {
+        Err(::Error::Usage(msg)) => {
+            assert!(msg.contains("Default value 'xml' for '--format' must \
+                                  be one of 'json' or 'yaml'."), "{}", msg)
+        }
+        r => panic!("expected usage error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_from_path_include() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/test/include/");
+    let dopt = Docopt::from_path(format!("{}tool.docopt", dir)).unwrap();
+    let vals = dopt.argv(&["tool", "-v", "--output=x", "f"]).parse().unwrap();
+    assert!(vals.get_bool("--verbose"));
+    assert!(!vals.get_bool("--dry-run"));
+    assert_eq!(vals.get_str("--output"), "x");
+    assert_eq!(vals.get_str("<file>"), "f");
+
+    let dopt = Docopt::from_path(format!("{}tool.docopt", dir)).unwrap();
+    assert_eq!(dopt.source_text(), "\
+Usage: tool [options] <file>
+
+Options:
+  --dry-run       Don't write anything.
+  -o, --output=<path>  Where to write [default: out].
+  -v, --verbose   Say more.
+");
+
+    match Docopt::from_path(format!("{}cycle.docopt", dir)) {
+        Err(::Error::Usage(msg)) => {
+            assert!(msg.ends_with("cycle.docopt' includes itself."), "{}", msg)
+        }
+        r => panic!("expected usage error, got {:?}", r),
+    }
+    match Docopt::from_path(format!("{}missing.docopt", dir)) {
+        Err(::Error::Usage(msg)) => {
+            assert!(msg.starts_with("Could not read '"), "{}", msg)
+        }
+        r => panic!("expected usage error, got {:?}", r),
+    }
+
+    // `%include` lines are resolved relative to the usage file too.
+    let dopt = Docopt::from_path(format!("{}mixed.docopt", dir)).unwrap();
+    let vals = dopt.argv(&["mixed", "-n", "-v"]).parse().unwrap();
+    assert!(vals.get_bool("--dry-run"));
+    assert!(vals.get_bool("--verbose"));
+    assert_eq!(vals.get_str("--output"), "out");
+}
+
+#[test]
+fn test_include_resolver() {
+    fn texts(name: &str) -> Result<String, ::Error> {
+        Ok(match name {
+            "common" => "  -v, --verbose  Say more.\n  %include output",
+            "output" => "  -o, --output=<path>  Where to write [default: out].",
+            "self" => "  -q  Quiet.\n  %include loop",
+            "loop" => "  %include self",
+            "bad" => "  -x  X.\n  --output=<a> <b>  Two arguments.",
+            _ => return Err(::Error::Usage(format!("no text '{}'", name))),
+        }.to_string())
+    }
+    let include = |doc: &str| {
+        Docopt::new(doc).unwrap().include_resolver(texts)
+    };
+    let doc = "
+Usage: tool [options] <file>
+
+Options:
+  --dry-run  Don't write anything.
+  %include common
+";
+    let dopt = include(doc).unwrap();
+    let vals = dopt.argv(&["tool", "-v", "f"]).parse().unwrap();
+    assert!(vals.get_bool("--verbose"));
+    assert_eq!(vals.get_str("--output"), "out");
+    assert_eq!(dopt.source_text(), "
+Usage: tool [options] <file>
+
+Options:
+  --dry-run  Don't write anything.
+  -v, --verbose  Say more.
+  -o, --output=<path>  Where to write [default: out].
+");
+    // Options set before includes are resolved are kept.
+    let dopt = Docopt::new(doc).unwrap().help(false)
+                      .include_resolver(texts).unwrap();
+    assert!(dopt.argv(&["tool", "--help", "f"]).parse().is_err());
+    let dopt = Docopt::with_includes(doc, texts).unwrap();
+    assert!(dopt.argv(&["tool", "-v", "f"]).parse().is_ok());
+
+    // Without a resolver, an include is an error once argv is parsed.
+    match Docopt::new(doc).unwrap().argv(&["tool", "f"]).parse() {
+        Err(::Error::Usage(msg)) => {
+            assert_eq!(msg, "'%include common' on line 6 is not resolved. \
+                             Includes are resolved with \
+                             'Docopt::include_resolver'.")
+        }
+        r => panic!("expected usage error, got {:?}", r),
+    }
+    // Outside of an options or arguments section, it's only text.
+    let notes = "Usage: tool\n\nNotes:\n  %include common\n";
+    assert!(Docopt::new(notes).unwrap().argv(&["tool"]).parse().is_ok());
+
+    let err = |doc: &str| match include(doc) {
+        Err(::Error::Usage(msg)) => msg,
+        r => panic!("expected usage error, got {:?}", r),
+    };
+    assert_eq!(err("Usage: tool [options]\n\nOptions:\n  %include self"),
+               "'self' includes itself (line 1 of 'loop').");
+    assert_eq!(err("Usage: tool [options]\n\nOptions:\n  %include nope"),
+               "Could not include 'nope' (line 4): no text 'nope'");
+    assert_eq!(err("Usage: tool\n  %include common\n"),
+               "'%include common' is only allowed in an options or \
+                arguments section (line 2).");
+    assert_eq!(err("Usage: tool [options]\n\nOptions:\n  %include bad"),
+               "Extraneous text ' <b>' in option description \
+                '--output=<a> <b>'.
+ --> bad, line 2, column 3
+  |
+2 |   --output=<a> <b>  Two arguments.
+  |   ^^^^^^^^^^^^^^^^");
+}
+
+#[test]
+fn test_fixed_positionals() {
+    // A usage with only positional arguments and commands, none of them
+    // optional or repeated, is matched by a simpler matcher, which must
+    // agree with the general matcher.
+    let doc = "Usage: prog copy <input> <output>";
+    check_matchers(doc, &["copy", "a", "copy"]);
+    check_matchers(doc, &["copy", "--", "-a", "b"]);
+    let vals = get_args(doc, &["copy", "a", "copy"]);
+    assert!(vals.get_bool("copy"));
+    assert_eq!(vals.get_str("<input>"), "a");
+    assert_eq!(vals.get_str("<output>"), "copy");
+    assert_eq!(get_args(doc, &["copy", "--", "-a", "b"]).get_str("<input>"),
+               "-a");
+    assert_eq!(get_args("Usage: prog", &[]).len(), 0);
+
+    for argv in &[&["move", "a", "b"][..], &["copy", "a"],
+                  &["copy", "a", "b", "c"], &["copy", "a", "-b"]] {
+        check_matchers(doc, argv);
+        let argv = vec!["prog"].into_iter().chain(argv.iter().cloned());
+        assert!(Docopt::new(doc).unwrap().argv(argv).parse().is_err());
+    }
+}
+
+#[test]
+fn test_parse_line() {
+    let dopt = Docopt::new("
+Usage:
+  prog add <name> <url> [--tag=<t>]
+  prog rm <key>...
+").unwrap();
+
+    let vals = dopt.parse_line("add site http://a.b").unwrap();
+    assert!(vals.get_bool("add"));
+    assert_eq!(vals.get_str("<name>"), "site");
+    assert_eq!(vals.get_str("<url>"), "http://a.b");
+
+    let vals = dopt.parse_line(r#"  add "my site" 'c d' --tag="x y" "#)
+                   .unwrap();
+    assert_eq!(vals.get_str("<name>"), "my site");
+    assert_eq!(vals.get_str("<url>"), "c d");
+    assert_eq!(vals.get_str("--tag"), "x y");
+
+    let vals = dopt.parse_line("rm a 'b c' d").unwrap();
+    assert_eq!(vals.get_vec("<key>"), vec!["a", "b c", "d"]);
+
+    match dopt.parse_line("rm 'a") {
+        Err(::Error::Split(_)) => {}
+        r => panic!("expected split error, got {:?}", r),
+    }
+    match dopt.parse_line("add onlyname") {
+        Err(::Error::WithProgramUsage(err, _)) => match *err {
+            ::Error::NoMatch => {}
+            err => panic!("expected no match, got {:?}", err),
+        },
+        r => panic!("expected no match, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_positional_spellings() {
+    let doc = "
+Usage: prog show FILE
+       prog edit <file> [--input=FILE]
+       prog diff <file> --input FILE
+
+Options:
+  --input=<file>  Where to read from.
+";
+    // `FILE` and `<file>` are the same argument, stored under the first
+    // spelling, and the `FILE`s after `--input` are its argument.
+    let vals = get_args(doc, &["show", "a"]);
+    assert_eq!(vals.get_str("FILE"), "a");
+    assert_eq!(vals.get_str("<file>"), "a");
+    assert_eq!(vals.canonical("<file>"), Some("FILE"));
+    assert_eq!(vals.find("--input"), Some(&Plain(None)));
+    assert_eq!(vals.len(), 5);
+
+    let vals = get_args(doc, &["edit", "b", "--input=c"]);
+    assert_eq!(vals.get_str("FILE"), "b");
+    assert_eq!(vals.get_str("--input"), "c");
+    let vals = get_args(doc, &["diff", "d", "--input", "e"]);
+    assert_eq!(vals.get_str("<file>"), "d");
+    assert_eq!(vals.get_str("--input"), "e");
+
+    #[derive(RustcDecodable)]
+    struct Args {
+        arg_file: String,
+        flag_input: Option<String>,
+    }
+    let args: Args = get_args(doc, &["show", "f"]).decode().unwrap();
+    assert_eq!(args.arg_file, "f");
+    assert_eq!(args.flag_input, None);
+
+    // A single spelling can be looked up either way too.
+    let vals = get_args("Usage: prog <src> DEST", &["a", "b"]);
+    assert_eq!(vals.get_str("SRC"), "a");
+    assert_eq!(vals.get_str("<dest>"), "b");
+    assert_eq!(vals.get_str("<DEST>"), "b");
+
+    // Only the all uppercase spelling is the same argument.
+    let vals = get_args("Usage: prog <file> <File>", &["a", "b"]);
+    assert_eq!(vals.get_str("<file>"), "a");
+    assert_eq!(vals.get_str("<File>"), "b");
+}
+
+#[test]
+fn test_alias() {
+    let doc = "
+Usage: prog [--new-name=<n>] [-q]
+
+Options:
+  -n, --new-name=<n>  The name.
+  -q, --quiet         Say less.
+";
+    let dopt = Docopt::new(doc)
+                      .and_then(|d| d.alias("--old-name", "-n"))
+                      .and_then(|d| d.alias("-s", "--quiet"))
+                      .unwrap();
+    let parse = |argv: &[&str]| {
+        dopt.argv(vec!["prog"].iter().chain(argv.iter())).parse()
+    };
+
+    let vals = parse(&["--old-name", "a", "-s"]).unwrap();
+    assert_eq!(vals.get_str("--new-name"), "a");
+    assert!(vals.get_bool("--quiet"));
+    let vals = parse(&["--old-name=b"]).unwrap();
+    assert_eq!(vals.get_str("-n"), "b");
+    assert!(!vals.get_bool("-q"));
+    assert_eq!(parse(&["-n", "c"]).unwrap().get_str("--new-name"), "c");
+
+    for argv in &[&["--old-name=a", "--new-name=b"][..],
+                  &["-n", "b", "--old-name=a"]] {
+        match parse(argv) {
+            Err(::Error::WithProgramUsage(err, _)) => match *err {
+                ::Error::Argv(msg) => {
+                    assert_eq!(msg, "Flags '--old-name' and '--new-name' \
+                                     cannot both be given, since \
+                                     '--old-name' is an old name for \
+                                     '--new-name'.")
+                }
+                err => panic!("expected argv error, got {:?}", err),
+            },
+            r => panic!("expected argv error, got {:?}", r),
+        }
+    }
+
+    let bad = &[("--old", "--nope"), ("-q", "--new-name"),
+                ("old", "--quiet"), ("--old", "<n>")];
+    for &(old, new) in bad {
+        match Docopt::new(doc).unwrap().alias(old, new) {
+            Err(::Error::Usage(_)) => {}
+            r => panic!("expected usage error for {}, got {:?}", old, r),
+        }
+    }
+}
+
+#[test]
+fn test_optional_flag_argument() {
+    let doc = "
+Usage: prog [options] <file>
+
+Options:
+  -c, --color[=WHEN]  Colorize output [default: always].
+  --page[=N]          Page through output.
+  -v                  Verbose.
+";
+    let color = |argv: &[&'static str]| {
+        get_args(doc, argv).find("--color").cloned()
+    };
+    assert_eq!(color(&["a"]), Some(Plain(None)));
+    assert_eq!(color(&["--color", "a"]), Some(Plain(Some("always".into()))));
+    assert_eq!(color(&["-cv", "a"]), Some(Plain(Some("always".into()))));
+    assert_eq!(color(&["--color=always", "a"]),
+               Some(Plain(Some("always".into()))));
+    assert_eq!(color(&["--color=never", "a"]),
+               Some(Plain(Some("never".into()))));
+    // Without a default, a bare flag can't be told from an absent one.
+    let vals = get_args(doc, &["--page", "a"]);
+    assert_eq!(vals.find("--page"), Some(&Plain(None)));
+    assert_eq!(vals.get_str("<file>"), "a");
+    assert_eq!(get_args(doc, &["--page=3", "a"]).get_str("--page"), "3");
+
+    #[derive(RustcDecodable, Debug)]
+    struct Args { flag_color: Option<String>, arg_file: String }
+    let decode = |argv: &[&str]| -> Args {
+        Docopt::new(doc).unwrap()
+                        .argv(vec!["prog"].iter().chain(argv.iter()))
+                        .decode().unwrap()
+    };
+    assert_eq!(decode(&["a"]).flag_color, None);
+    assert_eq!(decode(&["--color=never", "a"]).flag_color,
+               Some("never".into()));
+
+    let vals = get_args("Usage: prog [--color[=<when>]] [<file>]",
+                        &["--color", "a"]);
+    assert_eq!(vals.find("--color"), Some(&Plain(None)));
+    assert_eq!(vals.get_str("<file>"), "a");
+
+    let bad = &[
+        "Usage: prog --color[=WHEN]\n\nOptions:\n  --color=WHEN  Color.",
+        "Usage: prog -c\n\nOptions:\n  -c[=WHEN]  Color.",
+    ];
+    for doc in bad {
+        assert!(Docopt::new(*doc).is_err(), "{}", doc);
+    }
+    match Docopt::new("Usage: prog --color[=when]") {
+        Err(::Error::Usage(msg)) => {
+            assert!(msg.starts_with("Argument 'when' for flag \
+                                     '--color[=when]' is not in the form \
+                                     ARG or <arg>."), "{}", msg)
+        }
+        r => panic!("expected usage error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_decode_parsed() {
+    use std::net::{IpAddr, SocketAddr};
+    use Parsed;
+
+    #[derive(RustcDecodable, Debug)]
+    struct Args {
+        arg_addr: Parsed<SocketAddr>,
+        arg_peer: Vec<Parsed<IpAddr>>,
+        flag_bind: Option<Parsed<IpAddr>>,
+    }
+    let doc = "Usage: prog [--bind=<ip>] <addr> [<peer>...]";
+    let decode = |argv: &[&str]| -> Result<Args, ::Error> {
+        Docopt::new(doc).unwrap()
+                        .argv(vec!["prog"].iter().chain(argv.iter()))
+                        .decode()
+    };
+    let args = decode(&["127.0.0.1:80", "::1", "10.0.0.1"]).unwrap();
+    assert_eq!(*args.arg_addr, "127.0.0.1:80".parse().unwrap());
+    assert_eq!(args.arg_peer.len(), 2);
+    assert_eq!(args.arg_peer[1].0, "10.0.0.1".parse::<IpAddr>().unwrap());
+    assert!(args.flag_bind.is_none());
+    let args = decode(&["--bind=0.0.0.0", "[::1]:22"]).unwrap();
+    assert_eq!(args.flag_bind.unwrap().to_string(), "0.0.0.0");
+
+    match decode(&["localhost"]) {
+        Err(::Error::Decode(msg)) => {
+            assert_eq!(msg, "Could not decode 'localhost': invalid socket \
+                             address syntax (for '<addr>')")
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+    match decode(&["127.0.0.1:80", "::1", "nope"]) {
+        Err(::Error::Decode(msg)) => {
+            assert!(msg.ends_with("(for '<peer>' (element 2 of 2))"), "{}", msg)
+        }
+        r => panic!("expected decode error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_collect_unknown() {
+    let doc = "
+Usage: xtask [options] <task> [<args>...]
+
+Options:
+  -v, --verbose     Verbose.
+  -j, --jobs=<n>    Number of jobs.
+";
+    let parse = |argv: &[&str]| {
+        Docopt::new(doc).unwrap()
+                        .collect_unknown("<args>")
+                        .argv(vec!["xtask"].iter().chain(argv.iter()))
+                        .parse()
+    };
+    let vals = parse(&["--color=always", "test", "-qj2", "--jobs", "4",
+                       "--release", "lib", "-vx"]).unwrap();
+    assert_eq!(vals.get_str("<task>"), "test");
+    assert_eq!(vals.get_str("--jobs"), "4");
+    assert!(vals.get_bool("--verbose"));
+    assert_eq!(vals.get_vec("<args>"),
+               vec!["lib", "--color=always", "-qj2", "--release", "-x"]);
+
+    let vals = Docopt::new("Usage: prog [-v]").unwrap()
+                      .collect_unknown("rest")
+                      .argv(&["prog"])
+                      .parse().unwrap();
+    assert!(vals.get_vec("rest").is_empty());
+
+    match Docopt::new(doc).unwrap().argv(&["xtask", "--color", "t"]).parse() {
+        Err(::Error::WithProgramUsage(err, _)) => match *err {
+            ::Error::Argv(_) => {}
+            err => panic!("expected argv error, got {:?}", err),
+        },
+        r => panic!("expected argv error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_counted_commands() {
+    #[derive(RustcDecodable, Debug)]
+    struct Args { cmd_go: usize }
+    let decode = |doc: &str, argv: &[&str]| -> Result<Args, ::Error> {
+        Docopt::new(doc).unwrap()
+                        .argv(vec!["prog"].iter().chain(argv.iter()))
+                        .decode()
+    };
+    assert_eq!(decode("Usage: prog [go go]", &[]).unwrap().cmd_go, 0);
+    assert_eq!(decode("Usage: prog [go go]", &["go"]).unwrap().cmd_go, 1);
+    assert_eq!(decode("Usage: prog go [go]", &["go"]).unwrap().cmd_go, 1);
+    assert_eq!(decode("Usage: prog go [go]", &["go", "go"]).unwrap().cmd_go,
+               2);
+    assert_eq!(decode("Usage: prog go...", &["go", "go", "go"]).unwrap()
+                                                             .cmd_go, 3);
+    match decode("Usage: prog go [go]", &["go", "go", "go"]) {
+        Err(::Error::WithProgramUsage(err, _)) => match *err {
+            ::Error::NoMatch => {}
+            err => panic!("expected no match, got {:?}", err),
+        },
+        r => panic!("expected no match, got {:?}", r),
+    }
+    assert!(decode("Usage: prog go [go]", &[]).is_err());
+
+    let vals = get_args("Usage: prog go [go]", &["go"]);
+    assert_eq!(vals.find("go"), Some(&Counted(1)));
+    assert_eq!(vals.get_count("go"), 1);
+}
+
+#[test]
+fn test_trailing_var_arg() {
+    let doc = "
+Usage: prog [options] exec <cmd> <args>...
+       prog [options] shell [<args>...]
+
+Options:
+    -v, --verbose  Say more.
+";
+    let parse = |argv: &[&str]| {
+        Docopt::new(doc).unwrap()
+                        .argv(vec!["prog"].iter().chain(argv.iter()))
+                        .parse_with_trailing_var_arg("<args>")
+    };
+    let vals = parse(&["-v", "exec", "ls", "-la", "--verbose", "--", "x"])
+                   .unwrap();
+    assert_eq!(vals.get_count("--verbose"), 1);
+    assert_eq!(vals.get_str("<cmd>"), "ls");
+    assert_eq!(vals.get_vec("<args>"), vec!["-la", "--verbose", "--", "x"]);
+
+    let vals = parse(&["shell", "-c", "echo"]).unwrap();
+    assert!(vals.get_bool("shell"));
+    assert!(!vals.get_bool("--verbose"));
+    assert_eq!(vals.get_vec("<args>"), vec!["-c", "echo"]);
+
+    // Flags before the trailing argument are still parsed.
+    let vals = parse(&["exec", "-v", "ls", "-v"]).unwrap();
+    assert!(vals.get_bool("--verbose"));
+    assert_eq!(vals.get_vec("<args>"), vec!["-v"]);
+
+    // Without it, the flags are parsed as flags.
+    assert!(Docopt::new(doc).unwrap()
+                   .argv(&["prog", "exec", "ls", "-la"]).parse().is_err());
+    // Trailing arguments aren't rewritten as Windows style flags.
+    let vals = Docopt::new(doc).unwrap()
+                      .slash_flags(true)
+                      .argv(&["prog", "/verbose", "exec", "ls", "/verbose"])
+                      .parse_with_trailing_var_arg("<args>").unwrap();
+    assert_eq!(vals.get_count("--verbose"), 1);
+    assert_eq!(vals.get_vec("<args>"), vec!["/verbose"]);
+    match Docopt::new(doc).unwrap().parse_with_trailing_var_arg("<cmd>") {
+        Err(::Error::Usage(msg)) => {
+            assert_eq!(msg, "'<cmd>' is not a repeated positional argument.")
+        }
+        r => panic!("expected usage error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_repeated_groups() {
+    let vals = get_args("Usage: prog set (<key> <value>)...",
+                        &["set", "a", "1", "b", "2"]);
+    assert_eq!(vals.get_vec("<key>"), vec!["a", "b"]);
+    assert_eq!(vals.get_vec("<value>"), vec!["1", "2"]);
+    let vals = get_args("Usage: prog set (<key> <value>)...",
+                        &["set", "a", "1"]);
+    assert_eq!(vals.find("<key>"), Some(&List(vec!["a".into()])));
+
+    let dopt = Docopt::new("Usage: prog set (<key> <value>)...").unwrap();
+    assert!(dopt.argv(&["prog", "set", "a", "1", "b"]).parse().is_err());
+    assert!(dopt.argv(&["prog", "set"]).parse().is_err());
+
+    let vals = get_args("Usage: prog [<x>]...", &[]);
+    assert_eq!(vals.find("<x>"), Some(&List(vec![])));
+    let vals = get_args("Usage: prog [<k> <v>]...", &[]);
+    assert_eq!(vals.find("<k>"), Some(&List(vec![])));
+    assert_eq!(vals.find("<v>"), Some(&List(vec![])));
+}
+
+#[test]
+fn test_unknown_command() {
+    let dopt = Docopt::new("Usage: prog [-v] add <x>\n       prog rm <x>")
+                      .unwrap()
+                      .on_unknown_command(|name, args| match name {
+                          "foo" => {
+                              assert_eq!(args, ["--bar", "--", "x"]);
+                              Some(Ok(()))
+                          }
+                          "fail" => Some(Err(::Error::Argv("no".into()))),
+                          _ => None,
+                      });
+    match dopt.argv(&["prog", "-v", "foo", "--bar", "--", "x"]).parse() {
+        Err(::Error::Dispatched(ref name)) => assert_eq!(name, "foo"),
+        r => panic!("expected dispatch, got {:?}", r),
+    }
+    match dopt.argv(&["prog", "bar"]).parse() {
+        Err(::Error::WithProgramUsage(ref err, _)) => match **err {
+            ::Error::NoMatch => {}
+            ref err => panic!("expected NoMatch, got {:?}", err),
+        },
+        r => panic!("expected NoMatch, got {:?}", r),
+    }
+    match dopt.argv(&["prog", "fail"]).parse() {
+        Err(::Error::Argv(ref msg)) => assert_eq!(msg, "no"),
+        r => panic!("expected Argv error, got {:?}", r),
+    }
+    // Known commands given bad arguments aren't handed to the hook.
+    assert!(dopt.argv(&["prog", "add"]).parse().is_err());
+    let vals = dopt.argv(&["prog", "rm", "y"]).parse().unwrap();
+    assert!(vals.get_bool("rm"));
+    assert_eq!(vals.get_str("<x>"), "y");
+}
+
+#[test]
+fn test_windows_style_flags() {
+    let dopt = Docopt::new("\
+Usage: prog [options] <path>...
+
+Options:
+  -v             Say more.
+  --quiet        Say less.
+  -o, --output=DIR  Output directory.
+  --color[=WHEN]  Use color.
+").unwrap().value_separators(&['=', ':']).slash_flags(true);
+    let vals = dopt.argv(&["prog", "/quiet", "/output:dir", "/usr/bin/thing"])
+                   .parse().unwrap();
+    assert!(vals.get_bool("--quiet"));
+    assert_eq!(vals.get_str("--output"), "dir");
+    assert_eq!(vals.get_vec("<path>"), vec!["/usr/bin/thing"]);
+
+    let vals = dopt.argv(&["prog", "--output:C:\\tmp", "/color", "x"])
+                   .parse().unwrap();
+    assert_eq!(vals.get_str("--output"), "C:\\tmp");
+    assert!(vals.find("--color").is_some());
+    assert_eq!(vals.get_vec("<path>"), vec!["x"]);
+
+    // Values of flags, arguments after `--` and `=` values are untouched.
+    let vals = dopt.argv(&["prog", "--output", "/quiet", "--", "/quiet"])
+                   .parse().unwrap();
+    assert!(!vals.get_bool("--quiet"));
+    assert_eq!(vals.get_str("--output"), "/quiet");
+    assert_eq!(vals.get_vec("<path>"), vec!["/quiet"]);
+    let vals = dopt.argv(&["prog", "--output=a:b", "x"]).parse().unwrap();
+    assert_eq!(vals.get_str("--output"), "a:b");
+    let vals = dopt.argv(&["prog", "-vo", "/quiet", "x"]).parse().unwrap();
+    assert!(vals.get_bool("-v"));
+    assert!(!vals.get_bool("--quiet"));
+    assert_eq!(vals.get_str("--output"), "/quiet");
+    let vals = dopt.argv(&["prog", "-ov", "/quiet", "x"]).parse().unwrap();
+    assert!(!vals.get_bool("-v"));
+    assert!(vals.get_bool("--quiet"));
+    assert_eq!(vals.get_str("--output"), "v");
+
+    // Both are off by default.
+    let dopt = Docopt::new("Usage: prog [--quiet] [--output=DIR] <path>")
+                      .unwrap();
+    let vals = dopt.argv(&["prog", "/quiet"]).parse().unwrap();
+    assert!(!vals.get_bool("--quiet"));
+    assert_eq!(vals.get_str("<path>"), "/quiet");
+    assert!(dopt.argv(&["prog", "--output:dir", "x"]).parse().is_err());
+}
+
+#[test]
+fn test_edit_in_place() {
+    let mut vals = get_args("Usage: prog [--out=FILE] <path>...",
+                            &["--out=x", "a", "b"]);
+    {
+        let paths = vals.find_mut("<path>").unwrap().as_list_mut().unwrap();
+        paths.retain(|p| p != "a");
+        paths.push("c".to_string());
+    }
+    assert_eq!(vals.get_vec("<path>"), vec!["b", "c"]);
+    // The other spelling of a positional argument works too.
+    vals.find_mut("PATH").unwrap().as_list_mut().unwrap().clear();
+    assert_eq!(vals.get_vec("<path>"), Vec::<&str>::new());
+
+    *vals.find_mut("--out").unwrap().as_plain_mut().unwrap() =
+        Some("y".to_string());
+    assert_eq!(vals.get_str("--out"), "y");
+    assert!(vals.find_mut("--out").unwrap().as_list_mut().is_none());
+    assert!(vals.find_mut("<path>").unwrap().as_plain_mut().is_none());
+    assert!(vals.find_mut("--nope").is_none());
+}
+
+#[test]
+fn test_descs_in_order() {
+    let dopt = Docopt::new("\
+Usage: prog add [options] <name> <url>
+       prog rm [-f] <name>
+       prog --version
+
+Options:
+  -v, --verbose  Say more.
+  -q             Say less.
+  --color        Use color.
+  -f, --force    Force.
+  --version      Show version.
+").unwrap();
+    let atoms: Vec<String> = dopt.parser().descs_in_order().iter()
+                                 .map(|&(atom, _)| atom.to_string())
+                                 .collect();
+    assert_eq!(atoms, vec![
+        "add", "--verbose", "-q", "--color", "--force", "--version",
+        "<name>", "<url>", "rm",
+    ]);
+    let keys: Vec<String> =
+        dopt.parser().key_order().iter().map(|a| a.to_string()).collect();
+    assert_eq!(keys, atoms);
+}
+
+#[test]
+fn test_active_command() {
+    let doc = "\
+Usage: prog add <name>
+       prog rm [-f] <name>
+       prog remote (add | show) [<name>]
+       prog [-v]
+       prog --version
+
+Options:
+  -v, --verbose  Say more.
+  --version      Show version.
+";
+    let active = |argv: &[&'static str]| {
+        get_args(doc, argv).active_command().map(|s| s.to_string())
+    };
+    assert_eq!(active(&["add", "x"]), Some("add".to_string()));
+    assert_eq!(active(&["rm", "-f", "x"]), Some("rm".to_string()));
+    assert_eq!(active(&["remote", "show"]), Some("remote".to_string()));
+    assert_eq!(active(&["remote", "add", "x"]), Some("remote".to_string()));
+    assert_eq!(active(&["-v"]), None);
+    assert_eq!(active(&["--version"]), None);
+
+    let vals = ArgvMap::from_pairs(vec![
+        ("<name>", Plain(Some("build".into()))),
+        ("run", Switch(false)),
+        ("build", Switch(true)),
+    ]);
+    assert_eq!(vals.active_command(), Some("build"));
+}
+
+#[test]
+fn test_max_repeats() {
+    let dopt = Docopt::new("\
+Usage: prog [-v...] [-q...] [go...]
+
+Options:
+  -v, --verbose  Say more.
+").unwrap().max_repeats("--verbose", 3).max_repeats("go", 1);
+    let count = |argv: &[&str]| {
+        dopt.argv(argv).parse().map(|vals| vals.get_count("-v"))
+    };
+    assert_eq!(count(&["prog", "-vv"]).unwrap(), 2);
+    assert_eq!(count(&["prog", "-vvv"]).unwrap(), 3);
+    match count(&["prog", "-v", "-vvvv"]) {
+        Err(::Error::WithProgramUsage(ref err, _)) => match **err {
+            ::Error::Argv(ref msg) => assert_eq!(
+                msg, "'--verbose' was given 5 times, but at most 3 are \
+                      allowed."),
+            ref err => panic!("expected an Argv error, got {:?}", err),
+        },
+        r => panic!("expected an Argv error, got {:?}", r),
+    }
+    assert!(dopt.argv(&["prog", "go", "go"]).parse().is_err());
+    // Other keys are unlimited.
+    let vals = dopt.argv(&["prog", "-qqqqqq", "go"]).parse().unwrap();
+    assert_eq!(vals.get_count("-q"), 6);
+}
+
+#[test]
+fn test_to_json() {
+    let vals = get_args("
+Usage: prog [-v...] [--out=<file>] [--name=<name>] [--dry] <input>...
+
+Options:
+    -v, --verbose   Be loud.
+    --out=<file>    Output.
+    --name=<name>   Name.
+    --dry           Dry run.
+", &["-vv", "--name", "a \"b\"", "x", "y"]);
+    assert_eq!(vals.to_json(),
+               "{\"--verbose\":2,\"--out\":null,\"--name\":\"a \\\"b\\\"\",\
+                \"--dry\":false,\"<input>\":[\"x\",\"y\"]}");
+}
+
+#[test]
+fn test_to_json_round_trip() {
+    use rustc_serialize::json::Json;
+
+    let doc = "
+Usage: prog build [-q...] [--out=<file>] [--tag=<tag>] [<src>...]
+       prog clean
+
+Options:
+    -q, --quiet     Be quiet.
+    --out=<file>    Output.
+    --tag=<tag>     Tag.
+    --color         Use color.
+";
+    let vals = get_args(doc, &["build", "-qq", "--tag=", "a", "b"]);
+    let json = vals.to_json();
+    assert_eq!(json, "{\"build\":true,\"--quiet\":2,\"--out\":null,\
+                      \"--tag\":\"\",\"<src>\":[\"a\",\"b\"],\
+                      \"clean\":false,\"--color\":false}");
+
+    let obj = Json::from_str(&json).unwrap();
+    let obj = obj.as_object().unwrap();
+    assert_eq!(obj.len(), vals.len());
+    assert_eq!(obj["build"].as_boolean(), Some(vals.get_bool("build")));
+    assert_eq!(obj["clean"].as_boolean(), Some(vals.get_bool("clean")));
+    assert_eq!(obj["--color"].as_boolean(), Some(vals.get_bool("--color")));
+    assert_eq!(obj["--quiet"].as_u64(), Some(vals.get_count("-q")));
+    assert_eq!(obj["--tag"].as_string(), Some(vals.get_str("--tag")));
+    assert!(obj["--out"].is_null());
+    assert_eq!(vals.get_str("--out"), "");
+    let srcs: Vec<&str> = obj["<src>"].as_array().unwrap().iter()
+                                      .map(|j| j.as_string().unwrap())
+                                      .collect();
+    assert_eq!(srcs, vals.get_vec("<src>"));
+}
 
 mod testcases;
 mod suggestions;
+mod shellwords;
+mod man;
+mod color;
+#[cfg(feature = "devtools")]
+mod devtools;
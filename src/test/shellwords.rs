@@ -0,0 +1,154 @@
+use {Docopt, Error, QuoteStyle, reconstruct_command_line, shellwords};
+
+fn split(s: &str) -> Vec<String> {
+    match shellwords(s) {
+        Err(err) => panic!("Could not split '{}': {}", s, err),
+        Ok(words) => words,
+    }
+}
+
+fn split_err(s: &str) -> String {
+    match shellwords(s) {
+        Err(Error::Split(msg)) => msg,
+        r => panic!("Expected a split error for '{}', got {:?}", s, r),
+    }
+}
+
+#[test]
+fn whitespace() {
+    assert_eq!(split("  a  b\tc\n"), vec!["a", "b", "c"]);
+    assert!(split("").is_empty());
+    assert!(split("   ").is_empty());
+}
+
+#[test]
+fn embedded_quotes() {
+    assert_eq!(split(r#"--name "John Doe""#), vec!["--name", "John Doe"]);
+    assert_eq!(split(r#"a"b c"d"#), vec!["ab cd"]);
+    assert_eq!(split(r#"say "it's""#), vec!["say", "it's"]);
+    assert_eq!(split(r#"say 'a "b"'"#), vec!["say", r#"a "b""#]);
+    assert_eq!(split(r#""a \"b\" \$c \d""#), vec![r#"a "b" $c \d"#]);
+    assert_eq!(split(r#"'a\b'"#), vec![r#"a\b"#]);
+}
+
+#[test]
+fn empty_quotes() {
+    assert_eq!(split(r#"a '' "" b"#), vec!["a", "", "", "b"]);
+    assert_eq!(split("''"), vec![""]);
+    assert_eq!(split(r#"--out="""#), vec!["--out="]);
+}
+
+#[test]
+fn backslashes() {
+    assert_eq!(split(r"a\ b c\\d"), vec!["a b", r"c\d"]);
+    assert_eq!(split("a\\\nb"), vec!["ab"]);
+    assert_eq!(split(r"\'"), vec!["'"]);
+}
+
+#[test]
+fn errors() {
+    assert!(split_err(r"a\").starts_with("Unexpected backslash"));
+    assert!(split_err("'a").starts_with("Unterminated single quote"));
+    assert!(split_err(r#""a"#).starts_with("Unterminated double quote"));
+    assert!(split_err(r#""a\"#).starts_with("Unterminated double quote"));
+}
+
+#[test]
+fn argv_str() {
+    let vals = Docopt::new("Usage: prog [--name=<name>] <file>...").unwrap()
+                      .argv_str(r#"prog --name "John Doe" a\ b ''"#).unwrap()
+                      .parse().unwrap();
+    assert_eq!(vals.get_str("--name"), "John Doe");
+    assert_eq!(vals.get_vec("<file>"), vec!["a b", ""]);
+
+    match Docopt::new("Usage: prog").unwrap().argv_str("prog 'a") {
+        Err(Error::Split(_)) => {}
+        Err(err) => panic!("Expected a split error, got {:?}", err),
+        Ok(_) => panic!("Expected a split error"),
+    }
+}
+
+fn quote(arg: &str, style: QuoteStyle) -> String {
+    reconstruct_command_line(&["prog", arg], style)[5..].to_string()
+}
+
+#[test]
+fn reconstruct_posix() {
+    let style = QuoteStyle::Posix;
+    assert_eq!(quote("--out=a/b.txt", style), "--out=a/b.txt");
+    assert_eq!(quote("", style), "''");
+    assert_eq!(quote("a b", style), "'a b'");
+    assert_eq!(quote("a\nb", style), "'a\nb'");
+    assert_eq!(quote("it's", style), r"'it'\''s'");
+    assert_eq!(quote("\"", style), r#"'"'"#);
+    assert_eq!(quote("$HOME", style), "'$HOME'");
+    assert_eq!(quote("100%", style), "100%");
+    assert_eq!(quote("a!", style), "'a!'");
+    assert_eq!(quote("~", style), "'~'");
+    assert_eq!(quote("é", style), "'é'");
+    assert_eq!(reconstruct_command_line(&["A=b", "A=b"], style), "'A=b' A=b");
+}
+
+#[test]
+fn reconstruct_powershell() {
+    let style = QuoteStyle::PowerShell;
+    assert_eq!(quote("--out=a/b.txt", style), "--out=a/b.txt");
+    assert_eq!(quote("", style), "''");
+    assert_eq!(quote("a b", style), "'a b'");
+    assert_eq!(quote("a\nb", style), "'a\nb'");
+    assert_eq!(quote("it's", style), "'it''s'");
+    assert_eq!(quote("it\u{2019}s", style), "'it\u{2019}\u{2019}s'");
+    assert_eq!(quote("\"", style), r#"'"'"#);
+    assert_eq!(quote("$HOME", style), "'$HOME'");
+    assert_eq!(quote("100%", style), "'100%'");
+    assert_eq!(quote("a,b", style), "'a,b'");
+    assert_eq!(quote("@a", style), "'@a'");
+    assert_eq!(quote("é", style), "'é'");
+}
+
+#[test]
+fn reconstruct_cmd() {
+    let style = QuoteStyle::CmdExe;
+    assert_eq!(quote("--out=a\\b.txt", style), "--out=a\\b.txt");
+    assert_eq!(quote("", style), r#"^"^""#);
+    assert_eq!(quote("a b", style), r#"^"a b^""#);
+    assert_eq!(quote("it's", style), "it's");
+    assert_eq!(quote("\"", style), r#"^"\^"^""#);
+    assert_eq!(quote(r#"a\"b"#, style), r#"^"a\\\^"b^""#);
+    assert_eq!(quote(r"a b\", style), r#"^"a b\\^""#);
+    assert_eq!(quote(r"a\b", style), r"a\b");
+    assert_eq!(quote("%PATH%", style), "^%PATH^%");
+    assert_eq!(quote("a!", style), "a^!");
+    assert_eq!(quote("a&b|c", style), "a^&b^|c");
+    assert_eq!(quote("é", style), "é");
+}
+
+#[test]
+fn reconstruct_debug() {
+    let argv = ["prog", "a b", "it's", "\"", "a\nb", ""];
+    assert_eq!(reconstruct_command_line(&argv, QuoteStyle::Debug),
+               r#""prog" "a b" "it's" "\"" "a\nb" """#);
+}
+
+#[test]
+fn reconstruct_posix_round_trip() {
+    // A small linear congruential generator, so that every run checks the
+    // same arguments.
+    let mut state: u32 = 12345;
+    let mut next = |n: u32| {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        (state >> 16) % n
+    };
+    let alphabet: Vec<char> =
+        "ab -_=:,./@%+'\"\\$`!*?~#&|;<>(){}[]\t\n\u{e9}\u{2019}"
+        .chars().collect();
+    for _ in 0..500 {
+        let argv: Vec<String> = (0..next(5) + 1).map(|_| {
+            (0..next(6)).map(|_| {
+                alphabet[next(alphabet.len() as u32) as usize]
+            }).collect()
+        }).collect();
+        let line = reconstruct_command_line(&argv, QuoteStyle::Posix);
+        assert_eq!(split(&line), argv);
+    }
+}
@@ -799,3 +799,45 @@ Options:
   -x ARG
   -y", &["-x-y"], vec!(("-x", Plain(Some("-y".to_string())))));
 
+test_expect!(test_216_testcases, "Usage: prog [-a] [-b] [-o <file>] [<x>]
+
+Options:
+  -a
+  -b
+  -o <file>", &["-ovalue"], vec!(("-a", Switch(false)), ("-b", Switch(false)), ("-o", Plain(Some("value".to_string()))), ("<x>", Plain(None))));
+
+test_expect!(test_217_testcases, "Usage: prog [-a] [-b] [-o <file>] [<x>]
+
+Options:
+  -a
+  -b
+  -o <file>", &["-abovalue"], vec!(("-a", Switch(true)), ("-b", Switch(true)), ("-o", Plain(Some("value".to_string()))), ("<x>", Plain(None))));
+
+test_expect!(test_218_testcases, "Usage: prog [-a] [-b] [-o <file>] [<x>]
+
+Options:
+  -a
+  -b
+  -o <file>", &["-abo", "value"], vec!(("-a", Switch(true)), ("-b", Switch(true)), ("-o", Plain(Some("value".to_string()))), ("<x>", Plain(None))));
+
+test_expect!(test_219_testcases, "Usage: prog [-a] [-b] [-o <file>] [<x>]
+
+Options:
+  -a
+  -b
+  -o <file>", &["-bao", "value", "x"], vec!(("-a", Switch(true)), ("-b", Switch(true)), ("-o", Plain(Some("value".to_string()))), ("<x>", Plain(Some("x".to_string())))));
+
+test_expect!(test_220_testcases, "Usage: prog [-a] [-b] [-o <file>] [<x>]
+
+Options:
+  -a
+  -b
+  -o <file>", &["-oab"], vec!(("-a", Switch(false)), ("-b", Switch(false)), ("-o", Plain(Some("ab".to_string()))), ("<x>", Plain(None))));
+
+test_user_error!(test_221_testcases, "Usage: prog [-a] [-b] [-o <file>] [<x>]
+
+Options:
+  -a
+  -b
+  -o <file>", &["-abo"]);
+
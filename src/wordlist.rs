@@ -26,11 +26,17 @@ fn cap_or_empty<'t>(caps: &regex::Captures<'t>, name: &str) -> &'t str {
     caps.name(name).map_or("", |m| m.as_str())
 }
 
+#[allow(dead_code)]
+mod color;
 #[allow(dead_code)]
 mod dopt;
 #[allow(dead_code)]
+mod man;
+#[allow(dead_code)]
 mod parse;
 #[allow(dead_code)]
+mod shellwords;
+#[allow(dead_code)]
 mod synonym;
 
 const USAGE: &'static str = "